@@ -0,0 +1,8 @@
+ALTER TABLE sources
+	ADD COLUMN IF NOT EXISTS mime_type VARCHAR NOT NULL DEFAULT 'image/jpeg';
+
+-- The same dimensions can now exist once per format.
+ALTER TABLE sources
+	DROP CONSTRAINT IF EXISTS sources_photo_id_width_height_key;
+ALTER TABLE sources
+	ADD CONSTRAINT sources_photo_id_mime_type_width_height_key UNIQUE (photo_id, mime_type, width, height);
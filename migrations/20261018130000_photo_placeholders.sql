@@ -0,0 +1,4 @@
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS blurhash VARCHAR,
+	-- data: URL of a tiny JPEG version of the photo.
+	ADD COLUMN IF NOT EXISTS lqip VARCHAR;
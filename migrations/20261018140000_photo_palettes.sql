@@ -0,0 +1,3 @@
+-- Dominant colors of the photo packed as 0xRRGGBB, most dominant first.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS palette INTEGER[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- 64 bit difference hash of the photo, for finding near-duplicates.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS perceptual_hash BIGINT;
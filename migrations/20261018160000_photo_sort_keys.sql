@@ -0,0 +1,8 @@
+-- Manually set position of the photo, higher sorts first. Photos without one sort as if their ID
+-- was their sort key.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS sort_key INTEGER;
+
+-- Match the sort expressions used by the gallery, with the ID as tie-breaker.
+CREATE INDEX IF NOT EXISTS idx_photos_taken_timestamp ON photos ((COALESCE(taken_timestamp, '')), id);
+CREATE INDEX IF NOT EXISTS idx_photos_sort_key ON photos ((COALESCE(sort_key, id)), id);
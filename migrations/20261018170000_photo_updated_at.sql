@@ -0,0 +1,30 @@
+-- When the photo or any of its sources last changed.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE OR REPLACE FUNCTION photos_set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+	NEW.updated_at = NOW();
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS photos_updated_at ON photos;
+CREATE TRIGGER photos_updated_at
+	BEFORE UPDATE ON photos
+	FOR EACH ROW
+	WHEN (OLD IS DISTINCT FROM NEW)
+	EXECUTE FUNCTION photos_set_updated_at();
+
+CREATE OR REPLACE FUNCTION sources_touch_photo() RETURNS TRIGGER AS $$
+BEGIN
+	UPDATE photos SET updated_at = NOW() WHERE id = COALESCE(NEW.photo_id, OLD.photo_id);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS sources_touch_photo ON sources;
+CREATE TRIGGER sources_touch_photo
+	AFTER INSERT OR UPDATE OR DELETE ON sources
+	FOR EACH ROW
+	EXECUTE FUNCTION sources_touch_photo();
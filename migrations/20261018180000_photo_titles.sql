@@ -0,0 +1,4 @@
+-- Titles by language, keyed by the language tag of the XMP title alternative. The title column
+-- keeps the default title.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS titles JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,7 @@
+-- Caption shown with the photo, as Markdown.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS caption VARCHAR;
+
+-- Description of the photo for screen readers, separate from its title.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS alt_text VARCHAR;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS comments (
+	id SERIAL PRIMARY KEY,
+	photo_id INTEGER NOT NULL REFERENCES photos (id) ON DELETE CASCADE ON UPDATE CASCADE,
+
+	author VARCHAR NOT NULL,
+	body VARCHAR NOT NULL,
+
+	-- Comments are pending until approved, and only shown once approved.
+	approved BOOLEAN NOT NULL DEFAULT FALSE,
+
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_comments_photo_id ON comments (photo_id);
+CREATE INDEX IF NOT EXISTS idx_comments_pending ON comments (created_at) WHERE NOT approved;
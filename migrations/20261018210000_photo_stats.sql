@@ -0,0 +1,14 @@
+-- Anonymous view and favorite counts per photo and day (in UTC).
+--
+-- Nothing about visitors is stored, they're only deduplicated in memory for the current day.
+CREATE TABLE IF NOT EXISTS photo_daily_stats (
+	photo_id INTEGER NOT NULL REFERENCES photos (id) ON DELETE CASCADE ON UPDATE CASCADE,
+	day DATE NOT NULL,
+
+	views INTEGER NOT NULL DEFAULT 0,
+	favorites INTEGER NOT NULL DEFAULT 0,
+
+	PRIMARY KEY (photo_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_photo_daily_stats_day ON photo_daily_stats (day);
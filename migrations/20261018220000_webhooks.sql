@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS webhooks (
+	id SERIAL PRIMARY KEY,
+	url VARCHAR NOT NULL,
+
+	-- Shared secret that payloads are signed with using HMAC-SHA256.
+	secret VARCHAR NOT NULL,
+
+	-- Events to deliver to the endpoint, every event if empty.
+	events VARCHAR[] NOT NULL DEFAULT '{}',
+
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+
+-- Queue of payloads to deliver, one row per event and webhook.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+	id BIGSERIAL PRIMARY KEY,
+	webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE ON UPDATE CASCADE,
+
+	event VARCHAR NOT NULL,
+	payload JSONB NOT NULL,
+
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	last_error VARCHAR,
+
+	-- At most one of these is set, once the payload was delivered or given up on.
+	delivered_at TIMESTAMPTZ,
+	failed_at TIMESTAMPTZ,
+
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries (next_attempt_at)
+	WHERE delivered_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,35 @@
+-- Fediverse actors following the gallery.
+CREATE TABLE IF NOT EXISTS activitypub_followers (
+	id SERIAL PRIMARY KEY,
+	actor VARCHAR NOT NULL UNIQUE,
+	inbox VARCHAR NOT NULL,
+
+	-- Inbox shared by every actor on the follower's instance, preferred over the actor's own.
+	shared_inbox VARCHAR,
+
+	-- ID of the Follow activity, referenced when accepting it.
+	follow_id VARCHAR NOT NULL,
+
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+
+-- Queue of activities to deliver, one row per activity and inbox.
+CREATE TABLE IF NOT EXISTS activitypub_deliveries (
+	id BIGSERIAL PRIMARY KEY,
+	inbox VARCHAR NOT NULL,
+	activity JSONB NOT NULL,
+
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+	last_error VARCHAR,
+
+	-- At most one of these is set, once the activity was delivered or given up on.
+	delivered_at TIMESTAMPTZ,
+	failed_at TIMESTAMPTZ,
+
+	created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_activitypub_deliveries_due ON activitypub_deliveries (next_attempt_at)
+	WHERE delivered_at IS NULL AND failed_at IS NULL;
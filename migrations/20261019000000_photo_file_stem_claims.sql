@@ -0,0 +1,6 @@
+-- File stems reserved by uploads that are still being processed, so that nothing else stores
+-- photos under them in the meantime.
+CREATE TABLE IF NOT EXISTS photo_file_stem_claims (
+	file_stem VARCHAR PRIMARY KEY,
+	claimed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,41 @@
+-- When the photo was taken as a point in time, so that photos sort in capture order no matter
+-- the UTC offsets or precision of their XMP timestamps.
+ALTER TABLE photos
+	ADD COLUMN IF NOT EXISTS taken_at TIMESTAMPTZ;
+
+-- Timestamps without a UTC offset are taken to be in UTC, and ones that can't be parsed as a date
+-- and time count as missing.
+CREATE OR REPLACE FUNCTION parse_taken_timestamp(value VARCHAR) RETURNS TIMESTAMPTZ AS $$
+BEGIN
+	IF value ~ 'T\d\d:\d\d(:\d\d(\.\d+)?)?(Z|[+-]\d\d(:?\d\d)?)$' THEN
+		RETURN value::TIMESTAMPTZ;
+	END IF;
+	RETURN value::TIMESTAMP AT TIME ZONE 'UTC';
+EXCEPTION WHEN data_exception THEN
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+CREATE OR REPLACE FUNCTION photos_set_taken_at() RETURNS TRIGGER AS $$
+BEGIN
+	NEW.taken_at = parse_taken_timestamp(NEW.taken_timestamp);
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS photos_taken_at ON photos;
+CREATE TRIGGER photos_taken_at
+	BEFORE INSERT OR UPDATE OF taken_timestamp ON photos
+	FOR EACH ROW
+	EXECUTE FUNCTION photos_set_taken_at();
+
+-- Backfilling doesn't change the photos as far as anyone else is concerned.
+ALTER TABLE photos DISABLE TRIGGER photos_updated_at;
+UPDATE photos
+	SET taken_at = parse_taken_timestamp(taken_timestamp)
+	WHERE taken_timestamp IS NOT NULL;
+ALTER TABLE photos ENABLE TRIGGER photos_updated_at;
+
+-- Match the sort expression used by the gallery, with the ID as tie-breaker.
+DROP INDEX IF EXISTS idx_photos_taken_timestamp;
+CREATE INDEX IF NOT EXISTS idx_photos_taken_at ON photos ((COALESCE(taken_at, '-infinity')), id);
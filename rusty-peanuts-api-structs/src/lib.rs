@@ -1,15 +1,295 @@
+/// MIME type of JPEG sources, which every photo has as a fallback for other formats.
+pub const JPEG_MIME_TYPE: &str = "image/jpeg";
+
+fn default_mime_type() -> String {
+    JPEG_MIME_TYPE.to_string()
+}
+
+/// An sRGB color, serialized as a `#rrggbb` hex string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Color { red, green, blue }
+    }
+
+    /// Squared Euclidean distance between two colors in RGB space.
+    pub fn distance_squared(&self, other: &Color) -> u32 {
+        let channel = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2) as u32;
+        channel(self.red, other.red)
+            + channel(self.green, other.green)
+            + channel(self.blue, other.blue)
+    }
+}
+
+/// Packs the color as `0xRRGGBB`.
+impl From<Color> for u32 {
+    fn from(color: Color) -> Self {
+        u32::from(color.red) << 16 | u32::from(color.green) << 8 | u32::from(color.blue)
+    }
+}
+
+/// Unpacks a color from `0xRRGGBB`, ignoring any higher bits.
+impl From<u32> for Color {
+    fn from(rgb: u32) -> Self {
+        Color::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseColorError;
+
+impl std::fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("colors must be hex strings in the form #rrggbb")
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+/// Parses `#rrggbb`, with the `#` being optional.
+impl std::str::FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseColorError);
+        }
+
+        let rgb = u32::from_str_radix(hex, 16).map_err(|_| ParseColorError)?;
+        Ok(Color::from(rgb))
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+impl std::convert::TryFrom<String> for Color {
+    type Error = ParseColorError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Schema of a string of hex digits matching `pattern`.
+#[cfg(feature = "schema")]
+fn hex_string_schema(pattern: &str) -> schemars::schema::Schema {
+    schemars::schema::SchemaObject {
+        instance_type: Some(schemars::schema::InstanceType::String.into()),
+        string: Some(Box::new(schemars::schema::StringValidation {
+            pattern: Some(pattern.to_string()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Color {
+    fn schema_name() -> String {
+        "Color".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        hex_string_schema("^#[0-9a-f]{6}$")
+    }
+}
+
+/// A 64 bit perceptual hash of a photo, serialized as a 16 digit hex string.
+///
+/// Similar looking photos have hashes that differ in few bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct PerceptualHash(pub u64);
+
+impl PerceptualHash {
+    /// Number of bits that differ between two hashes.
+    pub fn distance(&self, other: &PerceptualHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+impl std::fmt::Display for PerceptualHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsePerceptualHashError;
+
+impl std::fmt::Display for ParsePerceptualHashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("perceptual hashes must be 16 digit hex strings")
+    }
+}
+
+impl std::error::Error for ParsePerceptualHashError {}
+
+impl std::str::FromStr for PerceptualHash {
+    type Err = ParsePerceptualHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 16 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParsePerceptualHashError);
+        }
+
+        u64::from_str_radix(s, 16)
+            .map(PerceptualHash)
+            .map_err(|_| ParsePerceptualHashError)
+    }
+}
+
+impl From<PerceptualHash> for String {
+    fn from(hash: PerceptualHash) -> Self {
+        hash.to_string()
+    }
+}
+
+impl std::convert::TryFrom<String> for PerceptualHash {
+    type Error = ParsePerceptualHashError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for PerceptualHash {
+    fn schema_name() -> String {
+        "PerceptualHash".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        hex_string_schema("^[0-9a-f]{16}$")
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Source {
     pub width: u32,
     pub height: u32,
     pub url: String,
+    /// Sources without a MIME type predate other formats and are JPEGs.
+    #[serde(default = "default_mime_type")]
+    pub mime_type: String,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PhotoPayload {
     pub file_stem: String,
+    /// Default title, used for languages without a title of their own.
     pub title: Option<String>,
+    /// Titles by language tag, left unchanged on updates when missing.
+    #[serde(default)]
+    pub titles: Option<std::collections::BTreeMap<String, String>>,
+    /// Caption shown with the photo, as Markdown.
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// Description of the photo for screen readers.
+    #[serde(default)]
+    pub alt_text: Option<String>,
     pub taken_timestamp: Option<String>,
     pub tags: Vec<String>,
     pub sources: Option<Vec<Source>>,
+    /// BlurHash of the photo, left unchanged on updates when missing.
+    pub blurhash: Option<String>,
+    /// `data:` URL of a tiny version of the photo, left unchanged on updates when missing.
+    pub lqip: Option<String>,
+    /// Dominant colors of the photo, most dominant first, left unchanged on updates when missing.
+    pub palette: Option<Vec<Color>>,
+    /// Perceptual hash of the photo, left unchanged on updates when missing.
+    pub perceptual_hash: Option<PerceptualHash>,
+}
+
+/// Version of the gallery archive format written by the current code.
+///
+/// Bumped whenever archives stop being readable by older versions.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Path of the archive JSON inside of tar archives, always their first entry.
+pub const ARCHIVE_JSON_PATH: &str = "archive.json";
+
+/// A portable export of a whole gallery.
+///
+/// Either stored as plain JSON, or as the first entry of a tar archive that also contains every
+/// source image at [`ArchivedPhoto::image_path`].
+///
+/// Secret keys aren't included: they're bare credentials without any metadata, which shouldn't
+/// end up in backups, so they have to be provisioned again on the restored gallery.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Archive {
+    pub version: u32,
+    /// Whether the archive is a tar archive that includes the source images.
+    pub includes_images: bool,
+    pub photos: Vec<ArchivedPhoto>,
+}
+
+/// Everything about a photo needed to restore it, independent of database IDs.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct ArchivedPhoto {
+    pub file_stem: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub titles: std::collections::BTreeMap<String, String>,
+    pub caption: Option<String>,
+    pub alt_text: Option<String>,
+    pub taken_timestamp: Option<String>,
+    pub tags: Vec<String>,
+    pub sources: Vec<Source>,
+    pub published: bool,
+    pub height_offset: u8,
+    pub sort_key: Option<i32>,
+    pub blurhash: Option<String>,
+    pub lqip: Option<String>,
+    #[serde(default)]
+    pub palette: Vec<Color>,
+    pub perceptual_hash: Option<PerceptualHash>,
+}
+
+impl ArchivedPhoto {
+    /// Path of a source image of the photo inside of a tar archive.
+    pub fn image_path(&self, source: &Source) -> String {
+        let file_name = source.url.rsplit('/').next().unwrap_or(&source.url);
+        format!("images/{}/{}", self.file_stem, file_name)
+    }
+
+    /// Payload to create or update the photo with, with its publish state, height offset and
+    /// sort key having to be set separately.
+    pub fn payload(&self) -> PhotoPayload {
+        PhotoPayload {
+            file_stem: self.file_stem.clone(),
+            title: self.title.clone(),
+            titles: Some(self.titles.clone()),
+            caption: self.caption.clone(),
+            alt_text: self.alt_text.clone(),
+            taken_timestamp: self.taken_timestamp.clone(),
+            tags: self.tags.clone(),
+            sources: Some(self.sources.clone()),
+            blurhash: self.blurhash.clone(),
+            lqip: self.lqip.clone(),
+            palette: Some(self.palette.clone()),
+            perceptual_hash: self.perceptual_hash,
+        }
+    }
 }
@@ -1,14 +1,18 @@
-use std::io::Seek;
+use std::collections::HashMap;
+use std::io::{Read, Seek};
 
-use async_std::task::JoinHandle;
-use futures_lite::stream::StreamExt;
-use s3::bucket::Bucket;
-use s3::creds::Credentials;
 use structopt::StructOpt;
 use surf::StatusCode;
 
-use rusty_peanuts_api_structs::{PhotoPayload, Source};
-use rusty_peanuts_cli::xmp::get_metadata;
+use rusty_peanuts_api_structs::{
+    Archive, ArchivedPhoto, PerceptualHash, PhotoPayload, ARCHIVE_JSON_PATH, ARCHIVE_VERSION,
+};
+use rusty_peanuts_media::palette::extract_palette;
+use rusty_peanuts_media::perceptual_hash::dhash;
+use rusty_peanuts_media::placeholder::Placeholders;
+use rusty_peanuts_media::storage::{is_safe_name, Storage};
+use rusty_peanuts_media::transcode::{decode_image, transcode_and_store_photo, Format};
+use rusty_peanuts_media::xmp::get_metadata;
 
 #[derive(StructOpt)]
 struct SharedApiArgs {
@@ -21,33 +25,69 @@ struct SharedApiArgs {
 }
 
 #[derive(StructOpt)]
-pub struct UploadArgs {
-    #[structopt(flatten)]
-    api_arguments: SharedApiArgs,
+struct StorageArgs {
+    /// Local directory to store photos in, takes precedence over S3-compatible storage.
+    #[structopt(long, parse(from_os_str), env = "RUSTY_PEANUTS_LOCAL_MEDIA_DIR")]
+    local_media_dir: Option<std::path::PathBuf>,
 
     /// Full S3-compatible region endpoint.
     #[structopt(long, env = "RUSTY_PEANUTS_S3_REGION_ENDPOINT")]
-    s3_region_endpoint: String,
+    s3_region_endpoint: Option<String>,
 
     /// S3-compatible bucket name.
     #[structopt(long, env = "RUSTY_PEANUTS_S3_BUCKET")]
-    s3_bucket: String,
+    s3_bucket: Option<String>,
 
     /// S3 access key ID.
     #[structopt(long, env = "RUSTY_PEANUTS_S3_ACCESS_KEY_ID", hide_env_values = true)]
-    s3_access_key_id: String,
+    s3_access_key_id: Option<String>,
     /// S3 secret access key
     #[structopt(
         long,
         env = "RUSTY_PEANUTS_S3_SECRET_ACCESS_KEY",
         hide_env_values = true
     )]
-    s3_secret_access_key: String,
+    s3_secret_access_key: Option<String>,
+}
+
+impl StorageArgs {
+    /// Open the configured storage, if any.
+    fn storage(&self) -> Option<Storage> {
+        if let Some(ref local_media_dir) = self.local_media_dir {
+            return Some(Storage::local(local_media_dir.clone()));
+        }
+
+        Some(Storage::s3(
+            self.s3_region_endpoint.as_deref()?,
+            self.s3_bucket.as_deref()?,
+            self.s3_access_key_id.as_deref()?,
+            self.s3_secret_access_key.as_deref()?,
+        ))
+    }
+}
 
-    /// Hostname to use when displaying files uploaded to S3-compatible storage.
+#[derive(StructOpt)]
+pub struct UploadArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    #[structopt(flatten)]
+    storage_arguments: StorageArgs,
+
+    /// Base URL to use when displaying stored files, e.g. the S3-compatible storage host, or the
+    /// gallery's /media URL when using a local media directory.
     #[structopt(long, env = "RUSTY_PEANUTS_STATIC_HOST")]
     static_host: String,
 
+    /// Comma-separated list of formats to transcode photos to, JPEG is always included.
+    #[structopt(
+        long,
+        default_value = "jpeg,webp,avif",
+        use_delimiter = true,
+        env = "RUSTY_PEANUTS_FORMATS"
+    )]
+    formats: Vec<Format>,
+
     /// Only update metadata.
     #[structopt(long)]
     only_update_metadata: bool,
@@ -85,123 +125,232 @@ pub struct SetHeightOffsetArgs {
     height_offset: u8,
 }
 
+#[derive(StructOpt)]
+pub struct SetSortKeyArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    /// Photo ID to change the sort key of.
+    #[structopt(name = "PHOTO_ID")]
+    photo_id: u32,
+
+    /// Manual sort key, or nothing to clear it.
+    #[structopt(name = "SORT_KEY")]
+    sort_key: Option<i32>,
+}
+
+#[derive(StructOpt)]
+pub struct BackfillArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    /// Recompute placeholders, palettes and perceptual hashes for photos that already have them.
+    #[structopt(long)]
+    force: bool,
+}
+
+#[derive(StructOpt)]
+pub struct CheckDuplicatesArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    /// Maximum number of bits perceptual hashes can differ by for photos to be considered
+    /// duplicates.
+    #[structopt(long)]
+    max_distance: Option<u32>,
+
+    /// Paths to photo files to check.
+    #[structopt(name = "PATH", parse(from_os_str), required = true)]
+    file_paths: Vec<std::path::PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct ExportArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    /// Write a tar archive that also includes every source image.
+    #[structopt(long)]
+    with_images: bool,
+
+    /// Path to write the archive to.
+    #[structopt(name = "OUTPUT", parse(from_os_str))]
+    output_path: std::path::PathBuf,
+}
+
+/// What to do when an imported photo has the same file stem as an existing one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Abort the import before changing anything.
+    Fail,
+    /// Keep the existing photo.
+    Skip,
+    /// Replace the existing photo with the imported one.
+    Overwrite,
+}
+
+impl std::str::FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnConflict::Fail),
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            _ => Err(format!("unknown conflict strategy: {}", s)),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub struct ImportArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    /// Where to store the images of archives that include them, source URLs are kept as they are
+    /// if unset.
+    #[structopt(flatten)]
+    storage_arguments: StorageArgs,
+
+    /// Base URL to use when displaying stored files, required to restore images.
+    #[structopt(long, env = "RUSTY_PEANUTS_STATIC_HOST")]
+    static_host: Option<String>,
+
+    /// What to do with photos whose file stem already exists: fail, skip or overwrite.
+    #[structopt(long, default_value = "fail")]
+    on_conflict: OnConflict,
+
+    /// Path to a JSON or tar archive written by the export command.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    file_path: std::path::PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct CommentsArgs {
+    #[structopt(flatten)]
+    api_arguments: SharedApiArgs,
+
+    #[structopt(subcommand)]
+    command: CommentsCommand,
+}
+
+#[derive(StructOpt)]
+pub enum CommentsCommand {
+    /// List comments waiting for approval.
+    List {
+        /// List approved comments instead.
+        #[structopt(long)]
+        approved: bool,
+
+        /// Only list comments on this photo.
+        #[structopt(long)]
+        photo_id: Option<u32>,
+    },
+    /// Approve comments so that they're shown on their photo's page.
+    Approve {
+        #[structopt(name = "COMMENT_ID", required = true)]
+        comment_ids: Vec<u32>,
+    },
+    /// Hide approved comments again by moving them back to pending.
+    Unapprove {
+        #[structopt(name = "COMMENT_ID", required = true)]
+        comment_ids: Vec<u32>,
+    },
+    /// Reject comments, deleting them.
+    Reject {
+        #[structopt(name = "COMMENT_ID", required = true)]
+        comment_ids: Vec<u32>,
+    },
+}
+
 #[derive(StructOpt)]
 pub enum Command {
     Upload(UploadArgs),
     Update(UploadArgs),
     SetPublished(SetPublishedArgs),
     SetHeightOffset(SetHeightOffsetArgs),
+    SetSortKey(SetSortKeyArgs),
+    /// Compute placeholders, color palettes and perceptual hashes for already uploaded photos
+    /// from their largest JPEG source.
+    Backfill(BackfillArgs),
+    /// Check whether photos look like already uploaded ones, exiting with an error if any do.
+    CheckDuplicates(CheckDuplicatesArgs),
+    /// Export every photo and its metadata to a versioned JSON archive, optionally bundled with
+    /// the source images in a tar archive. Secret keys aren't exported.
+    Export(ExportArgs),
+    /// Restore photos from an archive written by the export command.
+    Import(ImportArgs),
+    /// Moderate comments posted on photo pages.
+    Comments(CommentsArgs),
 }
 
-fn decode_image(file: &std::fs::File) -> (image::DynamicImage, image::ImageFormat) {
-    let bufreader = std::io::BufReader::new(file);
-    let reader = image::io::Reader::new(bufreader);
-    let reader = reader
-        .with_guessed_format()
-        .expect("couldn't guess file format");
-
-    let format = reader.format().expect("couldn't get guessed file format");
-    let image = reader.decode().expect("couldn't decode file");
-
-    (image, format)
-}
-
-fn encode_jpeg(image: &image::DynamicImage) -> (Vec<u8>, u32, u32) {
-    let rgb_image = image.to_rgb8();
-    let (width, height) = (rgb_image.width(), rgb_image.height());
-    let rgb_data = rgb_image.into_vec();
-    log::debug!("Turned image into raw RGB data");
-
-    let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_EXT_RGB);
-    compress.set_size(width as usize, height as usize);
-    compress.set_quality(80.0);
-    compress.set_progressive_mode();
-    compress.set_scan_optimization_mode(mozjpeg::ScanMode::AllComponentsTogether);
-    compress.set_optimize_scans(true);
-    compress.set_mem_dest();
-
-    compress.start_compress();
-    log::debug!("Started compressing image");
-
-    compress.write_scanlines(&rgb_data);
-    log::debug!("Wrote scanlines");
-
-    compress.finish_compress();
-    log::debug!("Finished compressing image");
-
-    let data = compress
-        .data_to_vec()
-        .expect("couldn't convert compressed image data to vector");
-    (data, width, height)
-}
-
-fn transcode_photo(image: image::DynamicImage) -> Vec<JoinHandle<(Vec<u8>, u32, u32)>> {
-    // Filter out the target sizes to only contain those less than or equal to the largest of the
-    // photo's dimensions.
-    let (width, height) = (image.width(), image.height());
-    let sizes = [
-        1800, 1700, 1600, 1500, 1400, 1300, 1200, 1100, 1000, 900, 800, 700, 600, 500, 400, 300,
-    ]
-    .iter()
-    .filter(move |&&s| s <= std::cmp::max(width, height));
-
-    let mut handles = Vec::new();
-    for size in sizes {
-        let image = image.clone();
-
-        let handle = async_std::task::spawn_blocking(move || {
-            let start = std::time::Instant::now();
-            log::info!("Started resizing image to {}px", size);
-            let resized = image.resize(*size, *size, image::imageops::FilterType::Lanczos3);
-            log::info!(
-                "Finished resizing image to {}px in {}s",
-                size,
-                start.elapsed().as_secs_f32()
-            );
-
-            let (jpeg_data, width, height) = encode_jpeg(&resized);
-            log::info!(
-                "Finished image of size {}px in {}s",
-                size,
-                start.elapsed().as_secs_f32()
-            );
-
-            (jpeg_data, width, height)
-        });
-        handles.push(handle);
+/// Find already uploaded photos that look like a photo with the given perceptual hash.
+///
+/// Returns the file stems of the similar photos and how many bits their hashes differ by.
+async fn find_similar_photos(
+    api_arguments: &SharedApiArgs,
+    perceptual_hash: PerceptualHash,
+    max_distance: Option<u32>,
+) -> Vec<(String, u64)> {
+    let mut url = format!(
+        "{}/api/v1/photos/similar?perceptual_hash={}",
+        api_arguments.endpoint, perceptual_hash
+    );
+    if let Some(max_distance) = max_distance {
+        url.push_str(&format!("&max_distance={}", max_distance));
     }
 
-    handles
+    let body: serde_json::Value = surf::get(url)
+        .header(
+            "Authorization",
+            format!("Bearer {}", api_arguments.secret_key),
+        )
+        .recv_json()
+        .await
+        .expect("couldn't get similar photos from rusty-peanuts API");
+
+    body["similar"]
+        .as_array()
+        .expect("similar photos in API response is not a list")
+        .iter()
+        .filter_map(|similar| {
+            let file_stem = similar["photo"]["file_stem"].as_str()?;
+            let distance = similar["distance"].as_u64()?;
+            Some((file_stem.to_string(), distance))
+        })
+        .collect()
 }
 
-async fn upload_transcoded_photo(
-    args: &UploadArgs,
-    bucket: &Bucket,
-    file_stem: &str,
-    data: Vec<u8>,
-    width: u32,
-    height: u32,
-) -> Source {
-    log::info!("Uploading resized image of size {}x{}", width, height);
+async fn check_duplicates(args: CheckDuplicatesArgs) -> std::io::Result<()> {
+    let mut found_duplicates = false;
 
-    let target_path = format!("{}/{}.{}x{}.jpeg", file_stem, file_stem, width, height);
-    let response = bucket
-        .put_object_with_content_type(&target_path, &data, "image/jpeg")
-        .await
-        .expect("could not upload file");
-    let code = response.status_code();
-    assert!((200..300).contains(&code));
-    log::info!(
-        "Uploading resized image of size {}x{} finished",
-        width,
-        height
-    );
+    for file_path in &args.file_paths {
+        let file = std::fs::File::open(file_path).expect("couldn't open photo file");
+        let (image, _) =
+            decode_image(std::io::BufReader::new(file)).expect("couldn't decode photo file");
+        let perceptual_hash = dhash(&image);
 
-    Source {
-        width,
-        height,
-        url: format!("{}/{}", args.static_host, target_path),
+        let similar =
+            find_similar_photos(&args.api_arguments, perceptual_hash, args.max_distance).await;
+        if similar.is_empty() {
+            log::info!("{} has no duplicates", file_path.display());
+        }
+        for (file_stem, distance) in similar {
+            found_duplicates = true;
+            log::warn!(
+                "{} looks like already uploaded photo {} ({} bits differ)",
+                file_path.display(),
+                file_stem,
+                distance
+            );
+        }
+    }
+
+    if found_duplicates {
+        std::process::exit(1);
     }
+
+    Ok(())
 }
 
 async fn upload_photo(args: UploadArgs, update: bool) -> std::io::Result<()> {
@@ -228,50 +377,37 @@ async fn upload_photo(args: UploadArgs, update: bool) -> std::io::Result<()> {
         }
     }
 
-    let credentials = Credentials::from_env_specific(
-        Some(&args.s3_access_key_id),
-        Some(&args.s3_secret_access_key),
-        None,
-        None,
-    )
-    .expect("couldn't create S3 credentials instance");
-
-    let s3_region_name = args
-        .s3_region_endpoint
-        .split('.')
-        .next()
-        .expect("couldn't get region name from region endpoint")
-        .to_string();
-
-    let mut bucket = Bucket::new(
-        &args.s3_bucket,
-        s3::Region::Custom {
-            region: s3_region_name,
-            endpoint: args.s3_region_endpoint.clone(),
+    let storage = match args.storage_arguments.storage() {
+        Some(storage) => storage,
+        None => {
+            log::error!("Either a local media directory or S3-compatible storage is required");
+            std::process::exit(1);
         },
-        credentials,
-    )
-    .expect("couldn't create S3 bucket instance")
-    .with_path_style();
-    bucket.add_header("x-amz-acl", "public-read");
-    bucket.add_header("Cache-Control", "max-age=31536000");
+    };
 
     let mut file = std::fs::File::open(&args.file_path).expect("couldn't open photo file");
 
-    let (image, format) = decode_image(&file);
+    let (image, format) =
+        decode_image(std::io::BufReader::new(&file)).expect("couldn't decode photo file");
     file.seek(std::io::SeekFrom::Start(0))
         .expect("couldn't seek file to begining");
 
     let image_create_datetime: String;
     let image_title: Option<String>;
+    let image_titles: std::collections::BTreeMap<String, String>;
+    let image_caption: Option<String>;
+    let image_alt_text: Option<String>;
     let image_tags: Vec<String>;
 
     match format {
         image::ImageFormat::Tiff => {
-            let (_xmp_xml, create_date, title, tags) = get_metadata(&file);
-            image_create_datetime = create_date;
-            image_title = title;
-            image_tags = tags;
+            let metadata = get_metadata(&file).expect("couldn't get XMP metadata from photo");
+            image_create_datetime = metadata.create_date;
+            image_title = metadata.title;
+            image_titles = metadata.titles;
+            image_caption = metadata.caption;
+            image_alt_text = metadata.alt_text;
+            image_tags = metadata.tags;
         },
         _ => {
             log::error!("Unupported format: {:?}", format);
@@ -279,17 +415,35 @@ async fn upload_photo(args: UploadArgs, update: bool) -> std::io::Result<()> {
         },
     }
 
+    let placeholders = Placeholders::new(&image);
+    let palette = extract_palette(&image);
+    let perceptual_hash = dhash(&image);
+
+    if !update {
+        for (similar_file_stem, distance) in
+            find_similar_photos(&args.api_arguments, perceptual_hash, None).await
+        {
+            log::warn!(
+                "Photo looks like already uploaded photo {} ({} bits differ)",
+                similar_file_stem,
+                distance
+            );
+        }
+    }
+
     let sources = if args.only_update_metadata {
         log::info!("Not uploading photos");
         None
     } else {
-        let sources = async_std::stream::from_iter(transcode_photo(image).into_iter())
-            .then(|handle: JoinHandle<_>| handle)
-            .then(|(data, width, height)| {
-                upload_transcoded_photo(&args, &bucket, &file_stem, data, width, height)
-            })
-            .collect()
-            .await;
+        let sources = transcode_and_store_photo(
+            &storage,
+            &args.static_host,
+            &file_stem,
+            image,
+            &args.formats,
+        )
+        .await
+        .expect("couldn't upload transcoded photo");
         log::info!("All images uploaded");
         Some(sources)
     };
@@ -298,8 +452,15 @@ async fn upload_photo(args: UploadArgs, update: bool) -> std::io::Result<()> {
         file_stem: file_stem.to_string(),
         taken_timestamp: Some(image_create_datetime),
         title: image_title,
+        titles: Some(image_titles),
+        caption: image_caption,
+        alt_text: image_alt_text,
         tags: image_tags,
         sources,
+        blurhash: Some(placeholders.blurhash),
+        lqip: Some(placeholders.lqip),
+        palette: Some(palette),
+        perceptual_hash: Some(perceptual_hash),
     };
 
     log::info!("Sending photo payload to rusty-peanuts API");
@@ -323,9 +484,27 @@ async fn upload_photo(args: UploadArgs, update: bool) -> std::io::Result<()> {
     let status = res.status();
     assert!(!status.is_client_error() && !status.is_server_error());
 
+    if update {
+        warn_if_missing_alt_text(&body["current"]);
+    }
+
     Ok(())
 }
 
+/// Warn about published photos without alt text, as screen readers fall back to their title.
+fn warn_if_missing_alt_text(photo: &serde_json::Value) {
+    let has_alt_text = photo["alt_text"]
+        .as_str()
+        .map_or(false, |alt_text| !alt_text.trim().is_empty());
+    if photo["published"] == true && !has_alt_text {
+        log::warn!(
+            "Published photo {} ({}) has no alt text",
+            photo["id"],
+            photo["file_stem"].as_str().unwrap_or("unknown file stem")
+        );
+    }
+}
+
 async fn set_published(args: SetPublishedArgs) -> std::io::Result<()> {
     let url = format!(
         "{}/api/v1/photo/by-id/{}/published",
@@ -341,6 +520,22 @@ async fn set_published(args: SetPublishedArgs) -> std::io::Result<()> {
         .expect("couldn't send POST request to rusty-peanuts API");
     log::info!("Rusty-peanuts API response: {:#?}", res);
 
+    if args.published {
+        let url = format!(
+            "{}/api/v1/photo/by-id/{}",
+            args.api_arguments.endpoint, args.photo_id,
+        );
+        let photo: serde_json::Value = surf::get(url)
+            .header(
+                "Authorization",
+                format!("Bearer {}", args.api_arguments.secret_key),
+            )
+            .recv_json()
+            .await
+            .expect("couldn't get photo from rusty-peanuts API");
+        warn_if_missing_alt_text(&photo);
+    }
+
     Ok(())
 }
 
@@ -362,6 +557,509 @@ async fn set_height_offset(args: SetHeightOffsetArgs) -> std::io::Result<()> {
     Ok(())
 }
 
+async fn set_sort_key(args: SetSortKeyArgs) -> std::io::Result<()> {
+    let url = format!(
+        "{}/api/v1/photo/by-id/{}/sort-key",
+        args.api_arguments.endpoint, args.photo_id,
+    );
+    let res = surf::post(url)
+        .header(
+            "Authorization",
+            format!("Bearer {}", args.api_arguments.secret_key),
+        )
+        .body(surf::Body::from_json(&args.sort_key).expect("couldn't serialize body"))
+        .await
+        .expect("couldn't send POST request to rusty-peanuts API");
+    log::info!("Rusty-peanuts API response: {:#?}", res);
+
+    Ok(())
+}
+
+/// Get every photo, including unpublished ones, from the rusty-peanuts API.
+async fn get_all_photos(api_arguments: &SharedApiArgs) -> Vec<serde_json::Value> {
+    let mut photos = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let mut url = format!(
+            "{}/api/v1/photos?published=false&sort=id",
+            api_arguments.endpoint
+        );
+        if let Some(ref cursor) = cursor {
+            url.push_str(&format!("&cursor={}", cursor));
+        }
+
+        let body: serde_json::Value = surf::get(url)
+            .header(
+                "Authorization",
+                format!("Bearer {}", api_arguments.secret_key),
+            )
+            .recv_json()
+            .await
+            .expect("couldn't get photos from rusty-peanuts API");
+
+        photos.extend(
+            body["photos"]
+                .as_array()
+                .expect("photos in API response is not a list")
+                .iter()
+                .cloned(),
+        );
+
+        match body["next"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    photos
+}
+
+async fn backfill(args: BackfillArgs) -> std::io::Result<()> {
+    let auth_header = format!("Bearer {}", args.api_arguments.secret_key);
+
+    for photo in get_all_photos(&args.api_arguments).await {
+        warn_if_missing_alt_text(&photo);
+
+        let file_stem = photo["file_stem"]
+            .as_str()
+            .expect("photo in API response is missing a file stem");
+        let has_palette = photo["palette"]
+            .as_array()
+            .map_or(false, |palette| !palette.is_empty());
+        if !args.force && !photo["blurhash"].is_null() && !photo["lqip"].is_null() && has_palette {
+            log::debug!("Photo {} already has placeholders", file_stem);
+            continue;
+        }
+
+        // Sources are sorted largest first.
+        let source_url = photo["sources"].as_array().and_then(|sources| {
+            sources
+                .iter()
+                .find(|source| source["mime_type"] == rusty_peanuts_api_structs::JPEG_MIME_TYPE)
+                .and_then(|source| source["url"].as_str())
+        });
+        let source_url = match source_url {
+            Some(source_url) => source_url,
+            None => {
+                log::warn!("Photo {} has no JPEG source, skipping", file_stem);
+                continue;
+            },
+        };
+
+        log::info!(
+            "Computing placeholders for {} from {}",
+            file_stem,
+            source_url
+        );
+        let data = surf::get(source_url)
+            .recv_bytes()
+            .await
+            .expect("couldn't download photo source");
+        let image = image::load_from_memory(&data).expect("couldn't decode photo source");
+        let placeholders = Placeholders::new(&image);
+        let palette = extract_palette(&image);
+        let perceptual_hash = dhash(&image);
+
+        let payload: PhotoPayload =
+            serde_json::from_value(photo.clone()).expect("couldn't parse photo from API");
+        let payload = PhotoPayload {
+            sources: None,
+            blurhash: Some(placeholders.blurhash),
+            lqip: Some(placeholders.lqip),
+            palette: Some(palette),
+            perceptual_hash: Some(perceptual_hash),
+            ..payload
+        };
+
+        let url = format!(
+            "{}/api/v1/photo/by-filestem/{}",
+            args.api_arguments.endpoint, file_stem
+        );
+        let res = surf::post(url)
+            .header("Authorization", &auth_header)
+            .body(surf::Body::from_json(&payload).expect("couldn't serialize body"))
+            .await
+            .expect("couldn't send POST request to rusty-peanuts API");
+        log::info!("Rusty-peanuts API response: {:#?}", res);
+
+        let status = res.status();
+        assert!(!status.is_client_error() && !status.is_server_error());
+    }
+
+    Ok(())
+}
+
+fn append_tar_file<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)
+}
+
+async fn export(args: ExportArgs) -> std::io::Result<()> {
+    let photos: Vec<ArchivedPhoto> = get_all_photos(&args.api_arguments)
+        .await
+        .into_iter()
+        .map(|photo| serde_json::from_value(photo).expect("couldn't parse photo from API"))
+        .collect();
+    let archive = Archive {
+        version: ARCHIVE_VERSION,
+        includes_images: args.with_images,
+        photos,
+    };
+    let json = serde_json::to_vec_pretty(&archive).expect("couldn't serialize archive");
+
+    if !args.with_images {
+        std::fs::write(&args.output_path, json)?;
+        log::info!(
+            "Exported {} photos to {}",
+            archive.photos.len(),
+            args.output_path.display()
+        );
+        return Ok(());
+    }
+
+    let mut builder = tar::Builder::new(std::fs::File::create(&args.output_path)?);
+    append_tar_file(&mut builder, ARCHIVE_JSON_PATH, &json)?;
+    for photo in &archive.photos {
+        for source in &photo.sources {
+            log::info!("Downloading {}", source.url);
+            let data = surf::get(&source.url)
+                .recv_bytes()
+                .await
+                .expect("couldn't download photo source");
+            append_tar_file(&mut builder, &photo.image_path(source), &data)?;
+        }
+    }
+    builder.into_inner()?;
+
+    log::info!(
+        "Exported {} photos with images to {}",
+        archive.photos.len(),
+        args.output_path.display()
+    );
+
+    Ok(())
+}
+
+async fn post_json<T: serde::Serialize>(
+    api_arguments: &SharedApiArgs,
+    path: &str,
+    body: &T,
+) -> surf::Response {
+    let res = surf::post(format!("{}/api/v1{}", api_arguments.endpoint, path))
+        .header(
+            "Authorization",
+            format!("Bearer {}", api_arguments.secret_key),
+        )
+        .body(surf::Body::from_json(body).expect("couldn't serialize body"))
+        .await
+        .expect("couldn't send POST request to rusty-peanuts API");
+
+    let status = res.status();
+    assert!(!status.is_client_error() && !status.is_server_error());
+
+    res
+}
+
+/// Get the ID of the photo with the given file stem, if it exists.
+async fn get_photo_id_by_file_stem(api_arguments: &SharedApiArgs, file_stem: &str) -> Option<u64> {
+    let url = format!(
+        "{}/api/v1/photo/by-filestem/{}",
+        api_arguments.endpoint, file_stem
+    );
+    let mut res = surf::get(url)
+        .header(
+            "Authorization",
+            format!("Bearer {}", api_arguments.secret_key),
+        )
+        .await
+        .expect("couldn't send GET request to rusty-peanuts API");
+    if res.status() == StatusCode::NotFound {
+        return None;
+    }
+
+    let photo: serde_json::Value = res
+        .body_json()
+        .await
+        .expect("couldn't parse photo from API");
+    Some(
+        photo["id"]
+            .as_u64()
+            .expect("photo in API response is missing an ID"),
+    )
+}
+
+/// Store the images of the photos being imported from a tar archive, and point their sources at
+/// the stored copies.
+async fn restore_images<R: Read>(
+    entries: tar::Entries<'_, R>,
+    photos: &mut [(ArchivedPhoto, Option<u64>)],
+    storage: &Storage,
+    static_host: &str,
+) -> std::io::Result<()> {
+    // Archives could come from anywhere, so make sure they can't store images outside of their
+    // photos' directories before storing anything.
+    for (photo, _) in photos.iter() {
+        if !is_safe_name(&photo.file_stem) {
+            log::error!(
+                "Archived photo has an invalid file stem {:?}",
+                photo.file_stem
+            );
+            std::process::exit(1);
+        }
+        for source in &photo.sources {
+            let file_name = source.url.rsplit('/').next().unwrap_or(&source.url);
+            if !is_safe_name(file_name) {
+                log::error!(
+                    "Photo {} has a source with an invalid file name {:?}",
+                    photo.file_stem,
+                    file_name
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut sources = HashMap::new();
+    for (photo, _) in photos.iter_mut() {
+        let paths: Vec<String> = photo
+            .sources
+            .iter()
+            .map(|source| photo.image_path(source))
+            .collect();
+        sources.extend(paths.into_iter().zip(photo.sources.iter_mut()));
+    }
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let source = match sources.get_mut(&path) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        let target_path = path.trim_start_matches("images/");
+        log::info!("Storing {}", target_path);
+        storage
+            .put(target_path, &data, &source.mime_type)
+            .await
+            .expect("couldn't store photo source");
+        source.url = format!("{}/{}", static_host, target_path);
+    }
+
+    Ok(())
+}
+
+async fn import(args: ImportArgs) -> std::io::Result<()> {
+    let is_tar = args
+        .file_path
+        .extension()
+        .map_or(false, |extension| extension == "tar");
+    let file = std::io::BufReader::new(std::fs::File::open(&args.file_path)?);
+
+    let mut tar_archive = tar::Archive::new(file);
+    let (archive, entries): (Archive, _) = if is_tar {
+        let mut entries = tar_archive.entries()?;
+        let entry = entries.next().transpose()?;
+        match entry {
+            Some(entry) if entry.path()?.as_ref() == std::path::Path::new(ARCHIVE_JSON_PATH) => {
+                (serde_json::from_reader(entry)?, Some(entries))
+            },
+            _ => {
+                log::error!("Archive doesn't start with {}", ARCHIVE_JSON_PATH);
+                std::process::exit(1);
+            },
+        }
+    } else {
+        (serde_json::from_reader(tar_archive.into_inner())?, None)
+    };
+
+    if archive.version > ARCHIVE_VERSION {
+        log::error!(
+            "Archive version {} is newer than the supported version {}",
+            archive.version,
+            ARCHIVE_VERSION
+        );
+        std::process::exit(1);
+    }
+
+    // Photos are exported newest first by ID, import the oldest first to keep their order.
+    let mut photos = Vec::new();
+    let mut conflicts = Vec::new();
+    for photo in archive.photos.into_iter().rev() {
+        let existing_id = get_photo_id_by_file_stem(&args.api_arguments, &photo.file_stem).await;
+        match (existing_id, args.on_conflict) {
+            (Some(_), OnConflict::Fail) => conflicts.push(photo.file_stem),
+            (Some(_), OnConflict::Skip) => {
+                log::info!("Photo {} already exists, skipping", photo.file_stem);
+            },
+            _ => photos.push((photo, existing_id)),
+        }
+    }
+    if !conflicts.is_empty() {
+        for file_stem in conflicts {
+            log::error!("Photo with file stem {} already exists", file_stem);
+        }
+        std::process::exit(1);
+    }
+
+    if archive.includes_images {
+        match (
+            entries,
+            args.storage_arguments.storage(),
+            args.static_host.as_deref(),
+        ) {
+            (Some(entries), Some(storage), Some(static_host)) => {
+                restore_images(entries, &mut photos, &storage, static_host).await?
+            },
+            _ => log::warn!("Not restoring images, keeping the archived source URLs"),
+        }
+    }
+
+    for (photo, existing_id) in &photos {
+        let payload = photo.payload();
+        let photo_id = match existing_id {
+            Some(photo_id) => {
+                log::info!("Overwriting photo {}", photo.file_stem);
+                post_json(
+                    &args.api_arguments,
+                    &format!("/photo/by-filestem/{}", photo.file_stem),
+                    &payload,
+                )
+                .await;
+                *photo_id
+            },
+            None => {
+                log::info!("Creating photo {}", photo.file_stem);
+                let mut res = post_json(&args.api_arguments, "/photos", &payload).await;
+                let body: serde_json::Value =
+                    res.body_json().await.expect("couldn't parse API response");
+                body["id"]
+                    .as_u64()
+                    .expect("API response is missing the created photo's ID")
+            },
+        };
+
+        let path = format!("/photo/by-id/{}", photo_id);
+        post_json(
+            &args.api_arguments,
+            &format!("{}/published", path),
+            &photo.published,
+        )
+        .await;
+        post_json(
+            &args.api_arguments,
+            &format!("{}/height-offset", path),
+            &photo.height_offset,
+        )
+        .await;
+        post_json(
+            &args.api_arguments,
+            &format!("{}/sort-key", path),
+            &photo.sort_key,
+        )
+        .await;
+    }
+
+    log::info!("Imported {} photos", photos.len());
+
+    Ok(())
+}
+
+async fn comments(args: CommentsArgs) -> std::io::Result<()> {
+    let auth_header = format!("Bearer {}", args.api_arguments.secret_key);
+
+    let (comment_ids, approved) = match args.command {
+        CommentsCommand::List { approved, photo_id } => {
+            let mut url = format!(
+                "{}/api/v1/comments?status={}",
+                args.api_arguments.endpoint,
+                if approved { "approved" } else { "pending" }
+            );
+            if let Some(photo_id) = photo_id {
+                url.push_str(&format!("&photo_id={}", photo_id));
+            }
+
+            let body: serde_json::Value = surf::get(url)
+                .header("Authorization", &auth_header)
+                .recv_json()
+                .await
+                .expect("couldn't get comments from rusty-peanuts API");
+
+            for comment in body["comments"]
+                .as_array()
+                .expect("comments in API response is not a list")
+            {
+                println!(
+                    "#{} on photo {} by {} at {}:",
+                    comment["id"],
+                    comment["photo_id"],
+                    comment["author"].as_str().unwrap_or_default(),
+                    comment["created_at"].as_str().unwrap_or_default(),
+                );
+                for line in comment["body"].as_str().unwrap_or_default().lines() {
+                    println!("    {}", line);
+                }
+            }
+
+            return Ok(());
+        },
+        CommentsCommand::Approve { comment_ids } => (comment_ids, Some(true)),
+        CommentsCommand::Unapprove { comment_ids } => (comment_ids, Some(false)),
+        CommentsCommand::Reject { comment_ids } => (comment_ids, None),
+    };
+
+    let mut missing = false;
+    for comment_id in comment_ids {
+        let url = format!(
+            "{}/api/v1/comment/{}",
+            args.api_arguments.endpoint, comment_id
+        );
+        let res = match approved {
+            Some(approved) => surf::post(format!("{}/approved", url))
+                .header("Authorization", &auth_header)
+                .body(surf::Body::from_json(&approved).expect("couldn't serialize body"))
+                .await
+                .expect("couldn't send POST request to rusty-peanuts API"),
+            None => surf::delete(url)
+                .header("Authorization", &auth_header)
+                .await
+                .expect("couldn't send DELETE request to rusty-peanuts API"),
+        };
+
+        match res.status() {
+            StatusCode::NotFound => {
+                log::error!("Comment {} doesn't exist", comment_id);
+                missing = true;
+            },
+            status => {
+                assert!(!status.is_client_error() && !status.is_server_error());
+                match approved {
+                    Some(true) => log::info!("Approved comment {}", comment_id),
+                    Some(false) => log::info!("Moved comment {} back to pending", comment_id),
+                    None => log::info!("Rejected comment {}", comment_id),
+                }
+            },
+        }
+    }
+
+    if missing {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
 #[async_std::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
@@ -372,5 +1070,11 @@ async fn main() -> std::io::Result<()> {
         Command::Update(args) => upload_photo(args, true).await,
         Command::SetPublished(args) => set_published(args).await,
         Command::SetHeightOffset(args) => set_height_offset(args).await,
+        Command::SetSortKey(args) => set_sort_key(args).await,
+        Command::Backfill(args) => backfill(args).await,
+        Command::CheckDuplicates(args) => check_duplicates(args).await,
+        Command::Export(args) => export(args).await,
+        Command::Import(args) => import(args).await,
+        Command::Comments(args) => comments(args).await,
     }
 }
@@ -1,7 +1,7 @@
 use std::io::Seek;
 use std::io::Write;
 
-use rusty_peanuts_cli::xmp::get_metadata;
+use rusty_peanuts_media::xmp::get_metadata;
 
 fn get_format(file: &std::fs::File) -> image::ImageFormat {
     let bufreader = std::io::BufReader::new(file);
@@ -32,15 +32,15 @@ async fn main() -> std::io::Result<()> {
         image::ImageFormat::Tiff => {
             file.seek(std::io::SeekFrom::Start(0))
                 .expect("couldn't seek file to begining");
-            let (xmp_xml, create_date, title, tags) = get_metadata(&file);
+            let metadata = get_metadata(&file).expect("couldn't get XMP metadata from file");
 
-            log::info!("Create Date: {}", create_date);
-            log::info!("Title: {:?}", title);
-            log::info!("Tags: {:?}", tags);
+            log::info!("Create Date: {}", metadata.create_date);
+            log::info!("Title: {:?}", metadata.title);
+            log::info!("Tags: {:?}", metadata.tags);
 
             std::fs::File::create(&format!("xmp.{}.xml", file_name))
                 .expect("could not create XMP metadata file")
-                .write_all(xmp_xml.as_bytes())
+                .write_all(metadata.xmp_xml.as_bytes())
                 .expect("could not write XMP metadata to file");
         },
         format => {
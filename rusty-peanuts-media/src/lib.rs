@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+pub mod palette;
+pub mod perceptual_hash;
+pub mod placeholder;
+pub mod storage;
+pub mod transcode;
+pub mod xmp;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("image error")]
+    Image(#[from] image::ImageError),
+    #[error("TIFF error")]
+    Tiff(#[from] tiff::TiffError),
+    #[error("XMP packet is not valid UTF-8")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("XMP parsing error")]
+    Xml(#[from] quick_xml::de::DeError),
+    #[error("couldn't find a single valid RDF.Description element in XMP metadata")]
+    MissingDescription,
+    #[error("unsupported image format: {0:?}")]
+    UnsupportedFormat(image::ImageFormat),
+    #[error("couldn't guess image format")]
+    UnknownFormat,
+    #[error("unknown output format: {0}")]
+    UnknownOutputFormat(String),
+    #[error("AVIF encoding error")]
+    Avif(#[from] ravif::Error),
+    #[error("storage error: {0}")]
+    Storage(String),
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use rusty_peanuts_api_structs::Color;
+
+/// Maximum number of colors in a palette.
+pub const PALETTE_SIZE: usize = 5;
+
+/// Size along the longest edge of the image palettes are extracted from.
+const SAMPLE_SIZE: u32 = 64;
+
+/// Colors closer to an already picked color than this are left out of the palette, so that it
+/// isn't made up of shades of the same color.
+const MIN_DISTANCE: u32 = 48;
+
+/// Extract the dominant colors of an image, most dominant first.
+///
+/// Pixels are grouped by their colors quantized to 4 bits per channel, and the palette is made up
+/// of the average colors of the largest groups.
+pub fn extract_palette(image: &image::DynamicImage) -> Vec<Color> {
+    let sample = image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).to_rgb8();
+
+    let mut buckets: HashMap<(u8, u8, u8), (u64, [u64; 3])> = HashMap::new();
+    for pixel in sample.pixels() {
+        let [red, green, blue] = pixel.0;
+        let (count, sums) = buckets
+            .entry((red >> 4, green >> 4, blue >> 4))
+            .or_default();
+        *count += 1;
+        sums[0] += u64::from(red);
+        sums[1] += u64::from(green);
+        sums[2] += u64::from(blue);
+    }
+
+    let mut buckets: Vec<_> = buckets.into_iter().collect();
+    // Break ties on the bucket so that the palette doesn't depend on the hash map's order.
+    buckets.sort_by(|(a_key, (a_count, _)), (b_key, (b_count, _))| {
+        b_count.cmp(a_count).then_with(|| a_key.cmp(b_key))
+    });
+
+    let mut palette: Vec<Color> = Vec::with_capacity(PALETTE_SIZE);
+    for (_, (count, sums)) in buckets {
+        let average = |sum: u64| (sum / count) as u8;
+        let color = Color::new(average(sums[0]), average(sums[1]), average(sums[2]));
+
+        if palette
+            .iter()
+            .all(|picked| picked.distance_squared(&color) >= MIN_DISTANCE.pow(2))
+        {
+            palette.push(color);
+            if palette.len() == PALETTE_SIZE {
+                break;
+            }
+        }
+    }
+
+    palette
+}
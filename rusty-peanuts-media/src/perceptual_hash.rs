@@ -0,0 +1,23 @@
+use rusty_peanuts_api_structs::PerceptualHash;
+
+/// Compute the difference hash (dHash) of an image.
+///
+/// The image is shrunk to 9x8 grayscale pixels, and each bit is whether a pixel is brighter than
+/// the one to its right. This survives resizing, recompression and small edits, which is what
+/// re-exports of the same photo usually differ by.
+pub fn dhash(image: &image::DynamicImage) -> PerceptualHash {
+    let small = image
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | u64::from(left > right);
+        }
+    }
+
+    PerceptualHash(hash)
+}
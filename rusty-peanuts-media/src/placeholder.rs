@@ -0,0 +1,54 @@
+use crate::transcode::encode_jpeg;
+
+/// Size along the longest edge of the image BlurHashes are computed from, as computing them from
+/// full size photos is needlessly slow and gives the same result.
+const BLURHASH_SAMPLE_SIZE: u32 = 64;
+
+/// Size along the longest edge of low quality image placeholders.
+const LQIP_SIZE: u32 = 16;
+
+/// Placeholders to show while a photo is loading.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placeholders {
+    pub blurhash: String,
+    /// A `data:` URL of a tiny JPEG version of the photo.
+    pub lqip: String,
+}
+
+impl Placeholders {
+    pub fn new(image: &image::DynamicImage) -> Self {
+        Placeholders {
+            blurhash: blurhash(image),
+            lqip: lqip(image),
+        }
+    }
+}
+
+pub fn blurhash(image: &image::DynamicImage) -> String {
+    let sample = image
+        .thumbnail(BLURHASH_SAMPLE_SIZE, BLURHASH_SAMPLE_SIZE)
+        .to_rgba8();
+
+    // Use more components along the longest edge so that the placeholder follows its shape.
+    let (components_x, components_y) = if sample.width() >= sample.height() {
+        (4, 3)
+    } else {
+        (3, 4)
+    };
+
+    blurhash::encode(
+        components_x,
+        components_y,
+        sample.width(),
+        sample.height(),
+        sample.as_raw(),
+    )
+}
+
+pub fn lqip(image: &image::DynamicImage) -> String {
+    let tiny = image.thumbnail(LQIP_SIZE, LQIP_SIZE);
+    format!(
+        "data:image/jpeg;base64,{}",
+        base64::encode(encode_jpeg(&tiny))
+    )
+}
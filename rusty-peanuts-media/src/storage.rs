@@ -0,0 +1,109 @@
+use std::path::{Component, Path, PathBuf};
+
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+
+use crate::Error;
+
+/// Whether a name is safe to use as a single path component in storage, e.g. a file stem or
+/// the file name of a variant.
+///
+/// Only ASCII letters, digits, `.`, `_` and `-` are allowed, and the name mustn't start with a
+/// `.`, so that it can neither escape its directory nor be hidden.
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Where transcoded photos get stored.
+pub enum Storage {
+    /// An S3-compatible bucket.
+    S3(Box<Bucket>),
+    /// A local directory, e.g. one served by the rusty-peanuts server under `/media/`.
+    Local(PathBuf),
+}
+
+impl Storage {
+    pub fn s3(
+        region_endpoint: &str,
+        bucket_name: &str,
+        access_key_id: &str,
+        secret_access_key: &str,
+    ) -> Self {
+        let credentials = Credentials::from_env_specific(
+            Some(access_key_id),
+            Some(secret_access_key),
+            None,
+            None,
+        )
+        .expect("couldn't create S3 credentials instance");
+
+        let region_name = region_endpoint
+            .split('.')
+            .next()
+            .expect("couldn't get region name from region endpoint")
+            .to_string();
+
+        let mut bucket = Bucket::new(
+            bucket_name,
+            s3::Region::Custom {
+                region: region_name,
+                endpoint: region_endpoint.to_string(),
+            },
+            credentials,
+        )
+        .expect("couldn't create S3 bucket instance")
+        .with_path_style();
+        bucket.add_header("x-amz-acl", "public-read");
+        bucket.add_header("Cache-Control", "max-age=31536000");
+
+        Storage::S3(Box::new(bucket))
+    }
+
+    pub fn local(directory: PathBuf) -> Self {
+        Storage::Local(directory)
+    }
+
+    /// Store `data` at `path`, relative to the root of the storage.
+    pub async fn put(&self, path: &str, data: &[u8], content_type: &str) -> Result<(), Error> {
+        // Refuse anything that could escape the root, or look like it could in an S3 key.
+        let mut components = Path::new(path).components().peekable();
+        if components.peek().is_none()
+            || !components.all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(Error::Storage(format!("refusing to store at {:?}", path)));
+        }
+
+        match self {
+            Storage::S3(bucket) => {
+                let response = bucket
+                    .put_object_with_content_type(path, data, content_type)
+                    .await
+                    .map_err(|err| Error::Storage(err.to_string()))?;
+                let code = response.status_code();
+                if !(200..300).contains(&code) {
+                    return Err(Error::Storage(format!(
+                        "uploading {} failed with status code {}",
+                        path, code
+                    )));
+                }
+            },
+            Storage::Local(directory) => {
+                let target = directory.join(path);
+                if let Some(parent) = target.parent() {
+                    async_std::fs::create_dir_all(parent).await?;
+                }
+
+                // Write to a temporary file first so that the file is never served half-written.
+                let temporary = target.with_extension("tmp");
+                async_std::fs::write(&temporary, data).await?;
+                async_std::fs::rename(&temporary, &target).await?;
+            },
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use async_std::task::JoinHandle;
+
+use rusty_peanuts_api_structs::Source;
+
+use crate::storage::Storage;
+use crate::Error;
+
+/// Sizes, in pixels along the longest edge, that photos are transcoded to.
+pub const SIZES: [u32; 16] = [
+    1800, 1700, 1600, 1500, 1400, 1300, 1200, 1100, 1000, 900, 800, 700, 600, 500, 400, 300,
+];
+
+pub fn decode_image<R: std::io::BufRead + std::io::Seek>(
+    read: R,
+) -> Result<(image::DynamicImage, image::ImageFormat), Error> {
+    let reader = image::io::Reader::new(read).with_guessed_format()?;
+
+    let format = reader.format().ok_or(Error::UnknownFormat)?;
+    let image = reader.decode()?;
+
+    Ok((image, format))
+}
+
+/// Output format of transcoded photos.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    WebP,
+    Avif,
+}
+
+impl Format {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Format::Jpeg => rusty_peanuts_api_structs::JPEG_MIME_TYPE,
+            Format::WebP => "image/webp",
+            Format::Avif => "image/avif",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpeg",
+            Format::WebP => "webp",
+            Format::Avif => "avif",
+        }
+    }
+
+    pub fn encode(self, image: &image::DynamicImage) -> Result<Vec<u8>, Error> {
+        match self {
+            Format::Jpeg => Ok(encode_jpeg(image)),
+            Format::WebP => Ok(encode_webp(image)),
+            Format::Avif => encode_avif(image),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Format::Jpeg),
+            "webp" => Ok(Format::WebP),
+            "avif" => Ok(Format::Avif),
+            _ => Err(Error::UnknownOutputFormat(s.to_string())),
+        }
+    }
+}
+
+/// A photo resized and encoded in a single format.
+#[derive(Debug)]
+pub struct Transcoded {
+    pub format: Format,
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn encode_jpeg(image: &image::DynamicImage) -> Vec<u8> {
+    let rgb_image = image.to_rgb8();
+    let (width, height) = (rgb_image.width(), rgb_image.height());
+    let rgb_data = rgb_image.into_vec();
+    log::debug!("Turned image into raw RGB data");
+
+    let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_EXT_RGB);
+    compress.set_size(width as usize, height as usize);
+    compress.set_quality(80.0);
+    compress.set_progressive_mode();
+    compress.set_scan_optimization_mode(mozjpeg::ScanMode::AllComponentsTogether);
+    compress.set_optimize_scans(true);
+    compress.set_mem_dest();
+
+    compress.start_compress();
+    log::debug!("Started compressing image");
+
+    compress.write_scanlines(&rgb_data);
+    log::debug!("Wrote scanlines");
+
+    compress.finish_compress();
+    log::debug!("Finished compressing image");
+
+    compress
+        .data_to_vec()
+        .expect("couldn't convert compressed image data to vector")
+}
+
+pub fn encode_webp(image: &image::DynamicImage) -> Vec<u8> {
+    let rgb_image = image.to_rgb8();
+    webp::Encoder::from_rgb(rgb_image.as_raw(), rgb_image.width(), rgb_image.height())
+        .encode(80.0)
+        .to_vec()
+}
+
+pub fn encode_avif(image: &image::DynamicImage) -> Result<Vec<u8>, Error> {
+    let rgb_image = image.to_rgb8();
+    let pixels: Vec<ravif::RGB8> = rgb_image
+        .pixels()
+        .map(|pixel| ravif::RGB8::new(pixel[0], pixel[1], pixel[2]))
+        .collect();
+
+    let encoded = ravif::Encoder::new()
+        .with_quality(70.0)
+        .with_speed(6)
+        .encode_rgb(ravif::Img::new(
+            &pixels[..],
+            rgb_image.width() as usize,
+            rgb_image.height() as usize,
+        ))?;
+    Ok(encoded.avif_file)
+}
+
+/// Resize a photo to every size in [`SIZES`] that's not larger than it, and encode each size in
+/// every format.
+///
+/// JPEG is always included, as it's the fallback for browsers that don't support other formats.
+pub fn transcode_photo(
+    image: image::DynamicImage,
+    formats: &[Format],
+) -> Vec<JoinHandle<Result<Vec<Transcoded>, Error>>> {
+    let mut formats = formats.to_vec();
+    if !formats.contains(&Format::Jpeg) {
+        formats.insert(0, Format::Jpeg);
+    }
+
+    // Filter out the target sizes to only contain those less than or equal to the largest of the
+    // photo's dimensions.
+    let (width, height) = (image.width(), image.height());
+    let sizes = SIZES
+        .iter()
+        .filter(move |&&s| s <= std::cmp::max(width, height));
+
+    let mut handles = Vec::new();
+    for size in sizes {
+        let image = image.clone();
+        let formats = formats.clone();
+
+        let handle = async_std::task::spawn_blocking(move || {
+            let start = std::time::Instant::now();
+            log::info!("Started resizing image to {}px", size);
+            let resized = image.resize(*size, *size, image::imageops::FilterType::Lanczos3);
+            log::info!(
+                "Finished resizing image to {}px in {}s",
+                size,
+                start.elapsed().as_secs_f32()
+            );
+
+            let mut transcoded = Vec::new();
+            for format in formats {
+                let data = format.encode(&resized)?;
+                log::info!(
+                    "Finished {:?} image of size {}px in {}s",
+                    format,
+                    size,
+                    start.elapsed().as_secs_f32()
+                );
+
+                transcoded.push(Transcoded {
+                    format,
+                    data,
+                    width: resized.width(),
+                    height: resized.height(),
+                });
+            }
+
+            Ok(transcoded)
+        });
+        handles.push(handle);
+    }
+
+    handles
+}
+
+/// Store a transcoded variant of a photo and describe where it ended up.
+///
+/// * `static_host`: Base URL that the root of `storage` is served from.
+pub async fn store_transcoded_photo(
+    storage: &Storage,
+    static_host: &str,
+    file_stem: &str,
+    transcoded: Transcoded,
+) -> Result<Source, Error> {
+    let Transcoded {
+        format,
+        data,
+        width,
+        height,
+    } = transcoded;
+    log::info!(
+        "Uploading resized {:?} image of size {}x{}",
+        format,
+        width,
+        height
+    );
+
+    let target_path = format!(
+        "{}/{}.{}x{}.{}",
+        file_stem,
+        file_stem,
+        width,
+        height,
+        format.extension()
+    );
+    storage.put(&target_path, &data, format.mime_type()).await?;
+    log::info!(
+        "Uploading resized {:?} image of size {}x{} finished",
+        format,
+        width,
+        height
+    );
+
+    Ok(Source {
+        width,
+        height,
+        url: format!("{}/{}", static_host, target_path),
+        mime_type: format.mime_type().to_string(),
+    })
+}
+
+/// Transcode a photo into every size and format, and store each variant as it's done.
+///
+/// * `static_host`: Base URL that the root of `storage` is served from.
+pub async fn transcode_and_store_photo(
+    storage: &Storage,
+    static_host: &str,
+    file_stem: &str,
+    image: image::DynamicImage,
+    formats: &[Format],
+) -> Result<Vec<Source>, Error> {
+    let mut sources = Vec::new();
+    for handle in transcode_photo(image, formats) {
+        for transcoded in handle.await? {
+            sources
+                .push(store_transcoded_photo(storage, static_host, file_stem, transcoded).await?);
+        }
+    }
+
+    Ok(sources)
+}
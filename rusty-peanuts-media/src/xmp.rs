@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use quick_xml::de::from_str;
+use serde::Deserialize;
+
+use crate::Error;
+
+/// Language tag of the default alternative of a language alternative array.
+const DEFAULT_LANGUAGE: &str = "x-default";
+
+#[derive(Debug, Deserialize)]
+struct LanguageAlternative {
+    #[serde(rename = "xml:lang")]
+    lang: Option<String>,
+    #[serde(rename = "$value", default)]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Alt {
+    li: Vec<LanguageAlternative>,
+}
+
+impl Alt {
+    /// Get the default alternative, falling back to the first one, and the alternatives of every
+    /// other language.
+    fn into_default_and_languages(self) -> (Option<String>, BTreeMap<String, String>) {
+        let mut default = None;
+        let mut first = None;
+        let mut languages = BTreeMap::new();
+        for alternative in self.li {
+            match alternative.lang {
+                Some(lang) if lang == DEFAULT_LANGUAGE => {
+                    default.get_or_insert(alternative.value);
+                },
+                Some(lang) => {
+                    first.get_or_insert_with(|| alternative.value.clone());
+                    languages.entry(lang).or_insert(alternative.value);
+                },
+                None => {
+                    first.get_or_insert(alternative.value);
+                },
+            }
+        }
+
+        (default.or(first), languages)
+    }
+}
+
+/// A language alternative array, like `dc:title`.
+#[derive(Debug, Deserialize)]
+struct LanguageAlternatives {
+    #[serde(rename = "Alt")]
+    alt: Alt,
+}
+
+#[derive(Debug, Deserialize)]
+struct Bag {
+    li: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subject {
+    #[serde(rename = "Bag")]
+    bag: Bag,
+}
+
+#[derive(Debug, Deserialize)]
+struct Description {
+    #[serde(rename = "xmp:CreateDate")]
+    create_date: Option<String>,
+    title: Option<LanguageAlternatives>,
+    /// `dc:description`, used as the caption.
+    description: Option<LanguageAlternatives>,
+    #[serde(rename = "AltTextAccessibility")]
+    alt_text: Option<LanguageAlternatives>,
+    subject: Option<Subject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rdf {
+    #[serde(rename = "Description")]
+    description: Vec<Description>,
+}
+
+#[derive(Debug, Deserialize)]
+struct XmpMeta {
+    #[serde(rename = "RDF")]
+    rdf: Rdf,
+}
+
+/// Metadata extracted from a photo's XMP packet.
+#[derive(Debug)]
+pub struct Metadata {
+    /// The raw XMP packet.
+    pub xmp_xml: String,
+    pub create_date: String,
+    /// The default title, or the first one if there is no default.
+    pub title: Option<String>,
+    /// Titles by language tag, without the default title.
+    pub titles: BTreeMap<String, String>,
+    /// The default caption, from `dc:description`.
+    pub caption: Option<String>,
+    /// The default accessibility description, from `Iptc4xmpCore:AltTextAccessibility`.
+    pub alt_text: Option<String>,
+    pub tags: Vec<String>,
+}
+
+pub fn get_metadata<R: std::io::Read + std::io::Seek>(read: R) -> Result<Metadata, Error> {
+    let bufreader = std::io::BufReader::new(read);
+    let mut decoder = tiff::decoder::Decoder::new(bufreader)?;
+
+    let xmp_tag = tiff::tags::Tag::Unknown(700);
+    let xmp_tag_data = decoder.get_tag(xmp_tag)?;
+
+    let xmp_bytes: Vec<_> = xmp_tag_data
+        .into_u64_vec()?
+        .into_iter()
+        .map(|v| v as u8)
+        .collect();
+    let xmp_xml_data = String::from_utf8(xmp_bytes)?;
+
+    let xmp_parsed: XmpMeta = from_str(&xmp_xml_data)?;
+
+    let (create_date, (title, titles), caption, alt_text, tags) = xmp_parsed
+        .rdf
+        .description
+        .into_iter()
+        .filter_map(|d| match (d.create_date, d.title, d.subject) {
+            (Some(create_date), title_element, Some(subject)) => {
+                let title = match title_element {
+                    Some(t) => t.alt.into_default_and_languages(),
+                    None => (None, BTreeMap::new()),
+                };
+                let default = |element: Option<LanguageAlternatives>| {
+                    element.and_then(|element| element.alt.into_default_and_languages().0)
+                };
+                let caption = default(d.description);
+                let alt_text = default(d.alt_text);
+                let subject = subject.bag.li;
+                Some((create_date, title, caption, alt_text, subject))
+            },
+            _ => None,
+        })
+        .next()
+        .ok_or(Error::MissingDescription)?;
+
+    Ok(Metadata {
+        xmp_xml: xmp_xml_data,
+        create_date,
+        title,
+        titles,
+        caption,
+        alt_text,
+        tags,
+    })
+}
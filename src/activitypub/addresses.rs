@@ -0,0 +1,96 @@
+//! Keeps requests to other servers on the public internet, as their URLs come from documents
+//! anyone can write.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{bail, Context, Result};
+use async_std::net::ToSocketAddrs;
+
+/// Whether an IPv4 address is reachable on the public internet.
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", 0.0.0.0/8.
+        || a == 0
+        // Shared address space for carrier-grade NAT, 100.64.0.0/10.
+        || (a == 100 && (b & 0xc0) == 64)
+        // IETF protocol assignments, 192.0.0.0/24.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15.
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4.
+        || a >= 240)
+}
+
+/// Whether an IPv6 address is reachable on the public internet.
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // IPv4-mapped and NAT64 addresses end up at the embedded IPv4 address.
+    if let [0, 0, 0, 0, 0, 0xffff, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] = segments {
+        let [.., a, b, c, d] = ip.octets();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local addresses, fc00::/7.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local addresses, fe80::/10.
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32.
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+/// Whether an address is reachable on the public internet, rather than e.g. loopback, a private
+/// network or a cloud provider's metadata service.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// Fail unless the URL is an HTTP(S) URL whose host only resolves to public addresses.
+pub async fn ensure_public(url: &url::Url) -> Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("Unsupported URL scheme {}", url.scheme());
+    }
+
+    let port = url.port_or_known_default().context("URL has no port")?;
+    let addresses: Vec<IpAddr> = match url.host() {
+        Some(url::Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(url::Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(url::Host::Domain(domain)) => (domain, port)
+            .to_socket_addrs()
+            .await
+            .with_context(|| format!("Couldn't resolve {}", domain))?
+            .map(|address| address.ip())
+            .collect(),
+        None => bail!("URL has no host"),
+    };
+
+    if addresses.is_empty() {
+        bail!(
+            "{} doesn't resolve to any address",
+            url.host_str().unwrap_or_default()
+        );
+    }
+    if let Some(address) = addresses.into_iter().find(|&address| !is_public(address)) {
+        bail!(
+            "{} resolves to non-public address {}",
+            url.host_str().unwrap_or_default(),
+            address
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_std::channel::{Receiver, Sender};
+use async_std::task::JoinHandle;
+use sqlx::postgres::PgPool;
+use tracing::{error, info, instrument, warn};
+
+use crate::activitypub::LocalActor;
+use crate::db::activitypub::{ActivityPubProvider, PendingDelivery};
+use crate::webhooks::retry_delay;
+
+/// Number of attempts after which a delivery is given up on, which with the same backoff as
+/// webhooks spans about a day and a half.
+const MAX_ATTEMPTS: i32 = 16;
+/// How long a claimed delivery is kept from being claimed again, must exceed the request
+/// timeout.
+const CLAIM_LEASE: Duration = Duration::from_secs(5 * 60);
+/// Number of deliveries to claim at a time.
+const CLAIM_BATCH_SIZE: i64 = 16;
+
+/// Background task delivering queued activities to other servers' inboxes, retrying failed
+/// deliveries with exponential backoff.
+///
+/// The queue lives in the database, so deliveries survive restarts.
+#[derive(Clone, Debug)]
+pub struct DeliveryDispatcher {
+    wake: Sender<()>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl DeliveryDispatcher {
+    /// Start delivering queued activities signed by `actor`, checking for due deliveries every
+    /// `poll_interval` and whenever new ones are queued.
+    pub fn start(db: PgPool, actor: Arc<LocalActor>, poll_interval: Duration) -> Self {
+        let (wake, woken) = async_std::channel::bounded(1);
+        let task = async_std::task::spawn(run(db, actor, woken, poll_interval));
+
+        DeliveryDispatcher {
+            wake,
+            task: Arc::new(Mutex::new(Some(task))),
+        }
+    }
+
+    /// Check for due deliveries right away.
+    pub fn wake(&self) {
+        // A full channel means a check is already pending.
+        let _ = self.wake.try_send(());
+    }
+
+    /// Stop checking for deliveries and wait for the current batch to be attempted, giving up
+    /// after `deadline`.
+    ///
+    /// Returns whether the batch was finished before the deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.wake.close();
+        let task = self
+            .task
+            .lock()
+            .expect("dispatcher task lock was poisoned")
+            .take();
+
+        crate::shutdown::join_tasks("activitypub", task.into_iter().collect(), deadline).await
+    }
+}
+
+async fn run(db: PgPool, actor: Arc<LocalActor>, woken: Receiver<()>, poll_interval: Duration) {
+    // The wake channel is only closed when shutting down.
+    while !woken.is_closed() {
+        match deliver_due(&db, &actor).await {
+            // A full batch means there might be more due deliveries waiting.
+            Ok(claimed) if claimed >= CLAIM_BATCH_SIZE as usize => continue,
+            Ok(_) => {},
+            Err(err) => error!(
+                exception.message = %format!("{:#}", err),
+                "Failed to process ActivityPub deliveries"
+            ),
+        }
+
+        let _ = async_std::future::timeout(poll_interval, woken.recv()).await;
+    }
+}
+
+/// Claim and attempt a batch of due deliveries, returning how many were claimed.
+async fn deliver_due(db: &PgPool, actor: &LocalActor) -> Result<usize> {
+    let mut conn = db.acquire().await?;
+    let deliveries = conn
+        .claim_activitypub_deliveries(CLAIM_BATCH_SIZE, CLAIM_LEASE.as_secs_f64())
+        .await?;
+    drop(conn);
+
+    let claimed = deliveries.len();
+    for delivery in deliveries {
+        attempt(db, actor, delivery).await?;
+    }
+
+    Ok(claimed)
+}
+
+#[instrument(skip_all, fields(delivery.id = delivery.id, inbox = %delivery.inbox))]
+async fn attempt(db: &PgPool, actor: &LocalActor, delivery: PendingDelivery) -> Result<()> {
+    let body = serde_json::to_vec(&delivery.activity.0)?;
+    let res = actor.deliver(&delivery.inbox, &body).await;
+
+    let mut conn = db.acquire().await?;
+    match res {
+        Ok(()) => {
+            info!(attempts = delivery.attempts, "Delivered activity");
+            conn.mark_activitypub_delivered(delivery.id).await?;
+        },
+        Err(err) if delivery.attempts >= MAX_ATTEMPTS => {
+            let reason = format!("{:#}", err);
+            error!(
+                attempts = delivery.attempts,
+                exception.message = %reason,
+                "Giving up on activity delivery"
+            );
+            conn.fail_activitypub_delivery(delivery.id, &reason).await?;
+        },
+        Err(err) => {
+            let reason = format!("{:#}", err);
+            let delay = retry_delay(delivery.attempts);
+            warn!(
+                attempts = delivery.attempts,
+                retry.seconds = delay.as_secs(),
+                exception.message = %reason,
+                "Activity delivery failed, retrying later"
+            );
+            conn.retry_activitypub_delivery(delivery.id, delay.as_secs_f64(), &reason)
+                .await?;
+        },
+    }
+
+    Ok(())
+}
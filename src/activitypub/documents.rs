@@ -0,0 +1,244 @@
+//! ActivityStreams documents describing the gallery, its photos and what happens to them.
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use crate::activitypub::LocalActor;
+use crate::models::photos::Photo;
+use crate::web::html::render_markdown;
+
+const ACTIVITYSTREAMS_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+/// Audience making an object visible to everyone.
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// The current time as a W3C datetime in UTC, for `published` properties.
+pub fn now() -> String {
+    let format = time::format_description::parse("[year]-[month]-[day]T[hour]:[minute]:[second]Z")
+        .expect("invalid format");
+    OffsetDateTime::now_utc()
+        .format(&format)
+        .expect("failed to format date")
+}
+
+/// WebFinger document pointing to the actor, see RFC 7033.
+pub fn webfinger(actor: &LocalActor) -> Value {
+    json!({
+        "subject": actor.acct(),
+        "aliases": [actor.id(), actor.base_url()],
+        "links": [
+            {
+                "rel": "self",
+                "type": super::ACTIVITY_JSON,
+                "href": actor.id(),
+            },
+            {
+                "rel": "http://webfinger.net/rel/profile-page",
+                "type": "text/html",
+                "href": actor.base_url(),
+            },
+        ],
+    })
+}
+
+/// The actor document, with the public key other servers verify our signatures with.
+///
+/// * `name`: Display name, usually the site name.
+/// * `summary`: Plain text bio, usually who took the photos.
+pub fn actor(actor: &LocalActor, name: &str, summary: Option<&str>) -> Value {
+    json!({
+        "@context": [ACTIVITYSTREAMS_CONTEXT, SECURITY_CONTEXT],
+        "id": actor.id(),
+        "type": "Person",
+        "preferredUsername": actor.username(),
+        "name": name,
+        "summary": summary.map(|summary| format!("<p>{}</p>", tera::escape_html(summary))),
+        "url": actor.base_url(),
+        "inbox": actor.inbox(),
+        "outbox": actor.outbox(),
+        "followers": actor.followers(),
+        "manuallyApprovesFollowers": false,
+        "discoverable": true,
+        "endpoints": {
+            "sharedInbox": actor.inbox(),
+        },
+        "publicKey": {
+            "id": actor.key_id(),
+            "owner": actor.id(),
+            "publicKeyPem": actor.key().public_key_pem(),
+        },
+    })
+}
+
+/// Whether a tag can be posted as a hashtag, which can't contain spaces or punctuation.
+fn is_hashtag(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// The Note a photo is posted as, with its largest JPEG source attached.
+pub fn note(actor: &LocalActor, photo: &Photo) -> Value {
+    let url = format!("{}/photo/{}", actor.base_url(), photo.id);
+    let tag_url = |tag: &str| {
+        format!(
+            "{}/tagged/{}",
+            actor.base_url(),
+            utf8_percent_encode(tag, NON_ALPHANUMERIC)
+        )
+    };
+    let hashtags: Vec<&str> = photo
+        .tags
+        .iter()
+        .map(String::as_str)
+        .filter(|tag| is_hashtag(tag))
+        .collect();
+
+    let mut content = String::new();
+    if let Some(ref title) = photo.title {
+        content.push_str(&format!("<p>{}</p>", tera::escape_html(title)));
+    }
+    if let Some(ref caption) = photo.caption {
+        content.push_str(&render_markdown(caption));
+    }
+    if !hashtags.is_empty() {
+        let links: Vec<String> = hashtags
+            .iter()
+            .map(|tag| {
+                format!(
+                    r#"<a href="{}" class="mention hashtag" rel="tag">#<span>{}</span></a>"#,
+                    tag_url(tag),
+                    tera::escape_html(tag)
+                )
+            })
+            .collect();
+        content.push_str(&format!("<p>{}</p>", links.join(" ")));
+    }
+
+    let attachment: Vec<Value> = photo
+        .jpeg_sources()
+        .next()
+        .map(|source| {
+            json!({
+                "type": "Document",
+                "mediaType": source.mime_type,
+                "url": source.url,
+                "name": photo.alt_text.as_deref().or(photo.title.as_deref()),
+                "width": source.width,
+                "height": source.height,
+                "blurhash": photo.blurhash,
+            })
+        })
+        .into_iter()
+        .collect();
+
+    let tag: Vec<Value> = hashtags
+        .iter()
+        .map(|tag| {
+            json!({
+                "type": "Hashtag",
+                "name": format!("#{}", tag),
+                "href": tag_url(tag),
+            })
+        })
+        .collect();
+
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": actor.photo_note(photo.id),
+        "type": "Note",
+        "attributedTo": actor.id(),
+        "url": url,
+        "content": content,
+        "to": [PUBLIC],
+        "cc": [actor.followers()],
+        "sensitive": false,
+        "attachment": attachment,
+        "tag": tag,
+    })
+}
+
+/// The activity posting a photo.
+///
+/// * `published`: When the photo was published, as a W3C datetime, if known.
+pub fn create(actor: &LocalActor, photo: &Photo, published: Option<&str>) -> Value {
+    let mut note = note(actor, photo);
+    let mut create = json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": format!("{}/activity", actor.photo_note(photo.id)),
+        "type": "Create",
+        "actor": actor.id(),
+        "to": [PUBLIC],
+        "cc": [actor.followers()],
+    });
+    if let Some(published) = published {
+        note["published"] = json!(published);
+        create["published"] = json!(published);
+    }
+    create["object"] = note;
+    create
+}
+
+/// The activity accepting a Follow activity.
+pub fn accept(actor: &LocalActor, follow: &Value) -> Value {
+    let id: String = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": format!("{}#accepts/{}", actor.id(), id),
+        "type": "Accept",
+        "actor": actor.id(),
+        "object": follow,
+    })
+}
+
+/// The outbox collection, whose pages list the published photos newest first.
+pub fn outbox(actor: &LocalActor, total_items: usize) -> Value {
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": actor.outbox(),
+        "type": "OrderedCollection",
+        "totalItems": total_items,
+        "first": format!("{}?page=true", actor.outbox()),
+    })
+}
+
+/// A page of the outbox.
+///
+/// * `page_id`: URL of the page.
+/// * `next_max_id`: If `Some`, the next page has the photos before this one.
+pub fn outbox_page(
+    actor: &LocalActor,
+    page_id: &str,
+    photos: &[Photo],
+    next_max_id: Option<i32>,
+) -> Value {
+    let items: Vec<Value> = photos
+        .iter()
+        .map(|photo| create(actor, photo, None))
+        .collect();
+
+    let mut page = json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": page_id,
+        "type": "OrderedCollectionPage",
+        "partOf": actor.outbox(),
+        "orderedItems": items,
+    });
+    if let Some(max_id) = next_max_id {
+        page["next"] = json!(format!("{}?page=true&max_id={}", actor.outbox(), max_id));
+    }
+    page
+}
+
+/// The followers collection, which only says how many there are.
+pub fn followers(actor: &LocalActor, total_items: i64) -> Value {
+    json!({
+        "@context": ACTIVITYSTREAMS_CONTEXT,
+        "id": actor.followers(),
+        "type": "OrderedCollection",
+        "totalItems": total_items,
+    })
+}
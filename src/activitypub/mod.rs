@@ -0,0 +1,431 @@
+//! Lets the gallery be followed from the fediverse, as a single ActivityPub actor whose posts
+//! are the published photos.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use sqlx::postgres::PgPool;
+use sqlx::PgConnection;
+use thiserror::Error;
+use tracing::{error, info};
+
+use crate::db::activitypub::ActivityPubProvider;
+use crate::models::photos::Photo;
+use delivery::DeliveryDispatcher;
+use signatures::{PrivateKey, PublicKey, SignatureError, SignatureParams};
+
+pub mod addresses;
+pub mod delivery;
+pub mod documents;
+pub mod signatures;
+
+/// Media type of ActivityStreams documents.
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// What to accept when fetching documents from other servers.
+const ACCEPT: &str = concat!(
+    "application/activity+json, ",
+    r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#,
+);
+
+/// How long another server gets to respond.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long fetched keys are used before fetching them again.
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// How soon a cached key can be fetched again when a signature doesn't verify with it, e.g.
+/// because it was rotated.
+const KEY_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of cached keys above which expired ones get pruned.
+const KEY_CACHE_PRUNE_THRESHOLD: usize = 1_000;
+
+/// The gallery's own actor.
+#[derive(Debug)]
+pub struct LocalActor {
+    base_url: String,
+    username: String,
+    /// Host and port the gallery is served from, the domain part of its WebFinger address.
+    host: String,
+    key: PrivateKey,
+    allow_private_addresses: bool,
+}
+
+impl LocalActor {
+    pub fn new(base_url: &str, username: &str, key: PrivateKey) -> Result<Self> {
+        let url = url::Url::parse(base_url).context("Invalid base URL")?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => bail!("Base URL has no host"),
+        };
+
+        Ok(LocalActor {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            host,
+            key,
+            allow_private_addresses: false,
+        })
+    }
+
+    /// Allow fetching from and delivering to loopback and private addresses, which are refused
+    /// by default as anyone can make the gallery send requests to them, see `addresses`.
+    pub fn allow_private_addresses(mut self) -> Self {
+        self.allow_private_addresses = true;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn key(&self) -> &PrivateKey {
+        &self.key
+    }
+
+    /// The actor's WebFinger address, e.g. `acct:gallery@example.com`.
+    pub fn acct(&self) -> String {
+        format!("acct:{}@{}", self.username, self.host)
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}/activitypub/actor", self.base_url)
+    }
+
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.id())
+    }
+
+    pub fn inbox(&self) -> String {
+        format!("{}/activitypub/inbox", self.base_url)
+    }
+
+    pub fn outbox(&self) -> String {
+        format!("{}/activitypub/outbox", self.base_url)
+    }
+
+    pub fn followers(&self) -> String {
+        format!("{}/activitypub/followers", self.base_url)
+    }
+
+    /// ID of the Note a photo is posted as.
+    pub fn photo_note(&self, photo_id: i32) -> String {
+        format!("{}/activitypub/photo/{}", self.base_url, photo_id)
+    }
+
+    /// Fail unless requests may be sent to the URL.
+    async fn check_destination(&self, url: &url::Url) -> Result<()> {
+        if self.allow_private_addresses {
+            return Ok(());
+        }
+        addresses::ensure_public(url).await
+    }
+
+    /// POST an activity signed with the actor's key to an inbox, see `post_signed`.
+    pub async fn deliver(&self, inbox: &str, body: &[u8]) -> Result<()> {
+        let url = url::Url::parse(inbox).context("Invalid inbox URL")?;
+        self.check_destination(&url).await?;
+
+        post_signed(self.key(), &self.key_id(), inbox, body).await
+    }
+}
+
+/// An actor on another server, as far as following the gallery is concerned.
+#[derive(Clone, Debug)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub public_key: PublicKey,
+}
+
+/// Get the ID of an object that's either embedded or referenced by ID.
+pub fn object_id(object: &Value) -> Option<&str> {
+    match object {
+        Value::String(id) => Some(id),
+        Value::Object(object) => object.get("id")?.as_str(),
+        _ => None,
+    }
+}
+
+/// Whether two URLs have the same scheme, host and port.
+fn same_origin(a: &str, b: &str) -> bool {
+    match (url::Url::parse(a), url::Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+/// Fetch a document from another server, signing the request in case it requires it.
+async fn fetch(local: &LocalActor, url: &str) -> Result<Value> {
+    let parsed = url::Url::parse(url).context("Invalid URL")?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!("Unsupported URL scheme {}", parsed.scheme());
+    }
+    local.check_destination(&parsed).await?;
+
+    let mut req = surf::get(url).header("Accept", ACCEPT);
+    for (name, value) in
+        signatures::sign_request(local.key(), &local.key_id(), "GET", &parsed, None)
+    {
+        req = req.header(name, value);
+    }
+
+    let mut res = async_std::future::timeout(REQUEST_TIMEOUT, req)
+        .await
+        .context("Server didn't respond in time")?
+        .map_err(|err| err.into_inner())
+        .context("Failed to send request")?;
+    if !res.status().is_success() {
+        bail!("Server responded with status {}", res.status());
+    }
+
+    res.body_json()
+        .await
+        .map_err(|err| err.into_inner())
+        .context("Failed to parse document")
+}
+
+/// Fetch the actor owning the key with the given ID.
+///
+/// Everything about the actor has to be on the same server as the key, so that a request can't
+/// make the gallery send requests to any other server.
+pub async fn fetch_actor(local: &LocalActor, key_id: &str) -> Result<RemoteActor> {
+    let url = key_id.split('#').next().unwrap_or(key_id);
+    let mut document = fetch(local, url).await?;
+
+    // Some servers serve keys as documents of their own, pointing to their owner.
+    if document.get("inbox").is_none() {
+        let owner = document
+            .get("owner")
+            .and_then(Value::as_str)
+            .context("Document is neither an actor nor a key")?
+            .to_string();
+        if !same_origin(&owner, key_id) {
+            bail!("Key owner {} is on another server than the key", owner);
+        }
+        document = fetch(local, &owner).await?;
+    }
+
+    let keys = match &document["publicKey"] {
+        Value::Array(keys) => keys.iter().collect(),
+        key => vec![key],
+    };
+    let key = keys
+        .into_iter()
+        .find(|key| key["id"].as_str() == Some(key_id))
+        .context("Actor doesn't have the key")?;
+    let public_key = PublicKey::from_pem(
+        key["publicKeyPem"]
+            .as_str()
+            .context("Key has no PEM-encoded public key")?,
+    )?;
+
+    // Otherwise any server could serve a key for actors on other servers.
+    let id = document["id"].as_str().context("Actor has no ID")?;
+    if !same_origin(id, key_id) {
+        bail!("Actor {} is on another server than its key", id);
+    }
+
+    let inbox = document["inbox"].as_str().context("Actor has no inbox")?;
+    if !same_origin(inbox, id) {
+        bail!("Actor {} has an inbox on another server", id);
+    }
+
+    Ok(RemoteActor {
+        id: id.to_string(),
+        inbox: inbox.to_string(),
+        shared_inbox: document["endpoints"]["sharedInbox"]
+            .as_str()
+            .filter(|shared_inbox| same_origin(shared_inbox, id))
+            .map(str::to_string),
+        public_key,
+    })
+}
+
+/// Actors fetched to verify signatures, by key ID, so that not every activity makes the gallery
+/// fetch its actor.
+#[derive(Clone, Debug, Default)]
+pub struct KeyCache {
+    actors: Arc<Mutex<HashMap<String, (RemoteActor, Instant)>>>,
+}
+
+impl KeyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the actor owning a key and when it was fetched, unless that was too long ago.
+    fn get(&self, key_id: &str) -> Option<(RemoteActor, Instant)> {
+        let actors = self.actors.lock().expect("key cache lock was poisoned");
+        actors
+            .get(key_id)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < KEY_CACHE_TTL)
+            .cloned()
+    }
+
+    fn insert(&self, key_id: &str, actor: RemoteActor) {
+        let mut actors = self.actors.lock().expect("key cache lock was poisoned");
+        if actors.len() > KEY_CACHE_PRUNE_THRESHOLD {
+            actors.retain(|_, (_, fetched_at)| fetched_at.elapsed() < KEY_CACHE_TTL);
+        }
+        // Don't let the cache grow without bounds when lots of keys get fetched at once.
+        if actors.len() > KEY_CACHE_PRUNE_THRESHOLD {
+            actors.clear();
+        }
+        actors.insert(key_id.to_string(), (actor, Instant::now()));
+    }
+}
+
+/// POST a signed activity to an inbox, failing unless it responds with a 2xx status.
+///
+/// Doesn't check where the inbox is, which `LocalActor::deliver` does.
+pub async fn post_signed(key: &PrivateKey, key_id: &str, inbox: &str, body: &[u8]) -> Result<()> {
+    let url = url::Url::parse(inbox).context("Invalid inbox URL")?;
+
+    let mut req = surf::post(inbox)
+        .body(body.to_vec())
+        .content_type(ACTIVITY_JSON);
+    for (name, value) in signatures::sign_request(key, key_id, "POST", &url, Some(body)) {
+        req = req.header(name, value);
+    }
+
+    let res = async_std::future::timeout(REQUEST_TIMEOUT, req)
+        .await
+        .context("Inbox didn't respond in time")?
+        .map_err(|err| err.into_inner())
+        .context("Failed to send request")?;
+    if !res.status().is_success() {
+        bail!("Inbox responded with status {}", res.status());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum InboxError {
+    #[error("invalid signature: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("couldn't fetch the signing key: {0:#}")]
+    Key(anyhow::Error),
+    #[error("the activity's actor doesn't own the signing key")]
+    ActorMismatch,
+}
+
+/// Verify that an activity POSTed to the inbox was signed by its actor.
+///
+/// The signing key's actor is fetched unless it's in `keys`, and then cached there.
+pub async fn authenticate(
+    local: &LocalActor,
+    keys: &KeyCache,
+    req: &tide::http::Request,
+    body: &[u8],
+    activity: &Value,
+) -> Result<RemoteActor, InboxError> {
+    let params = SignatureParams::from_request(req)?;
+
+    let cached = match keys.get(&params.key_id) {
+        Some((actor, fetched_at)) => {
+            match signatures::verify_request(req, body, &params, &actor.public_key) {
+                Ok(()) => Some(actor),
+                // The key might have been rotated, but bad signatures mustn't be a way to make
+                // the gallery fetch it over and over.
+                Err(_) if fetched_at.elapsed() >= KEY_REFETCH_INTERVAL => None,
+                Err(err) => return Err(err.into()),
+            }
+        },
+        None => None,
+    };
+    let actor = match cached {
+        Some(actor) => actor,
+        None => {
+            let actor = fetch_actor(local, &params.key_id)
+                .await
+                .map_err(InboxError::Key)?;
+            keys.insert(&params.key_id, actor.clone());
+            signatures::verify_request(req, body, &params, &actor.public_key)?;
+            actor
+        },
+    };
+
+    if activity.get("actor").and_then(object_id) != Some(&actor.id) {
+        return Err(InboxError::ActorMismatch);
+    }
+
+    Ok(actor)
+}
+
+/// Federation state: the gallery's actor, the keys of actors sending it activities and the task
+/// delivering its activities.
+#[derive(Clone, Debug)]
+pub struct Federation {
+    pub actor: Arc<LocalActor>,
+    pub keys: KeyCache,
+    dispatcher: DeliveryDispatcher,
+}
+
+impl Federation {
+    /// Start delivering queued activities, see `DeliveryDispatcher::start`.
+    pub fn start(db: PgPool, actor: LocalActor, poll_interval: Duration) -> Self {
+        let actor = Arc::new(actor);
+        let dispatcher = DeliveryDispatcher::start(db, actor.clone(), poll_interval);
+
+        Federation {
+            actor,
+            keys: KeyCache::new(),
+            dispatcher,
+        }
+    }
+
+    /// Stop delivering activities, see `DeliveryDispatcher::shutdown`.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.dispatcher.shutdown(deadline).await
+    }
+
+    /// Queue a `Create` activity for a photo that was just published for every follower.
+    ///
+    /// Failing to queue is logged rather than failing the request that published the photo.
+    pub async fn publish(&self, conn: &mut PgConnection, photo: &Photo) {
+        let activity = documents::create(&self.actor, photo, Some(&documents::now()));
+
+        match conn
+            .enqueue_activitypub_deliveries_to_followers(&activity)
+            .await
+        {
+            Ok(0) => {},
+            Ok(queued) => {
+                info!(
+                    photo.id = photo.id,
+                    deliveries = queued,
+                    "Queued Create activity"
+                );
+                self.dispatcher.wake();
+            },
+            Err(err) => error!(
+                exception.message = %err,
+                photo.id = photo.id,
+                "Failed to queue Create activity"
+            ),
+        }
+    }
+
+    /// Queue an activity for delivery to a single inbox.
+    pub async fn send(
+        &self,
+        conn: &mut PgConnection,
+        inbox: &str,
+        activity: &Value,
+    ) -> Result<(), sqlx::Error> {
+        conn.enqueue_activitypub_delivery(inbox, activity).await?;
+        self.dispatcher.wake();
+
+        Ok(())
+    }
+}
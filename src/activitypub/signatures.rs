@@ -0,0 +1,410 @@
+//! HTTP signatures as used by ActivityPub servers, see
+//! <https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-12>.
+//!
+//! Only RSA-SHA256 is supported, as that's what every major implementation uses.
+
+use anyhow::{bail, Context, Result};
+use ring::signature::{self, RsaKeyPair, UnparsedPublicKey};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+/// DER encoding of the `rsaEncryption` algorithm identifier's OID and NULL parameters.
+const RSA_ALGORITHM: [u8; 13] = [
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+];
+
+/// Format of the `Date` header, see RFC 7231 section 7.1.1.1.
+const HTTP_DATE_FORMAT: &str =
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT";
+
+/// How far in the past a signed request's date can be.
+const MAX_AGE: Duration = Duration::hours(12);
+/// How far in the future a signed request's date can be, to allow for clock skew.
+const MAX_SKEW: Duration = Duration::hours(1);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("missing Signature header")]
+    Missing,
+    #[error("malformed Signature header")]
+    Malformed,
+    #[error("unsupported signature algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("the {0} header must be signed")]
+    Unsigned(&'static str),
+    #[error("signed header {0} is missing")]
+    MissingHeader(String),
+    #[error("the date is malformed or too far from now")]
+    Date,
+    #[error("the digest doesn't match the body")]
+    Digest,
+    #[error("the signature doesn't match")]
+    Invalid,
+}
+
+/// Decode the base64 between the `BEGIN` and `END` lines of a PEM document with the given label.
+fn decode_pem(pem: &str, label: &str) -> Option<Vec<u8>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+
+    let (_, rest) = pem.split_once(&begin)?;
+    let (body, _) = rest.split_once(&end)?;
+    let body: String = body.split_whitespace().collect();
+    base64::decode(body).ok()
+}
+
+fn encode_pem(der: &[u8], label: &str) -> String {
+    let body = base64::encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// DER-encode an element with the given tag and contents.
+fn der_element(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    match contents.len() {
+        len @ 0..=0x7f => der.push(len as u8),
+        len => {
+            let len_bytes: Vec<u8> = len
+                .to_be_bytes()
+                .iter()
+                .copied()
+                .skip_while(|byte| *byte == 0)
+                .collect();
+            der.push(0x80 | len_bytes.len() as u8);
+            der.extend(len_bytes);
+        },
+    }
+    der.extend(contents);
+    der
+}
+
+/// Read a DER element with the given tag, returning its contents and whatever follows it.
+fn read_der_element(der: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, rest) = der.split_first()?;
+    if actual_tag != tag {
+        return None;
+    }
+
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        len @ 0..=0x7f => (len as usize, rest),
+        long => {
+            let count = (long & 0x7f) as usize;
+            if count == 0 || count > std::mem::size_of::<usize>() || rest.len() < count {
+                return None;
+            }
+            let (len_bytes, rest) = rest.split_at(count);
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+            (len, rest)
+        },
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+/// An RSA private key to sign requests with.
+pub struct PrivateKey {
+    key_pair: RsaKeyPair,
+    public_key_pem: String,
+}
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrivateKey").finish_non_exhaustive()
+    }
+}
+
+impl PrivateKey {
+    /// Parse a PEM-encoded PKCS#8 RSA private key, as generated by `openssl genpkey`.
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let der = decode_pem(pem, "PRIVATE KEY").context("Not a PEM-encoded PKCS#8 key")?;
+        let key_pair = RsaKeyPair::from_pkcs8(&der)
+            .map_err(|err| anyhow::anyhow!("Unsupported RSA key: {}", err))?;
+
+        // ring only gives us the PKCS#1 public key, but other servers expect a
+        // SubjectPublicKeyInfo.
+        let mut bit_string = vec![0];
+        bit_string.extend(key_pair.public_key().as_ref());
+        let mut spki = der_element(0x30, &RSA_ALGORITHM);
+        spki.extend(der_element(0x03, &bit_string));
+        let public_key_pem = encode_pem(&der_element(0x30, &spki), "PUBLIC KEY");
+
+        Ok(PrivateKey {
+            key_pair,
+            public_key_pem,
+        })
+    }
+
+    /// The public key as a PEM-encoded SubjectPublicKeyInfo, for actor documents.
+    pub fn public_key_pem(&self) -> &str {
+        &self.public_key_pem
+    }
+
+    /// Sign a message with RSASSA-PKCS1-v1_5 using SHA-256.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        self.key_pair
+            .sign(
+                &signature::RSA_PKCS1_SHA256,
+                &ring::rand::SystemRandom::new(),
+                message,
+                &mut signature,
+            )
+            .expect("failed to sign message");
+        signature
+    }
+}
+
+/// An RSA public key to verify signed requests with.
+#[derive(Clone, Debug)]
+pub struct PublicKey {
+    /// The key as a DER-encoded PKCS#1 `RSAPublicKey`.
+    der: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Parse a PEM-encoded SubjectPublicKeyInfo or PKCS#1 RSA public key.
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        if let Some(der) = decode_pem(pem, "RSA PUBLIC KEY") {
+            return Ok(PublicKey { der });
+        }
+
+        let spki = decode_pem(pem, "PUBLIC KEY").context("Not a PEM-encoded public key")?;
+        let (spki, _) = read_der_element(&spki, 0x30).context("Malformed public key")?;
+        let (algorithm, rest) = read_der_element(spki, 0x30).context("Malformed public key")?;
+        if !algorithm.starts_with(&RSA_ALGORITHM[..11]) {
+            bail!("Not an RSA public key");
+        }
+        match read_der_element(rest, 0x03) {
+            Some(([0, der @ ..], _)) => Ok(PublicKey { der: der.to_vec() }),
+            _ => bail!("Malformed public key"),
+        }
+    }
+
+    /// Verify an RSASSA-PKCS1-v1_5 signature using SHA-256.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, &self.der)
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+/// Format a time for the `Date` header.
+pub fn http_date(at: OffsetDateTime) -> String {
+    let format = time::format_description::parse(HTTP_DATE_FORMAT).expect("invalid format");
+    at.to_offset(time::UtcOffset::UTC)
+        .format(&format)
+        .expect("failed to format date")
+}
+
+fn parse_http_date(date: &str) -> Option<OffsetDateTime> {
+    let format = time::format_description::parse(HTTP_DATE_FORMAT).expect("invalid format");
+    PrimitiveDateTime::parse(date, &format)
+        .ok()
+        .map(PrimitiveDateTime::assume_utc)
+}
+
+/// Value of the `Digest` header for a body.
+pub fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", base64::encode(Sha256::digest(body)))
+}
+
+/// Check a `Digest` header, which can contain digests using several algorithms.
+fn digest_matches(header: &str, body: &[u8]) -> bool {
+    let expected = base64::encode(Sha256::digest(body));
+    header
+        .split(',')
+        .filter_map(|digest| digest.trim().split_once('='))
+        .any(|(algorithm, value)| algorithm.eq_ignore_ascii_case("SHA-256") && value == expected)
+}
+
+fn request_target(method: &str, url: &url::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{} {}?{}", method.to_lowercase(), url.path(), query),
+        None => format!("{} {}", method.to_lowercase(), url.path()),
+    }
+}
+
+/// Value of the `Host` header for a URL.
+fn host(url: &url::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Get the headers to add to a request to sign it, including the `Signature` header.
+///
+/// The request target, `Host` and `Date` headers are always signed, along with the `Digest`
+/// header if there's a body. The `Host` header isn't included, as the HTTP client sets it from
+/// the URL.
+pub fn sign_request(
+    key: &PrivateKey,
+    key_id: &str,
+    method: &str,
+    url: &url::Url,
+    body: Option<&[u8]>,
+) -> Vec<(&'static str, String)> {
+    let mut headers = vec![
+        ("host", host(url)),
+        ("date", http_date(OffsetDateTime::now_utc())),
+    ];
+    if let Some(body) = body {
+        headers.push(("digest", digest(body)));
+    }
+
+    let mut signing_string = format!("(request-target): {}", request_target(method, url));
+    for (name, value) in &headers {
+        signing_string.push_str(&format!("\n{}: {}", name, value));
+    }
+    let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+
+    let signature = format!(
+        r#"keyId="{}",algorithm="rsa-sha256",headers="(request-target) {}",signature="{}""#,
+        key_id,
+        names.join(" "),
+        base64::encode(key.sign(signing_string.as_bytes())),
+    );
+    headers.push(("signature", signature));
+    headers.retain(|(name, _)| *name != "host");
+    headers
+}
+
+/// The parameters of a `Signature` header.
+#[derive(Debug)]
+pub struct SignatureParams {
+    /// URL of the key the request was signed with.
+    pub key_id: String,
+    /// Lowercase names of the signed headers, in signing order.
+    headers: Vec<String>,
+    signature: Vec<u8>,
+}
+
+impl std::str::FromStr for SignatureParams {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut key_id = None;
+        let mut algorithm = None;
+        let mut headers = None;
+        let mut signature = None;
+
+        let mut rest = s.trim();
+        while !rest.is_empty() {
+            let (name, value) = rest.split_once('=').ok_or(SignatureError::Malformed)?;
+            let value = value.trim_start();
+            let (value, after) = match value.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').ok_or(SignatureError::Malformed)?,
+                None => value.split_once(',').unwrap_or((value, "")),
+            };
+            rest = after.trim_start().trim_start_matches(',').trim_start();
+
+            match name.trim() {
+                "keyId" => key_id = Some(value.to_string()),
+                "algorithm" => algorithm = Some(value.to_string()),
+                "headers" => headers = Some(value.to_lowercase()),
+                "signature" => {
+                    signature = Some(base64::decode(value).map_err(|_| SignatureError::Malformed)?)
+                },
+                _ => {},
+            }
+        }
+
+        // Implementations using hs2019 with RSA keys still sign with RSA-SHA256.
+        match algorithm.as_deref() {
+            None | Some("rsa-sha256") | Some("hs2019") => {},
+            Some(other) => return Err(SignatureError::UnsupportedAlgorithm(other.to_string())),
+        }
+
+        Ok(SignatureParams {
+            key_id: key_id.ok_or(SignatureError::Malformed)?,
+            headers: headers
+                .as_deref()
+                .unwrap_or("date")
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            signature: signature.ok_or(SignatureError::Malformed)?,
+        })
+    }
+}
+
+impl SignatureParams {
+    /// Get the parameters of a request's `Signature` header.
+    pub fn from_request(req: &tide::http::Request) -> Result<Self, SignatureError> {
+        req.header("signature")
+            .ok_or(SignatureError::Missing)?
+            .last()
+            .as_str()
+            .parse()
+    }
+}
+
+/// Verify that a request was signed with `key`, that it's recent, and that its body matches the
+/// signed digest.
+pub fn verify_request(
+    req: &tide::http::Request,
+    body: &[u8],
+    params: &SignatureParams,
+    key: &PublicKey,
+) -> Result<(), SignatureError> {
+    let signed = |name: &str| params.headers.iter().any(|header| header == name);
+    if !signed("(request-target)") {
+        return Err(SignatureError::Unsigned("(request-target)"));
+    }
+    if !signed("date") {
+        return Err(SignatureError::Unsigned("date"));
+    }
+    if !body.is_empty() && !signed("digest") {
+        return Err(SignatureError::Unsigned("digest"));
+    }
+
+    let header = |name: &str| {
+        req.header(name)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .ok_or_else(|| SignatureError::MissingHeader(name.to_string()))
+    };
+
+    let date = parse_http_date(&header("date")?).ok_or(SignatureError::Date)?;
+    let now = OffsetDateTime::now_utc();
+    if date < now - MAX_AGE || date > now + MAX_SKEW {
+        return Err(SignatureError::Date);
+    }
+
+    if signed("digest") && !digest_matches(&header("digest")?, body) {
+        return Err(SignatureError::Digest);
+    }
+
+    let mut lines = Vec::with_capacity(params.headers.len());
+    for name in &params.headers {
+        let value = match name.as_str() {
+            "(request-target)" => request_target(&req.method().to_string(), req.url()),
+            name => header(name)?,
+        };
+        lines.push(format!("{}: {}", name, value));
+    }
+
+    match key.verify(lines.join("\n").as_bytes(), &params.signature) {
+        true => Ok(()),
+        false => Err(SignatureError::Invalid),
+    }
+}
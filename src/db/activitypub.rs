@@ -0,0 +1,312 @@
+use sqlx::{FromRow, PgConnection};
+use tracing::instrument;
+
+pub type DeliveryId = i64;
+
+/// A queued activity that's due to be delivered.
+#[derive(Debug, FromRow)]
+pub struct PendingDelivery {
+    pub id: DeliveryId,
+    pub inbox: String,
+    pub activity: sqlx::types::Json<serde_json::Value>,
+    /// Number of attempts including the current one.
+    pub attempts: i32,
+}
+
+#[async_trait::async_trait]
+pub trait ActivityPubProvider {
+    /// Add a follower, or update the inboxes and Follow activity of an existing one.
+    async fn upsert_activitypub_follower(
+        &mut self,
+        actor: &str,
+        inbox: &str,
+        shared_inbox: Option<&str>,
+        follow_id: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Remove a follower.
+    ///
+    /// Returns whether the actor was following.
+    async fn delete_activitypub_follower(&mut self, actor: &str) -> Result<bool, sqlx::Error>;
+
+    /// Get the number of followers.
+    async fn count_activitypub_followers(&mut self) -> Result<i64, sqlx::Error>;
+
+    /// Queue an activity for delivery to a single inbox.
+    async fn enqueue_activitypub_delivery(
+        &mut self,
+        inbox: &str,
+        activity: &serde_json::Value,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Queue an activity for delivery to every follower, once per shared inbox.
+    ///
+    /// Returns the number of queued deliveries.
+    async fn enqueue_activitypub_deliveries_to_followers(
+        &mut self,
+        activity: &serde_json::Value,
+    ) -> Result<u64, sqlx::Error>;
+
+    /// Claim up to `limit` due deliveries and count the attempt.
+    ///
+    /// Claimed deliveries aren't due again until `lease_seconds` have passed, so that they're
+    /// retried if the process delivering them dies, but not delivered twice concurrently.
+    async fn claim_activitypub_deliveries(
+        &mut self,
+        limit: i64,
+        lease_seconds: f64,
+    ) -> Result<Vec<PendingDelivery>, sqlx::Error>;
+
+    /// Mark a delivery as delivered.
+    async fn mark_activitypub_delivered(
+        &mut self,
+        delivery_id: DeliveryId,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Schedule another attempt at a failed delivery in `delay_seconds`.
+    async fn retry_activitypub_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        delay_seconds: f64,
+        error: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Give up on a delivery.
+    async fn fail_activitypub_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        error: &str,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait::async_trait]
+impl ActivityPubProvider for PgConnection {
+    #[instrument(skip(self))]
+    async fn upsert_activitypub_follower(
+        &mut self,
+        actor: &str,
+        inbox: &str,
+        shared_inbox: Option<&str>,
+        follow_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                INSERT INTO activitypub_followers (
+                    actor,
+                    inbox,
+                    shared_inbox,
+                    follow_id
+                )
+                VALUES
+                    ($1, $2, $3, $4)
+                ON CONFLICT (actor) DO UPDATE SET
+                    inbox = EXCLUDED.inbox,
+                    shared_inbox = EXCLUDED.shared_inbox,
+                    follow_id = EXCLUDED.follow_id
+            "#,
+            actor,
+            inbox,
+            shared_inbox,
+            follow_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_activitypub_follower(&mut self, actor: &str) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM
+                    activitypub_followers
+                WHERE
+                    actor = $1
+            "#,
+            actor,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    async fn count_activitypub_followers(&mut self) -> Result<i64, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    COUNT(*) AS "count!"
+                FROM
+                    activitypub_followers
+            "#,
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(res.count)
+    }
+
+    #[instrument(skip(self, activity))]
+    async fn enqueue_activitypub_delivery(
+        &mut self,
+        inbox: &str,
+        activity: &serde_json::Value,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                INSERT INTO activitypub_deliveries (
+                    inbox,
+                    activity
+                )
+                VALUES
+                    ($1, $2)
+            "#,
+            inbox,
+            sqlx::types::Json(activity) as _,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, activity))]
+    async fn enqueue_activitypub_deliveries_to_followers(
+        &mut self,
+        activity: &serde_json::Value,
+    ) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO activitypub_deliveries (
+                    inbox,
+                    activity
+                )
+                SELECT DISTINCT
+                    COALESCE(shared_inbox, inbox), $1::JSONB
+                FROM
+                    activitypub_followers
+            "#,
+            sqlx::types::Json(activity) as _,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_activitypub_deliveries(
+        &mut self,
+        limit: i64,
+        lease_seconds: f64,
+    ) -> Result<Vec<PendingDelivery>, sqlx::Error> {
+        // Same claiming as for webhook deliveries, see `claim_webhook_deliveries`.
+        let query = r#"
+            UPDATE
+                activitypub_deliveries delivery
+            SET
+                attempts = delivery.attempts + 1,
+                next_attempt_at = NOW() + MAKE_INTERVAL(secs => $2)
+            WHERE
+                delivery.id IN (
+                    SELECT
+                        id
+                    FROM
+                        activitypub_deliveries
+                    WHERE
+                        delivered_at IS NULL
+                        AND failed_at IS NULL
+                        AND next_attempt_at <= NOW()
+                    ORDER BY
+                        next_attempt_at ASC
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING
+                delivery.id, delivery.inbox, delivery.activity, delivery.attempts
+        "#;
+
+        sqlx::query_as(query)
+            .bind(limit)
+            .bind(lease_seconds)
+            .fetch_all(self)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_activitypub_delivered(
+        &mut self,
+        delivery_id: DeliveryId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    activitypub_deliveries
+                SET
+                    delivered_at = NOW(),
+                    last_error = NULL
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn retry_activitypub_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        delay_seconds: f64,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    activitypub_deliveries
+                SET
+                    next_attempt_at = NOW() + MAKE_INTERVAL(secs => $2),
+                    last_error = $3
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+            delay_seconds,
+            error,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn fail_activitypub_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    activitypub_deliveries
+                SET
+                    failed_at = NOW(),
+                    last_error = $2
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+            error,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use tracing::instrument;
+
+use crate::db::photos::PhotoId;
+use crate::db::W3C_DATETIME_FORMAT;
+use crate::models;
+
+pub type CommentId = i32;
+
+/// Where a comment is in moderation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentStatus {
+    /// Waiting to be approved, not shown to visitors.
+    Pending,
+    Approved,
+}
+
+#[derive(Debug, FromRow)]
+pub struct Comment {
+    pub id: CommentId,
+    pub photo_id: PhotoId,
+    pub author: String,
+    pub body: String,
+    pub approved: bool,
+    /// When the comment was posted, as a W3C datetime in UTC.
+    pub created_at: String,
+}
+
+#[async_trait::async_trait]
+pub trait CommentProvider {
+    /// Get comments in a moderation state, oldest first.
+    ///
+    /// * `photo_id`: If `Some`, only get comments on this photo.
+    async fn get_comments(
+        &mut self,
+        photo_id: Option<PhotoId>,
+        status: CommentStatus,
+    ) -> Result<Vec<models::comments::Comment>, sqlx::Error>;
+
+    /// Store a new comment, pending approval.
+    async fn insert_comment(
+        &mut self,
+        photo_id: PhotoId,
+        author: &str,
+        body: &str,
+    ) -> Result<CommentId, sqlx::Error>;
+
+    /// Approve a comment, or move it back to pending.
+    ///
+    /// Returns whether the comment exists.
+    async fn set_comment_approved(
+        &mut self,
+        comment_id: CommentId,
+        approved: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Delete a comment, e.g. to reject it.
+    ///
+    /// Returns whether the comment existed.
+    async fn delete_comment(&mut self, comment_id: CommentId) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait::async_trait]
+impl CommentProvider for PgConnection {
+    #[instrument(skip(self))]
+    async fn get_comments(
+        &mut self,
+        photo_id: Option<PhotoId>,
+        status: CommentStatus,
+    ) -> Result<Vec<models::comments::Comment>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                id,
+                photo_id,
+                author,
+                body,
+                approved,
+                TO_CHAR(created_at AT TIME ZONE 'UTC', {}) AS created_at
+            FROM
+                comments
+            WHERE
+                approved = $1
+        "#,
+            W3C_DATETIME_FORMAT
+        );
+
+        if photo_id.is_some() {
+            query.push_str(
+                r#"
+                AND photo_id = $2
+            "#,
+            );
+        }
+
+        query.push_str(
+            r#"
+            ORDER BY
+                created_at ASC,
+                id ASC
+        "#,
+        );
+
+        let mut query = sqlx::query_as(&query).bind(status == CommentStatus::Approved);
+        if let Some(photo_id) = photo_id {
+            query = query.bind(photo_id);
+        }
+
+        let res: Vec<Comment> = query.fetch_all(self).await?;
+
+        Ok(res
+            .into_iter()
+            .map(models::comments::Comment::from)
+            .collect())
+    }
+
+    #[instrument(skip(self, body))]
+    async fn insert_comment(
+        &mut self,
+        photo_id: PhotoId,
+        author: &str,
+        body: &str,
+    ) -> Result<CommentId, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO comments (
+                    photo_id,
+                    author,
+                    body
+                )
+                VALUES
+                    ($1, $2, $3)
+                RETURNING
+                    id
+            "#,
+            photo_id,
+            author,
+            body,
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(res.id)
+    }
+
+    #[instrument(skip(self))]
+    async fn set_comment_approved(
+        &mut self,
+        comment_id: CommentId,
+        approved: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE
+                    comments
+                SET
+                    approved = $2
+                WHERE
+                    id = $1
+            "#,
+            comment_id,
+            approved,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_comment(&mut self, comment_id: CommentId) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM
+                    comments
+                WHERE
+                    id = $1
+            "#,
+            comment_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
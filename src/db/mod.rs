@@ -3,8 +3,15 @@ use std::time::Duration;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use thiserror::Error;
 
+pub mod activitypub;
+pub mod comments;
 pub mod photos;
 pub mod secret_keys;
+pub mod stats;
+pub mod webhooks;
+
+/// Formats a `TIMESTAMPTZ` column as a W3C datetime in UTC.
+pub(crate) const W3C_DATETIME_FORMAT: &str = r#"'YYYY-MM-DD"T"HH24:MI:SS"Z"'"#;
 
 #[derive(Error, Debug)]
 pub enum Error {
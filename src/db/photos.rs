@@ -1,21 +1,63 @@
+use std::collections::BTreeMap;
 use std::fmt::Write as _;
 
-use serde::Serialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sqlx::{Connection, FromRow, PgConnection};
+use time::OffsetDateTime;
 use tracing::{info, instrument};
 
-use rusty_peanuts_api_structs::Source;
+use rusty_peanuts_api_structs::{Color, PerceptualHash, Source};
 
-use crate::db::Error;
+use crate::db::{Error, W3C_DATETIME_FORMAT};
 use crate::models;
 
 pub type PhotoId = i32;
 
-#[derive(Debug, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Page {
     Latest,
-    Before(u32),
-    After(u32),
+    Before(Anchor),
+    After(Anchor),
+    Oldest,
+}
+
+/// Where a photo sorts, so that a page can start right next to it even if the photo has since
+/// been deleted, unpublished or had its sort key changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Anchor {
+    #[serde(rename = "i")]
+    pub id: PhotoId,
+    /// Value the photo is sorted by: its sort key, or when it was taken in microseconds since the
+    /// Unix epoch. `None` when sorting by ID, or when the photo has no capture time.
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<i64>,
+}
+
+impl Anchor {
+    /// Anchor for where `photo` sorts by `sort`.
+    pub fn new(photo: &models::photos::Photo, sort: SortMode) -> Self {
+        let key = match sort {
+            SortMode::Id => None,
+            SortMode::TakenTimestamp => photo
+                .taken_at
+                .map(|taken_at| (taken_at.unix_timestamp_nanos() / 1_000) as i64),
+            SortMode::SortKey => Some(photo.sort_key.unwrap_or(photo.id).into()),
+        };
+
+        Anchor { id: photo.id, key }
+    }
+
+    /// Anchor for the photo with this ID when sorting by ID.
+    pub fn id(id: PhotoId) -> Self {
+        Anchor { id, key: None }
+    }
+
+    fn taken_at(self) -> Option<OffsetDateTime> {
+        self.key.and_then(|micros| {
+            OffsetDateTime::from_unix_timestamp_nanos(i128::from(micros) * 1_000).ok()
+        })
+    }
 }
 
 impl Page {
@@ -24,17 +66,105 @@ impl Page {
             Page::Latest => "DESC",
             Page::Before(_) => "DESC",
             Page::After(_) => "ASC",
+            Page::Oldest => "ASC",
         }
     }
 }
 
-impl From<Option<i32>> for Page {
-    fn from(page_id: Option<i32>) -> Self {
-        match page_id {
-            None => Page::Latest,
-            Some(photo_id) if photo_id >= 0 => Page::Before(photo_id as u32),
-            Some(photo_id) if photo_id < 0 => Page::After((-photo_id - 1) as u32),
-            Some(_) => unreachable!("i32 cannot be neither >=0 nor <0 at the same time"),
+/// What photos are sorted by, with the first photo being the newest or highest.
+///
+/// Photos that sort the same are ordered by ID, so that every photo has a unique position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    /// Upload order.
+    Id,
+    /// When the photo was taken, with photos without a timestamp, or with one that can't be
+    /// parsed, sorted as the oldest.
+    TakenTimestamp,
+    /// Manually set sort key, with photos without one sorted as if their ID was their sort key.
+    SortKey,
+}
+
+impl SortMode {
+    /// Expression that photos in `table` are sorted by before being tie-broken by ID.
+    fn key(self, table: &str) -> String {
+        match self {
+            SortMode::Id => format!("{}.id", table),
+            SortMode::TakenTimestamp => format!("COALESCE({}.taken_at, '-infinity')", table),
+            SortMode::SortKey => format!("COALESCE({}.sort_key, {}.id)", table, table),
+        }
+    }
+
+    /// Condition for `photo` sorting before (`<`) or after (`>`) `anchor`, whose values get
+    /// bound starting at `$bind_count`.
+    fn position_condition(
+        self,
+        operator: &str,
+        anchor: Anchor,
+        bind_count: &mut usize,
+        bind_values: &mut Vec<BindValue<'_>>,
+    ) -> String {
+        let condition = match self {
+            SortMode::Id => format!("photo.id {} ${}", operator, bind_count),
+            SortMode::TakenTimestamp => format!(
+                "({}, photo.id) {} (COALESCE(${}::timestamptz, '-infinity'), ${})",
+                self.key("photo"),
+                operator,
+                *bind_count,
+                *bind_count + 1,
+            ),
+            SortMode::SortKey => format!(
+                "({}, photo.id) {} (${}, ${})",
+                self.key("photo"),
+                operator,
+                *bind_count,
+                *bind_count + 1,
+            ),
+        };
+
+        match self {
+            SortMode::Id => {},
+            SortMode::TakenTimestamp => {
+                *bind_count += 1;
+                bind_values.push(BindValue::Timestamp(anchor.taken_at()));
+            },
+            SortMode::SortKey => {
+                *bind_count += 1;
+                bind_values.push(BindValue::I64(anchor.key.unwrap_or(anchor.id.into())));
+            },
+        }
+        *bind_count += 1;
+        bind_values.push(BindValue::I64(anchor.id.into()));
+
+        condition
+    }
+
+    fn order_by(self, direction: &str) -> String {
+        match self {
+            SortMode::Id => format!("photo.id {}", direction),
+            _ => format!(
+                "{} {}, photo.id {}",
+                self.key("photo"),
+                direction,
+                direction
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for SortMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortMode::Id),
+            "taken_timestamp" => Ok(SortMode::TakenTimestamp),
+            "sort_key" => Ok(SortMode::SortKey),
+            _ => Err(format!(
+                "unknown sort mode {}, expected id, taken_timestamp or sort_key",
+                s
+            )),
         }
     }
 }
@@ -49,6 +179,14 @@ pub enum Published {
 enum BindValue<'a> {
     I64(i64),
     ArrayString(&'a [String]),
+    Timestamp(Option<OffsetDateTime>),
+}
+
+fn pack_palette(palette: &[Color]) -> Vec<i32> {
+    palette
+        .iter()
+        .map(|&color| u32::from(color) as i32)
+        .collect()
 }
 
 #[derive(Debug, FromRow)]
@@ -56,11 +194,32 @@ pub struct Photo {
     pub id: PhotoId,
     pub file_stem: String,
     pub title: Option<String>,
+    pub titles: sqlx::types::Json<BTreeMap<String, String>>,
+    pub caption: Option<String>,
+    pub alt_text: Option<String>,
     pub taken_timestamp: Option<String>,
+    pub taken_at: Option<OffsetDateTime>,
     pub height_offset: i32,
     pub tags: Vec<String>,
     pub sources: sqlx::types::Json<Vec<Source>>,
     pub published: bool,
+    pub blurhash: Option<String>,
+    pub lqip: Option<String>,
+    /// Colors packed as `0xRRGGBB`.
+    pub palette: Vec<i32>,
+    pub perceptual_hash: Option<i64>,
+    pub sort_key: Option<i32>,
+}
+
+/// What the sitemap needs to know about a photo.
+#[derive(Debug, FromRow)]
+pub struct SitemapPhoto {
+    pub id: PhotoId,
+    pub title: Option<String>,
+    /// URL of the largest JPEG source.
+    pub image_url: Option<String>,
+    /// When the photo or its sources last changed, as a W3C datetime in UTC.
+    pub updated_at: String,
 }
 
 #[async_trait::async_trait]
@@ -69,35 +228,41 @@ pub trait PhotoProvider {
     ///
     /// * `limit`: The number of photos to get.
     /// * `page`: Which photo to start the page on.
+    /// * `sort`: What to sort the photos by.
     /// * `tagged`: If `Some`, only get photos with these tags.
     /// * `published`: Whether to get all photos, or only published ones.
     async fn get_photo_page(
         &mut self,
         limit: i64,
         page: Page,
+        sort: SortMode,
         tagged: &Option<Vec<String>>,
         published: Published,
     ) -> Result<Vec<models::photos::Photo>, Error>;
 
-    /// Get the pagination IDs for a list of photos.
+    /// Get the pagination anchors for a list of photos.
     ///
-    /// Returns the IDs of the photo that comes after the ID of the first photo, and before the ID
-    /// of the last photo, in the list of photos.
+    /// Returns the anchors of the first and the last photo in the list, if there are photos
+    /// sorted before the first one and after the last one respectively.
     ///
     /// * `photos`: A list of photos to get the pagination IDs for.
+    /// * `sort`: What the photos are sorted by.
     /// * `tagged`: If `Some`, only take inte account photos with these tags.
     /// * `published`: Whether to take into account all photos, or only published ones.
     async fn get_photo_pagination_ids(
         &mut self,
         photos: &[models::photos::Photo],
+        sort: SortMode,
         tagged: &Option<Vec<String>>,
         published: Published,
-    ) -> Result<(Option<i32>, Option<i32>), Error>;
+    ) -> Result<(Option<Anchor>, Option<Anchor>), Error>;
 
-    /// Get a single photo by ID.
+    /// Get a single photo by ID, along with the IDs of the photos sorted right before and after
+    /// it by `sort`.
     async fn get_photo_by_id(
         &mut self,
         photo_id: PhotoId,
+        sort: SortMode,
         published: Published,
     ) -> Result<Option<(models::photos::Photo, Option<PhotoId>, Option<PhotoId>)>, sqlx::Error>;
 
@@ -122,6 +287,68 @@ pub trait PhotoProvider {
     /// * `published`: Whether to take into account all photos, or only published ones.
     async fn get_all_photo_ids(&mut self, published: Published) -> Result<Vec<i32>, sqlx::Error>;
 
+    /// Get everything sitemap entries of all photos need, ordered by ID.
+    async fn get_sitemap_photos(
+        &mut self,
+        published: Published,
+    ) -> Result<Vec<SitemapPhoto>, sqlx::Error>;
+
+    /// Get all tags and when any photo with that tag was last updated, as W3C datetimes in UTC.
+    async fn get_tags_last_updated(
+        &mut self,
+        published: Published,
+    ) -> Result<Vec<(String, String)>, sqlx::Error>;
+
+    /// Get the IDs of up to `count` random photos.
+    ///
+    /// Rather than sorting the whole table randomly this picks random points in the ID range and
+    /// takes the first matching photo at or after each of them, so photos following large gaps
+    /// in the IDs are somewhat more likely to be picked.
+    ///
+    /// * `count`: The maximum number of photo IDs to get.
+    /// * `tagged`: If `Some`, only get photos with these tags.
+    /// * `published`: Whether to get all photos, or only published ones.
+    async fn get_random_photo_ids(
+        &mut self,
+        count: usize,
+        tagged: &Option<Vec<String>>,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, Error>;
+
+    /// Get multiple photos by ID, in the order of the given IDs.
+    async fn get_photos_by_ids(
+        &mut self,
+        photo_ids: &[PhotoId],
+        published: Published,
+    ) -> Result<Vec<models::photos::Photo>, sqlx::Error>;
+
+    /// Get the IDs of photos with a palette color near the given color, closest first.
+    ///
+    /// * `max_distance`: The maximum Euclidean distance in RGB space between the colors.
+    /// * `limit`: The maximum number of photo IDs to get.
+    /// * `published`: Whether to get all photos, or only published ones.
+    async fn get_photo_ids_near_color(
+        &mut self,
+        color: Color,
+        max_distance: u32,
+        limit: i64,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, sqlx::Error>;
+
+    /// Get the IDs of photos with a perceptual hash similar to the given one, along with how many
+    /// bits their hashes differ by, most similar first.
+    ///
+    /// * `max_distance`: The maximum number of bits the hashes can differ by.
+    /// * `exclude`: A photo to leave out, usually the one the hash is from.
+    /// * `published`: Whether to get all photos, or only published ones.
+    async fn get_similar_photo_ids(
+        &mut self,
+        perceptual_hash: PerceptualHash,
+        max_distance: u32,
+        exclude: Option<PhotoId>,
+        published: Published,
+    ) -> Result<Vec<(PhotoId, u32)>, sqlx::Error>;
+
     /// Insert a new photo.
     async fn insert_photo(&mut self, photo: &models::photos::Photo)
         -> Result<PhotoId, sqlx::Error>;
@@ -146,6 +373,25 @@ pub trait PhotoProvider {
         photo_id: PhotoId,
         height_offset: u8,
     ) -> Result<(), sqlx::Error>;
+
+    /// Set the manual sort key of a photo by ID, or clear it.
+    async fn set_photo_sort_key(
+        &mut self,
+        photo_id: PhotoId,
+        sort_key: Option<i32>,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Reserve a file stem for an upload until it's released, returning whether it was free.
+    ///
+    /// Claims that were never released, e.g. because the server stopped while processing the
+    /// upload, expire after a day.
+    async fn claim_photo_file_stem(&mut self, file_stem: &str) -> Result<bool, sqlx::Error>;
+
+    /// Whether a file stem is reserved by an upload.
+    async fn is_photo_file_stem_claimed(&mut self, file_stem: &str) -> Result<bool, sqlx::Error>;
+
+    /// Release a file stem reserved with `claim_photo_file_stem`.
+    async fn release_photo_file_stem(&mut self, file_stem: &str) -> Result<(), sqlx::Error>;
 }
 
 #[async_trait::async_trait]
@@ -155,6 +401,7 @@ impl PhotoProvider for PgConnection {
         &mut self,
         limit: i64,
         page: Page,
+        sort: SortMode,
         tagged: &Option<Vec<String>>,
         published: Published,
     ) -> Result<Vec<models::photos::Photo>, Error> {
@@ -162,7 +409,8 @@ impl PhotoProvider for PgConnection {
         let mut bind_values = Vec::new();
         let mut query = r#"
             SELECT
-                id, title, file_stem, taken_timestamp, height_offset, tags, published,
+                id, title, file_stem, taken_timestamp, taken_at, height_offset, tags, published,
+                titles, caption, alt_text, blurhash, lqip, palette, perceptual_hash, sort_key,
                 JSONB_AGG(TO_JSONB(source)) AS "sources"
             FROM
                 photos photo
@@ -174,33 +422,33 @@ impl PhotoProvider for PgConnection {
         .to_string();
 
         match page {
-            Page::Before(photo_id) => {
+            Page::Before(anchor) => {
+                let condition =
+                    sort.position_condition("<", anchor, &mut bind_count, &mut bind_values);
                 write!(
                     query,
                     r#"
                             WHERE
-                                id < ${}
+                                {}
                     "#,
-                    bind_count,
+                    condition,
                 )?;
-                bind_count += 1;
-                bind_values.push(BindValue::I64(photo_id.into()));
             },
 
-            Page::After(photo_id) => {
+            Page::After(anchor) => {
+                let condition =
+                    sort.position_condition(">", anchor, &mut bind_count, &mut bind_values);
                 write!(
                     query,
                     r#"
                             WHERE
-                                id > ${}
+                                {}
                     "#,
-                    bind_count,
+                    condition,
                 )?;
-                bind_count += 1;
-                bind_values.push(BindValue::I64(photo_id.into()));
             },
 
-            Page::Latest => {
+            Page::Latest | Page::Oldest => {
                 query.push_str(
                     r#"
                         WHERE
@@ -236,10 +484,10 @@ impl PhotoProvider for PgConnection {
                     GROUP BY
                         id, title, file_stem, taken_timestamp, height_offset, tags, published
                     ORDER BY
-                        id {}
+                        {}
                     LIMIT ${}
             "#,
-            page.order_direction(),
+            sort.order_by(page.order_direction()),
             bind_count,
         )?;
         // Necessary if any more bind variables are added in this function, but leaving it
@@ -254,12 +502,16 @@ impl PhotoProvider for PgConnection {
             query = match value {
                 BindValue::I64(v) => query.bind(v),
                 BindValue::ArrayString(v) => query.bind(v),
+                BindValue::Timestamp(v) => query.bind(v),
             };
         }
         let res: Vec<Photo> = query.fetch_all(self).await?;
 
+        // Pages after a photo are fetched in ascending order to get the photos closest to it.
         let mut photos: Vec<_> = res.into_iter().map(models::photos::Photo::from).collect();
-        photos.sort_by(|a, b| b.id.cmp(&a.id));
+        if page.order_direction() == "ASC" {
+            photos.reverse();
+        }
         Ok(photos)
     }
 
@@ -267,19 +519,21 @@ impl PhotoProvider for PgConnection {
     async fn get_photo_pagination_ids(
         &mut self,
         photos: &[models::photos::Photo],
+        sort: SortMode,
         tagged: &Option<Vec<String>>,
         published: Published,
-    ) -> Result<(Option<i32>, Option<i32>), Error> {
+    ) -> Result<(Option<Anchor>, Option<Anchor>), Error> {
         let previous = match photos.first() {
             Some(photo) => {
+                let anchor = Anchor::new(photo, sort);
                 if self
-                    .get_photo_page(1, Page::After(photo.id as u32), tagged, published)
+                    .get_photo_page(1, Page::After(anchor), sort, tagged, published)
                     .await?
                     .is_empty()
                 {
                     None
                 } else {
-                    Some(photo.id)
+                    Some(anchor)
                 }
             },
             None => None,
@@ -287,14 +541,15 @@ impl PhotoProvider for PgConnection {
 
         let next = match photos.last() {
             Some(photo) => {
+                let anchor = Anchor::new(photo, sort);
                 if self
-                    .get_photo_page(1, Page::Before(photo.id as u32), tagged, published)
+                    .get_photo_page(1, Page::Before(anchor), sort, tagged, published)
                     .await?
                     .is_empty()
                 {
                     None
                 } else {
-                    Some(photo.id)
+                    Some(anchor)
                 }
             },
             None => None,
@@ -307,12 +562,14 @@ impl PhotoProvider for PgConnection {
     async fn get_photo_by_id(
         &mut self,
         photo_id: PhotoId,
+        sort: SortMode,
         published: Published,
     ) -> Result<Option<(models::photos::Photo, Option<PhotoId>, Option<PhotoId>)>, sqlx::Error>
     {
         let mut query = r#"
             SELECT
-                id, title, file_stem, taken_timestamp, height_offset, tags, published,
+                id, title, file_stem, taken_timestamp, taken_at, height_offset, tags, published,
+                titles, caption, alt_text, blurhash, lqip, palette, perceptual_hash, sort_key,
                 JSONB_AGG(TO_JSONB(source)) AS "sources"
             FROM
                 photos photo
@@ -346,72 +603,96 @@ impl PhotoProvider for PgConnection {
             Ok(None) => return Ok(None),
             Err(err) => return Err(err),
         };
+        let photo = models::photos::Photo::from(photo);
+        let anchor = Anchor::new(&photo, sort);
 
         let newer_id = {
-            let mut query = r#"
-                SELECT
-                    id
-                FROM
-                    photos photo
-                WHERE
-                    id > $1
-            "#
-            .to_string();
+            let mut bind_count = 1;
+            let mut bind_values = Vec::new();
+            let mut query = format!(
+                r#"
+                    SELECT
+                        id
+                    FROM
+                        photos photo
+                    WHERE
+                        {}
+                "#,
+                sort.position_condition(">", anchor, &mut bind_count, &mut bind_values),
+            );
 
             if published == Published::OnlyPublished {
                 query.push_str("    AND photo.published = 't'\n")
             }
 
-            query.push_str(
+            query.push_str(&format!(
                 r#"
-                ORDER BY id ASC
-                LIMIT 1
-            "#,
-            );
+                    ORDER BY
+                        {}
+                    LIMIT 1
+                "#,
+                sort.order_by("ASC"),
+            ));
+
+            let mut query = sqlx::query_as::<_, (PhotoId,)>(&query);
+            for value in bind_values {
+                query = match value {
+                    BindValue::I64(v) => query.bind(v),
+                    BindValue::ArrayString(v) => query.bind(v),
+                    BindValue::Timestamp(v) => query.bind(v),
+                };
+            }
 
-            match sqlx::query_as(&query)
-                .bind(photo_id)
-                .fetch_one(&mut *self)
-                .await
-            {
+            match query.fetch_one(&mut *self).await {
                 Ok((option_photo_id,)) => Some(option_photo_id),
                 Err(_) => None,
             }
         };
 
         let older_id = {
-            let mut query = r#"
-                SELECT
-                    id
-                FROM
-                    photos photo
-                WHERE
-                    id < $1
-            "#
-            .to_string();
+            let mut bind_count = 1;
+            let mut bind_values = Vec::new();
+            let mut query = format!(
+                r#"
+                    SELECT
+                        id
+                    FROM
+                        photos photo
+                    WHERE
+                        {}
+                "#,
+                sort.position_condition("<", anchor, &mut bind_count, &mut bind_values),
+            );
 
             if published == Published::OnlyPublished {
                 query.push_str("    AND photo.published = 't'\n")
             }
 
-            query.push_str(
+            query.push_str(&format!(
                 r#"
-                ORDER BY id DESC
-                LIMIT 1
-            "#,
-            );
+                    ORDER BY
+                        {}
+                    LIMIT 1
+                "#,
+                sort.order_by("DESC"),
+            ));
+
+            let mut query = sqlx::query_as::<_, (PhotoId,)>(&query);
+            for value in bind_values {
+                query = match value {
+                    BindValue::I64(v) => query.bind(v),
+                    BindValue::ArrayString(v) => query.bind(v),
+                    BindValue::Timestamp(v) => query.bind(v),
+                };
+            }
 
-            match sqlx::query_as(&query)
-                .bind(photo_id)
-                .fetch_one(&mut *self)
-                .await
-            {
+            match query.fetch_one(&mut *self).await {
                 Ok((option_photo_id,)) => Some(option_photo_id),
                 Err(_) => None,
             }
         };
 
-        Ok(Some((photo.into(), newer_id, older_id)))
+        Ok(Some((photo, newer_id, older_id)))
     }
 
     #[instrument(skip(self))]
@@ -422,7 +703,8 @@ impl PhotoProvider for PgConnection {
     ) -> Result<Option<models::photos::Photo>, sqlx::Error> {
         let mut query = r#"
             SELECT
-                id, title, file_stem, taken_timestamp, height_offset, tags, published,
+                id, title, file_stem, taken_timestamp, taken_at, height_offset, tags, published,
+                titles, caption, alt_text, blurhash, lqip, palette, perceptual_hash, sort_key,
                 JSONB_AGG(TO_JSONB(source)) AS "sources"
             FROM
                 photos photo
@@ -509,6 +791,7 @@ impl PhotoProvider for PgConnection {
             query = match value {
                 BindValue::I64(v) => query.bind(v),
                 BindValue::ArrayString(v) => query.bind(v),
+                BindValue::Timestamp(v) => query.bind(v),
             };
         }
 
@@ -548,6 +831,317 @@ impl PhotoProvider for PgConnection {
         Ok(ids.into_iter().map(|(id,)| id).collect())
     }
 
+    #[instrument(skip(self))]
+    async fn get_sitemap_photos(
+        &mut self,
+        published: Published,
+    ) -> Result<Vec<SitemapPhoto>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                photo.id,
+                photo.title,
+                (
+                    SELECT
+                        source.url
+                    FROM
+                        sources source
+                    WHERE
+                        source.photo_id = photo.id
+                        AND source.mime_type = 'image/jpeg'
+                    ORDER BY
+                        source.width DESC
+                    LIMIT 1
+                ) AS image_url,
+                TO_CHAR(photo.updated_at AT TIME ZONE 'UTC', {}) AS updated_at
+            FROM
+                photos photo
+        "#,
+            W3C_DATETIME_FORMAT
+        );
+
+        if published == Published::OnlyPublished {
+            query.push_str(
+                r#"
+                WHERE
+                    photo.published = 't'
+            "#,
+            );
+        }
+
+        query.push_str(
+            r#"
+            ORDER BY
+                photo.id ASC
+        "#,
+        );
+
+        sqlx::query_as(&query).fetch_all(self).await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_tags_last_updated(
+        &mut self,
+        published: Published,
+    ) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                tag,
+                TO_CHAR(MAX(photo.updated_at) AT TIME ZONE 'UTC', {})
+            FROM
+                photos photo,
+                UNNEST(photo.tags) AS tag
+        "#,
+            W3C_DATETIME_FORMAT
+        );
+
+        if published == Published::OnlyPublished {
+            query.push_str(
+                r#"
+                WHERE
+                    photo.published = 't'
+            "#,
+            );
+        }
+
+        query.push_str(
+            r#"
+            GROUP BY
+                tag
+            ORDER BY
+                tag
+        "#,
+        );
+
+        sqlx::query_as(&query).fetch_all(self).await
+    }
+
+    #[instrument(skip(self))]
+    async fn get_random_photo_ids(
+        &mut self,
+        count: usize,
+        tagged: &Option<Vec<String>>,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, Error> {
+        // Builds the filtering part of the queries, with the tags bound to `$tags_bind`.
+        let filter = |tags_bind: usize| -> Result<String, Error> {
+            let mut filter = String::new();
+            if tagged.is_some() {
+                writeln!(filter, "    AND photo.tags @> ${}::varchar[]", tags_bind)?;
+            }
+            if published == Published::OnlyPublished {
+                filter.push_str("    AND photo.published = 't'\n");
+            }
+            Ok(filter)
+        };
+
+        let mut range_query = r#"
+            SELECT
+                MIN(id), MAX(id)
+            FROM
+                photos photo
+            WHERE
+                true
+        "#
+        .to_string();
+        range_query.push_str(&filter(1)?);
+
+        let mut query = sqlx::query_as(&range_query);
+        if let Some(tags) = tagged {
+            query = query.bind(&tags[..]);
+        }
+        let (min_id, max_id): (Option<i32>, Option<i32>) = query.fetch_one(&mut *self).await?;
+        let (min_id, max_id) = match (min_id, max_id) {
+            (Some(min_id), Some(max_id)) => (min_id, max_id),
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut pick_query = r#"
+            SELECT
+                id
+            FROM
+                photos photo
+            WHERE
+                id >= $1
+        "#
+        .to_string();
+        pick_query.push_str(&filter(2)?);
+        pick_query.push_str(
+            r#"
+            ORDER BY
+                id ASC
+            LIMIT 1
+        "#,
+        );
+
+        // Give up after a few misses, since there might be fewer matching photos than requested.
+        let mut photo_ids = Vec::with_capacity(count);
+        for _ in 0..count * 3 {
+            if photo_ids.len() >= count {
+                break;
+            }
+
+            let point: i32 = rand::thread_rng().gen_range(min_id..=max_id);
+            let mut query = sqlx::query_as(&pick_query).bind(point);
+            if let Some(tags) = tagged {
+                query = query.bind(&tags[..]);
+            }
+            let (photo_id,): (PhotoId,) = query.fetch_one(&mut *self).await?;
+
+            if !photo_ids.contains(&photo_id) {
+                photo_ids.push(photo_id);
+            }
+        }
+
+        Ok(photo_ids)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_photos_by_ids(
+        &mut self,
+        photo_ids: &[PhotoId],
+        published: Published,
+    ) -> Result<Vec<models::photos::Photo>, sqlx::Error> {
+        let mut query = r#"
+            SELECT
+                id, title, file_stem, taken_timestamp, taken_at, height_offset, tags, published,
+                titles, caption, alt_text, blurhash, lqip, palette, perceptual_hash, sort_key,
+                JSONB_AGG(TO_JSONB(source)) AS "sources"
+            FROM
+                photos photo
+            LEFT JOIN
+                sources source
+            ON
+                source.photo_id = photo.id
+            WHERE
+                id = ANY($1)
+        "#
+        .to_string();
+
+        if published == Published::OnlyPublished {
+            query.push_str("    AND photo.published = 't'\n")
+        }
+
+        query.push_str(
+            r#"
+            GROUP BY
+                id, title, file_stem
+        "#,
+        );
+
+        let res: Vec<Photo> = sqlx::query_as(&query)
+            .bind(photo_ids)
+            .fetch_all(self)
+            .await?;
+
+        let mut photos: Vec<_> = res.into_iter().map(models::photos::Photo::from).collect();
+        photos.sort_by_key(|photo| photo_ids.iter().position(|&id| id == photo.id));
+        Ok(photos)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_photo_ids_near_color(
+        &mut self,
+        color: Color,
+        max_distance: u32,
+        limit: i64,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, sqlx::Error> {
+        let mut query = r#"
+            SELECT
+                id
+            FROM
+                photos photo,
+                LATERAL (
+                    SELECT
+                        MIN(
+                            POWER(((color.rgb >> 16) & 255) - $1, 2)
+                            + POWER(((color.rgb >> 8) & 255) - $2, 2)
+                            + POWER((color.rgb & 255) - $3, 2)
+                        ) AS distance
+                    FROM
+                        UNNEST(photo.palette) AS color(rgb)
+                ) nearest
+            WHERE
+                nearest.distance <= $4
+        "#
+        .to_string();
+
+        if published == Published::OnlyPublished {
+            query.push_str("    AND photo.published = 't'\n")
+        }
+
+        query.push_str(
+            r#"
+            ORDER BY
+                nearest.distance ASC, id DESC
+            LIMIT $5
+        "#,
+        );
+
+        let ids: Vec<(PhotoId,)> = sqlx::query_as(&query)
+            .bind(i32::from(color.red))
+            .bind(i32::from(color.green))
+            .bind(i32::from(color.blue))
+            .bind(f64::from(max_distance).powi(2))
+            .bind(limit)
+            .fetch_all(self)
+            .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_similar_photo_ids(
+        &mut self,
+        perceptual_hash: PerceptualHash,
+        max_distance: u32,
+        exclude: Option<PhotoId>,
+        published: Published,
+    ) -> Result<Vec<(PhotoId, u32)>, sqlx::Error> {
+        // Counting the set bits of the XOR through the text representation works on all
+        // PostgreSQL versions, unlike BIT_COUNT.
+        let mut query = r#"
+            SELECT
+                id, distance
+            FROM
+                photos photo,
+                LATERAL (
+                    SELECT
+                        LENGTH(REPLACE(((photo.perceptual_hash # $1)::BIT(64))::TEXT, '0', ''))
+                            AS distance
+                ) hamming
+            WHERE
+                hamming.distance <= $2
+                AND id IS DISTINCT FROM $3
+        "#
+        .to_string();
+
+        if published == Published::OnlyPublished {
+            query.push_str("    AND photo.published = 't'\n")
+        }
+
+        query.push_str(
+            r#"
+            ORDER BY
+                hamming.distance ASC, id DESC
+        "#,
+        );
+
+        let ids: Vec<(PhotoId, i32)> = sqlx::query_as(&query)
+            .bind(perceptual_hash.0 as i64)
+            .bind(max_distance as i32)
+            .bind(exclude)
+            .fetch_all(self)
+            .await?;
+
+        Ok(ids
+            .into_iter()
+            .map(|(id, distance)| (id, distance as u32))
+            .collect())
+    }
+
     #[instrument(skip(self))]
     async fn insert_photo(
         &mut self,
@@ -558,9 +1152,12 @@ impl PhotoProvider for PgConnection {
         let res = sqlx::query!(
             r#"
                 INSERT INTO photos
-                    (title, file_stem, taken_timestamp, height_offset, tags, published)
+                    (
+                        title, file_stem, taken_timestamp, height_offset, tags, published,
+                        blurhash, lqip, palette, perceptual_hash, titles, caption, alt_text
+                    )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
                 RETURNING
                     id
             "#,
@@ -570,6 +1167,13 @@ impl PhotoProvider for PgConnection {
             photo.height_offset as i32,
             &photo.tags,
             photo.published,
+            photo.blurhash,
+            photo.lqip,
+            &pack_palette(&photo.palette),
+            photo.perceptual_hash.map(|hash| hash.0 as i64),
+            sqlx::types::Json(&photo.titles) as _,
+            photo.caption,
+            photo.alt_text,
         )
         .fetch_one(&mut trans)
         .await?;
@@ -578,14 +1182,15 @@ impl PhotoProvider for PgConnection {
             sqlx::query!(
                 r#"
                     INSERT INTO sources
-                        (photo_id, width, height, url)
+                        (photo_id, width, height, url, mime_type)
                     VALUES
-                        ($1, $2, $3, $4)
+                        ($1, $2, $3, $4, $5)
                 "#,
                 res.id,
                 source.width as i32,
                 source.height as i32,
                 source.url,
+                source.mime_type,
             )
             .execute(&mut trans)
             .await?;
@@ -651,6 +1256,77 @@ impl PhotoProvider for PgConnection {
             .await?;
         }
 
+        if let Some(titles) = &new_photo.titles {
+            if &old_photo.titles != titles {
+                info!(
+                    titles.before = ?old_photo.titles,
+                    titles.after = ?titles,
+                    "Titles differ, updating"
+                );
+                changed = true;
+                sqlx::query!(
+                    r#"
+                        UPDATE
+                            photos
+                        SET
+                            titles = $2
+                        WHERE
+                            id = $1
+                    "#,
+                    old_photo.id,
+                    sqlx::types::Json(titles) as _,
+                )
+                .execute(&mut trans)
+                .await?;
+            }
+        }
+
+        if old_photo.caption != new_photo.caption {
+            info!(
+                caption.before = old_photo.caption,
+                caption.after = new_photo.caption,
+                "Caption differs, updating"
+            );
+            changed = true;
+            sqlx::query!(
+                r#"
+                    UPDATE
+                        photos
+                    SET
+                        caption = $2
+                    WHERE
+                        id = $1
+                "#,
+                old_photo.id,
+                new_photo.caption,
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+
+        if old_photo.alt_text != new_photo.alt_text {
+            info!(
+                alt_text.before = old_photo.alt_text,
+                alt_text.after = new_photo.alt_text,
+                "Alt text differs, updating"
+            );
+            changed = true;
+            sqlx::query!(
+                r#"
+                    UPDATE
+                        photos
+                    SET
+                        alt_text = $2
+                    WHERE
+                        id = $1
+                "#,
+                old_photo.id,
+                new_photo.alt_text,
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+
         if old_photo.tags != new_photo.tags {
             info!(
                 tags.before = ?old_photo.tags,
@@ -674,6 +1350,98 @@ impl PhotoProvider for PgConnection {
             .await?;
         }
 
+        if new_photo.blurhash.is_some() && old_photo.blurhash != new_photo.blurhash {
+            info!(
+                blurhash.before = old_photo.blurhash,
+                blurhash.after = new_photo.blurhash,
+                "BlurHash differs, updating"
+            );
+            changed = true;
+            sqlx::query!(
+                r#"
+                    UPDATE
+                        photos
+                    SET
+                        blurhash = $2
+                    WHERE
+                        id = $1
+                "#,
+                old_photo.id,
+                new_photo.blurhash,
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+
+        if new_photo.lqip.is_some() && old_photo.lqip != new_photo.lqip {
+            info!("LQIP differs, updating");
+            changed = true;
+            sqlx::query!(
+                r#"
+                    UPDATE
+                        photos
+                    SET
+                        lqip = $2
+                    WHERE
+                        id = $1
+                "#,
+                old_photo.id,
+                new_photo.lqip,
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+
+        if let Some(palette) = &new_photo.palette {
+            if &old_photo.palette != palette {
+                info!(
+                    palette.before = ?old_photo.palette,
+                    palette.after = ?palette,
+                    "Palette differs, updating"
+                );
+                changed = true;
+                sqlx::query!(
+                    r#"
+                        UPDATE
+                            photos
+                        SET
+                            palette = $2
+                        WHERE
+                            id = $1
+                    "#,
+                    old_photo.id,
+                    &pack_palette(palette),
+                )
+                .execute(&mut trans)
+                .await?;
+            }
+        }
+
+        if new_photo.perceptual_hash.is_some()
+            && old_photo.perceptual_hash != new_photo.perceptual_hash
+        {
+            info!(
+                perceptual_hash.before = ?old_photo.perceptual_hash,
+                perceptual_hash.after = ?new_photo.perceptual_hash,
+                "Perceptual hash differs, updating"
+            );
+            changed = true;
+            sqlx::query!(
+                r#"
+                    UPDATE
+                        photos
+                    SET
+                        perceptual_hash = $2
+                    WHERE
+                        id = $1
+                "#,
+                old_photo.id,
+                new_photo.perceptual_hash.map(|hash| hash.0 as i64),
+            )
+            .execute(&mut trans)
+            .await?;
+        }
+
         if let Some(sources) = &new_photo.sources {
             if &old_photo.sources != sources {
                 info!(
@@ -698,14 +1466,15 @@ impl PhotoProvider for PgConnection {
                     sqlx::query!(
                         r#"
                             INSERT INTO sources
-                                (photo_id, width, height, url)
+                                (photo_id, width, height, url, mime_type)
                             VALUES
-                                ($1, $2, $3, $4)
+                                ($1, $2, $3, $4, $5)
                         "#,
                         old_photo.id,
                         source.width as i32,
                         source.height as i32,
                         source.url,
+                        source.mime_type,
                     )
                     .execute(&mut trans)
                     .await?;
@@ -764,4 +1533,90 @@ impl PhotoProvider for PgConnection {
 
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    async fn set_photo_sort_key(
+        &mut self,
+        photo_id: PhotoId,
+        sort_key: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    photos
+                SET
+                    sort_key = $1
+                WHERE
+                    photos.id = $2
+            "#,
+            sort_key,
+            photo_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_photo_file_stem(&mut self, file_stem: &str) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO photo_file_stem_claims (file_stem)
+                VALUES ($1)
+                ON CONFLICT (file_stem) DO UPDATE
+                SET
+                    claimed_at = NOW()
+                WHERE
+                    photo_file_stem_claims.claimed_at < NOW() - INTERVAL '1 day'
+                RETURNING
+                    file_stem
+            "#,
+            file_stem,
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(res.is_some())
+    }
+
+    #[instrument(skip(self))]
+    async fn is_photo_file_stem_claimed(&mut self, file_stem: &str) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    EXISTS (
+                        SELECT
+                            1
+                        FROM
+                            photo_file_stem_claims
+                        WHERE
+                            file_stem = $1
+                            AND claimed_at >= NOW() - INTERVAL '1 day'
+                    ) AS "claimed!"
+            "#,
+            file_stem,
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(res.claimed)
+    }
+
+    #[instrument(skip(self))]
+    async fn release_photo_file_stem(&mut self, file_stem: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                DELETE FROM
+                    photo_file_stem_claims
+                WHERE
+                    file_stem = $1
+            "#,
+            file_stem,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
 }
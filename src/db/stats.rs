@@ -0,0 +1,278 @@
+use sqlx::PgConnection;
+use tracing::instrument;
+
+use crate::db::photos::{PhotoId, Published};
+use crate::models;
+use crate::models::stats::DayStats;
+
+/// Something a visitor can do that gets counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsEvent {
+    View,
+    Favorite,
+}
+
+/// Condition for `day` in `table` being within the last `$bind` days, including today.
+fn within_days(table: &str, bind: usize) -> String {
+    format!(
+        "{}.day > (NOW() AT TIME ZONE 'UTC')::DATE - ${}::INTEGER",
+        table, bind
+    )
+}
+
+/// Group daily counts ordered by key into totals and days per key, highest totals first.
+fn group_daily<K: PartialEq>(
+    rows: Vec<(K, String, i64, i64)>,
+) -> Vec<(K, i64, i64, Vec<DayStats>)> {
+    let mut grouped: Vec<(K, i64, i64, Vec<DayStats>)> = Vec::new();
+    for (key, day, views, favorites) in rows {
+        let day = DayStats {
+            day,
+            views,
+            favorites,
+        };
+        match grouped.last_mut() {
+            Some((last_key, total_views, total_favorites, daily)) if *last_key == key => {
+                *total_views += views;
+                *total_favorites += favorites;
+                daily.push(day);
+            },
+            _ => grouped.push((key, views, favorites, vec![day])),
+        }
+    }
+
+    grouped.sort_by(
+        |(_, a_views, a_favorites, _), (_, b_views, b_favorites, _)| {
+            (b_views, b_favorites).cmp(&(a_views, a_favorites))
+        },
+    );
+    grouped
+}
+
+#[async_trait::async_trait]
+pub trait StatsProvider {
+    /// Count a view or favorite of a photo for today.
+    async fn record_photo_event(
+        &mut self,
+        photo_id: PhotoId,
+        event: StatsEvent,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Get the IDs of the photos with the most favorites and views in the last `days` days.
+    async fn get_popular_photo_ids(
+        &mut self,
+        days: i32,
+        limit: i64,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, sqlx::Error>;
+
+    /// Get the counts of photos in the last `days` days, most viewed first.
+    ///
+    /// * `photo_id`: If `Some`, only get the counts of this photo.
+    async fn get_photo_stats(
+        &mut self,
+        days: i32,
+        photo_id: Option<PhotoId>,
+    ) -> Result<Vec<models::stats::PhotoStats>, sqlx::Error>;
+
+    /// Get the counts summed per tag in the last `days` days, most viewed first.
+    ///
+    /// * `tag`: If `Some`, only get the counts of this tag.
+    async fn get_tag_stats(
+        &mut self,
+        days: i32,
+        tag: Option<&str>,
+    ) -> Result<Vec<models::stats::TagStats>, sqlx::Error>;
+}
+
+#[async_trait::async_trait]
+impl StatsProvider for PgConnection {
+    #[instrument(skip(self))]
+    async fn record_photo_event(
+        &mut self,
+        photo_id: PhotoId,
+        event: StatsEvent,
+    ) -> Result<(), sqlx::Error> {
+        let (views, favorites) = match event {
+            StatsEvent::View => (1, 0),
+            StatsEvent::Favorite => (0, 1),
+        };
+
+        sqlx::query!(
+            r#"
+                INSERT INTO photo_daily_stats (
+                    photo_id,
+                    day,
+                    views,
+                    favorites
+                )
+                VALUES
+                    ($1, (NOW() AT TIME ZONE 'UTC')::DATE, $2, $3)
+                ON CONFLICT (photo_id, day) DO UPDATE SET
+                    views = photo_daily_stats.views + EXCLUDED.views,
+                    favorites = photo_daily_stats.favorites + EXCLUDED.favorites
+            "#,
+            photo_id,
+            views,
+            favorites,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_popular_photo_ids(
+        &mut self,
+        days: i32,
+        limit: i64,
+        published: Published,
+    ) -> Result<Vec<PhotoId>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                photo.id
+            FROM
+                photos photo
+            JOIN
+                photo_daily_stats stats
+            ON
+                stats.photo_id = photo.id
+            WHERE
+                {}
+        "#,
+            within_days("stats", 1)
+        );
+
+        if published == Published::OnlyPublished {
+            query.push_str("    AND photo.published = 't'\n")
+        }
+
+        query.push_str(
+            r#"
+            GROUP BY
+                photo.id
+            ORDER BY
+                SUM(stats.favorites) DESC, SUM(stats.views) DESC, photo.id DESC
+            LIMIT $2
+        "#,
+        );
+
+        let ids: Vec<(PhotoId,)> = sqlx::query_as(&query)
+            .bind(days)
+            .bind(limit)
+            .fetch_all(self)
+            .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_photo_stats(
+        &mut self,
+        days: i32,
+        photo_id: Option<PhotoId>,
+    ) -> Result<Vec<models::stats::PhotoStats>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                stats.photo_id,
+                TO_CHAR(stats.day, 'YYYY-MM-DD'),
+                stats.views::BIGINT,
+                stats.favorites::BIGINT
+            FROM
+                photo_daily_stats stats
+            WHERE
+                {}
+        "#,
+            within_days("stats", 1)
+        );
+
+        if photo_id.is_some() {
+            query.push_str("    AND stats.photo_id = $2\n")
+        }
+
+        query.push_str(
+            r#"
+            ORDER BY
+                stats.photo_id, stats.day
+        "#,
+        );
+
+        let mut query = sqlx::query_as(&query).bind(days);
+        if let Some(photo_id) = photo_id {
+            query = query.bind(photo_id);
+        }
+        let rows: Vec<(PhotoId, String, i64, i64)> = query.fetch_all(self).await?;
+
+        Ok(group_daily(rows)
+            .into_iter()
+            .map(
+                |(photo_id, views, favorites, daily)| models::stats::PhotoStats {
+                    photo_id,
+                    views,
+                    favorites,
+                    daily,
+                },
+            )
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_tag_stats(
+        &mut self,
+        days: i32,
+        tag: Option<&str>,
+    ) -> Result<Vec<models::stats::TagStats>, sqlx::Error> {
+        let mut query = format!(
+            r#"
+            SELECT
+                tag,
+                TO_CHAR(stats.day, 'YYYY-MM-DD'),
+                SUM(stats.views)::BIGINT,
+                SUM(stats.favorites)::BIGINT
+            FROM
+                photo_daily_stats stats
+            JOIN
+                photos photo
+            ON
+                photo.id = stats.photo_id
+            CROSS JOIN LATERAL
+                UNNEST(photo.tags) AS tag
+            WHERE
+                {}
+        "#,
+            within_days("stats", 1)
+        );
+
+        if tag.is_some() {
+            query.push_str("    AND tag = $2\n")
+        }
+
+        query.push_str(
+            r#"
+            GROUP BY
+                tag, stats.day
+            ORDER BY
+                tag, stats.day
+        "#,
+        );
+
+        let mut query = sqlx::query_as(&query).bind(days);
+        if let Some(tag) = tag {
+            query = query.bind(tag);
+        }
+        let rows: Vec<(String, String, i64, i64)> = query.fetch_all(self).await?;
+
+        Ok(group_daily(rows)
+            .into_iter()
+            .map(|(tag, views, favorites, daily)| models::stats::TagStats {
+                tag,
+                views,
+                favorites,
+                daily,
+            })
+            .collect())
+    }
+}
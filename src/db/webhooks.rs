@@ -0,0 +1,317 @@
+use sqlx::{FromRow, PgConnection};
+use tracing::instrument;
+
+use crate::db::W3C_DATETIME_FORMAT;
+use crate::models;
+use crate::models::webhooks::{WebhookEvent, WebhookId};
+
+pub type DeliveryId = i64;
+
+#[derive(Debug, FromRow)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<String>,
+    /// When the webhook was registered, as a W3C datetime in UTC.
+    pub created_at: String,
+}
+
+/// A queued payload that's due to be delivered, along with where to deliver it.
+#[derive(Debug, FromRow)]
+pub struct PendingDelivery {
+    pub id: DeliveryId,
+    pub event: String,
+    pub payload: sqlx::types::Json<serde_json::Value>,
+    /// Number of attempts including the current one.
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+#[async_trait::async_trait]
+pub trait WebhookProvider {
+    /// Get every registered webhook, oldest first.
+    async fn get_webhooks(&mut self) -> Result<Vec<models::webhooks::Webhook>, sqlx::Error>;
+
+    /// Register a webhook endpoint.
+    async fn insert_webhook(
+        &mut self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+    ) -> Result<WebhookId, sqlx::Error>;
+
+    /// Delete a webhook along with its queued deliveries.
+    ///
+    /// Returns whether the webhook existed.
+    async fn delete_webhook(&mut self, webhook_id: WebhookId) -> Result<bool, sqlx::Error>;
+
+    /// Queue a payload for delivery to every webhook registered for the event.
+    ///
+    /// Returns the number of queued deliveries.
+    async fn enqueue_webhook_deliveries(
+        &mut self,
+        event: WebhookEvent,
+        payload: &serde_json::Value,
+    ) -> Result<u64, sqlx::Error>;
+
+    /// Claim up to `limit` due deliveries and count the attempt.
+    ///
+    /// Claimed deliveries aren't due again until `lease_seconds` have passed, so that they're
+    /// retried if the process delivering them dies, but not delivered twice concurrently.
+    async fn claim_webhook_deliveries(
+        &mut self,
+        limit: i64,
+        lease_seconds: f64,
+    ) -> Result<Vec<PendingDelivery>, sqlx::Error>;
+
+    /// Mark a delivery as delivered.
+    async fn mark_webhook_delivered(&mut self, delivery_id: DeliveryId) -> Result<(), sqlx::Error>;
+
+    /// Schedule another attempt at a failed delivery in `delay_seconds`.
+    async fn retry_webhook_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        delay_seconds: f64,
+        error: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Give up on a delivery.
+    async fn fail_webhook_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        error: &str,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait::async_trait]
+impl WebhookProvider for PgConnection {
+    #[instrument(skip(self))]
+    async fn get_webhooks(&mut self) -> Result<Vec<models::webhooks::Webhook>, sqlx::Error> {
+        let query = format!(
+            r#"
+            SELECT
+                id,
+                url,
+                events,
+                TO_CHAR(created_at AT TIME ZONE 'UTC', {}) AS created_at
+            FROM
+                webhooks
+            ORDER BY
+                id ASC
+        "#,
+            W3C_DATETIME_FORMAT
+        );
+
+        let res: Vec<Webhook> = sqlx::query_as(&query).fetch_all(self).await?;
+
+        Ok(res
+            .into_iter()
+            .map(models::webhooks::Webhook::from)
+            .collect())
+    }
+
+    #[instrument(skip(self, secret))]
+    async fn insert_webhook(
+        &mut self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+    ) -> Result<WebhookId, sqlx::Error> {
+        let events: Vec<String> = events
+            .iter()
+            .map(|event| event.as_str().to_string())
+            .collect();
+
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO webhooks (
+                    url,
+                    secret,
+                    events
+                )
+                VALUES
+                    ($1, $2, $3)
+                RETURNING
+                    id
+            "#,
+            url,
+            secret,
+            &events,
+        )
+        .fetch_one(self)
+        .await?;
+
+        Ok(res.id)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_webhook(&mut self, webhook_id: WebhookId) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM
+                    webhooks
+                WHERE
+                    id = $1
+            "#,
+            webhook_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self, payload))]
+    async fn enqueue_webhook_deliveries(
+        &mut self,
+        event: WebhookEvent,
+        payload: &serde_json::Value,
+    ) -> Result<u64, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO webhook_deliveries (
+                    webhook_id,
+                    event,
+                    payload
+                )
+                SELECT
+                    id, $1, $2
+                FROM
+                    webhooks
+                WHERE
+                    events = '{}' OR $1 = ANY(events)
+            "#,
+            event.as_str(),
+            sqlx::types::Json(payload) as _,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    async fn claim_webhook_deliveries(
+        &mut self,
+        limit: i64,
+        lease_seconds: f64,
+    ) -> Result<Vec<PendingDelivery>, sqlx::Error> {
+        // SKIP LOCKED lets several processes work through the queue without blocking each
+        // other or claiming the same deliveries.
+        let query = r#"
+            WITH claimed AS (
+                UPDATE
+                    webhook_deliveries delivery
+                SET
+                    attempts = delivery.attempts + 1,
+                    next_attempt_at = NOW() + MAKE_INTERVAL(secs => $2)
+                WHERE
+                    delivery.id IN (
+                        SELECT
+                            id
+                        FROM
+                            webhook_deliveries
+                        WHERE
+                            delivered_at IS NULL
+                            AND failed_at IS NULL
+                            AND next_attempt_at <= NOW()
+                        ORDER BY
+                            next_attempt_at ASC
+                        LIMIT $1
+                        FOR UPDATE SKIP LOCKED
+                    )
+                RETURNING
+                    delivery.id, delivery.webhook_id, delivery.event, delivery.payload,
+                    delivery.attempts
+            )
+            SELECT
+                claimed.id, claimed.event, claimed.payload, claimed.attempts,
+                webhook.url, webhook.secret
+            FROM
+                claimed
+            JOIN
+                webhooks webhook
+            ON
+                webhook.id = claimed.webhook_id
+        "#;
+
+        sqlx::query_as(query)
+            .bind(limit)
+            .bind(lease_seconds)
+            .fetch_all(self)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_webhook_delivered(&mut self, delivery_id: DeliveryId) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    webhook_deliveries
+                SET
+                    delivered_at = NOW(),
+                    last_error = NULL
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn retry_webhook_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        delay_seconds: f64,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    webhook_deliveries
+                SET
+                    next_attempt_at = NOW() + MAKE_INTERVAL(secs => $2),
+                    last_error = $3
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+            delay_seconds,
+            error,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn fail_webhook_delivery(
+        &mut self,
+        delivery_id: DeliveryId,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE
+                    webhook_deliveries
+                SET
+                    failed_at = NOW(),
+                    last_error = $2
+                WHERE
+                    id = $1
+            "#,
+            delivery_id,
+            error,
+        )
+        .execute(self)
+        .await?;
+
+        Ok(())
+    }
+}
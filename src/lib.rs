@@ -1,14 +1,27 @@
 use std::io::Read;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use opentelemetry_tide::TideExt;
+use rusty_peanuts_media::storage::Storage;
+use rusty_peanuts_media::transcode::Format;
 use structopt::StructOpt;
+use tide::listener::Listener;
+use tracing::{info, warn};
 
+use crate::db::photos::SortMode;
+
+pub mod activitypub;
 pub mod db;
 pub mod models;
+pub mod render_static;
+pub mod shutdown;
+pub mod stats;
 pub mod telemetry;
+pub mod uploads;
 pub mod web;
+pub mod webhooks;
 
 #[derive(Clone, Debug)]
 pub struct State {
@@ -16,6 +29,11 @@ pub struct State {
     pub db: sqlx::postgres::PgPool,
     pub tera: Arc<tera::Tera>,
     pub cache_busting_string: Option<String>,
+    pub cursor_secret: Arc<Vec<u8>>,
+    pub uploads: Option<uploads::UploadQueue>,
+    pub visitors: stats::DailyVisitors,
+    pub webhooks: Option<webhooks::WebhookDispatcher>,
+    pub activitypub: Option<activitypub::Federation>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -35,6 +53,18 @@ pub struct Args {
     #[structopt(long, env = "RUSTY_PEANUTS_BASE_URL")]
     base_url: String,
 
+    /// Site name used in page metadata
+    #[structopt(long, default_value = "rusty-peanuts", env = "RUSTY_PEANUTS_SITE_NAME")]
+    site_name: String,
+
+    /// Author of the photos, used in page metadata
+    #[structopt(long, env = "RUSTY_PEANUTS_AUTHOR")]
+    author: Option<String>,
+
+    /// Twitter handle of the site, e.g. @example, used in page metadata
+    #[structopt(long, env = "RUSTY_PEANUTS_TWITTER_SITE")]
+    twitter_site: Option<String>,
+
     /// Default number of photos per gallery page
     #[structopt(
         long,
@@ -43,10 +73,20 @@ pub struct Args {
     )]
     default_photos_per_page: u8,
 
+    /// What galleries are sorted by unless overridden by a request: id, taken_timestamp or
+    /// sort_key
+    #[structopt(long, default_value = "id", env = "RUSTY_PEANUTS_DEFAULT_SORT")]
+    default_sort: SortMode,
+
     /// Max number of photos per gallery page
     #[structopt(long, default_value = "100", env = "RUSTY_PEANUTS_MAX_PHOTOS_PER_PAGE")]
     max_photos_per_page: u8,
 
+    /// Secret used to encrypt pagination cursors, random on every start if unset, in which case
+    /// links with legacy offsets are only redirected temporarily
+    #[structopt(long, env = "RUSTY_PEANUTS_CURSOR_SECRET", hide_env_values = true)]
+    cursor_secret: Option<String>,
+
     /// Path to Tera templates directory
     #[structopt(
         long,
@@ -55,6 +95,204 @@ pub struct Args {
         env = "RUSTY_PEANUTS_TEMPLATE_PATH"
     )]
     template_path: std::path::PathBuf,
+
+    /// Directory of photo files to serve under /media/, e.g. one populated by rusty-peanuts-cli
+    /// with a local media directory
+    #[structopt(long, parse(from_os_str), env = "RUSTY_PEANUTS_MEDIA_DIR")]
+    media_dir: Option<std::path::PathBuf>,
+
+    /// Full S3-compatible region endpoint to store uploaded photos in, if not using --media-dir
+    #[structopt(long, env = "RUSTY_PEANUTS_S3_REGION_ENDPOINT")]
+    s3_region_endpoint: Option<String>,
+
+    /// S3-compatible bucket name to store uploaded photos in
+    #[structopt(long, env = "RUSTY_PEANUTS_S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    /// S3 access key ID
+    #[structopt(long, env = "RUSTY_PEANUTS_S3_ACCESS_KEY_ID", hide_env_values = true)]
+    s3_access_key_id: Option<String>,
+
+    /// S3 secret access key
+    #[structopt(
+        long,
+        env = "RUSTY_PEANUTS_S3_SECRET_ACCESS_KEY",
+        hide_env_values = true
+    )]
+    s3_secret_access_key: Option<String>,
+
+    /// Base URL that files stored in the S3-compatible bucket are served from
+    #[structopt(long, env = "RUSTY_PEANUTS_STATIC_HOST")]
+    static_host: Option<String>,
+
+    /// Number of uploaded photos to transcode concurrently
+    #[structopt(long, default_value = "1", env = "RUSTY_PEANUTS_UPLOAD_WORKERS")]
+    upload_workers: usize,
+
+    /// Number of uploaded photos that can wait to be transcoded before uploads are rejected
+    #[structopt(long, default_value = "8", env = "RUSTY_PEANUTS_UPLOAD_QUEUE_SIZE")]
+    upload_queue_size: usize,
+
+    /// Comma-separated list of formats to transcode uploaded photos to, JPEG is always included
+    #[structopt(
+        long,
+        default_value = "jpeg,webp,avif",
+        use_delimiter = true,
+        env = "RUSTY_PEANUTS_UPLOAD_FORMATS"
+    )]
+    upload_formats: Vec<Format>,
+
+    /// Maximum size in bytes of an uploaded photo
+    #[structopt(
+        long,
+        default_value = "268435456",
+        env = "RUSTY_PEANUTS_MAX_UPLOAD_SIZE"
+    )]
+    max_upload_size: usize,
+
+    /// Directory to cache resized photos in, enables the /img/:photo_id/:size endpoint if set
+    #[structopt(long, parse(from_os_str), env = "RUSTY_PEANUTS_RESIZE_CACHE_DIR")]
+    resize_cache_dir: Option<std::path::PathBuf>,
+
+    /// Comma-separated sizes the resizing endpoint may produce, either WIDTH or WIDTHxHEIGHT
+    #[structopt(
+        long,
+        use_delimiter = true,
+        default_value = "1200x630,600x600,250x250",
+        env = "RUSTY_PEANUTS_RESIZE_SIZES"
+    )]
+    resize_sizes: Vec<String>,
+
+    /// Seconds to wait for in-flight requests, queued uploads and deliveries to finish when
+    /// shutting down
+    #[structopt(long, default_value = "30", env = "RUSTY_PEANUTS_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: u64,
+
+    /// Sustained number of API requests allowed per client and minute, 0 disables rate limiting
+    #[structopt(
+        long,
+        default_value = "120",
+        env = "RUSTY_PEANUTS_RATE_LIMIT_PER_MINUTE"
+    )]
+    rate_limit_per_minute: u32,
+
+    /// Number of API requests a client can make in a burst before being rate limited
+    #[structopt(long, default_value = "30", env = "RUSTY_PEANUTS_RATE_LIMIT_BURST")]
+    rate_limit_burst: u32,
+
+    /// Number of failed authentication attempts before a client gets locked out
+    #[structopt(
+        long,
+        default_value = "5",
+        env = "RUSTY_PEANUTS_RATE_LIMIT_MAX_FAILURES"
+    )]
+    rate_limit_max_failures: u32,
+
+    /// Seconds to lock out a client for, doubled for every repeated lockout
+    #[structopt(
+        long,
+        default_value = "300",
+        env = "RUSTY_PEANUTS_RATE_LIMIT_LOCKOUT_SECONDS"
+    )]
+    rate_limit_lockout_seconds: u64,
+
+    /// Header set by a trusted reverse proxy containing the client IP address, e.g.
+    /// X-Forwarded-For
+    #[structopt(long, env = "RUSTY_PEANUTS_TRUSTED_PROXY_HEADER")]
+    trusted_proxy_header: Option<String>,
+
+    /// Sustained number of comments a client can post per hour, 0 disables rate limiting
+    #[structopt(
+        long,
+        default_value = "10",
+        env = "RUSTY_PEANUTS_COMMENT_RATE_LIMIT_PER_HOUR"
+    )]
+    comment_rate_limit_per_hour: u32,
+
+    /// Number of comments a client can post in a burst before being rate limited
+    #[structopt(
+        long,
+        default_value = "3",
+        env = "RUSTY_PEANUTS_COMMENT_RATE_LIMIT_BURST"
+    )]
+    comment_rate_limit_burst: u32,
+
+    /// Sustained number of photos a client can favorite per hour, 0 disables rate limiting
+    #[structopt(
+        long,
+        default_value = "30",
+        env = "RUSTY_PEANUTS_FAVORITE_RATE_LIMIT_PER_HOUR"
+    )]
+    favorite_rate_limit_per_hour: u32,
+
+    /// Number of photos a client can favorite in a burst before being rate limited
+    #[structopt(
+        long,
+        default_value = "10",
+        env = "RUSTY_PEANUTS_FAVORITE_RATE_LIMIT_BURST"
+    )]
+    favorite_rate_limit_burst: u32,
+
+    /// Number of days of views and favorites the popular page is based on
+    #[structopt(long, default_value = "30", env = "RUSTY_PEANUTS_POPULAR_DAYS")]
+    popular_days: u16,
+
+    /// Seconds between checks for webhook and ActivityPub deliveries that are due to be retried
+    #[structopt(
+        long,
+        default_value = "10",
+        env = "RUSTY_PEANUTS_WEBHOOK_POLL_INTERVAL"
+    )]
+    webhook_poll_interval: u64,
+
+    /// PEM-encoded PKCS#8 RSA private key of the gallery's ActivityPub actor, enables federation
+    /// if set, e.g. generated with `openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048`
+    #[structopt(long, parse(from_os_str), env = "RUSTY_PEANUTS_ACTIVITYPUB_KEY")]
+    activitypub_key: Option<std::path::PathBuf>,
+
+    /// Username of the gallery's ActivityPub actor, as in @username@example.com
+    #[structopt(
+        long,
+        default_value = "gallery",
+        env = "RUSTY_PEANUTS_ACTIVITYPUB_USERNAME"
+    )]
+    activitypub_username: String,
+
+    /// Sustained number of activities a client can send to the ActivityPub inbox per minute, 0
+    /// disables rate limiting
+    #[structopt(
+        long,
+        default_value = "120",
+        env = "RUSTY_PEANUTS_ACTIVITYPUB_INBOX_RATE_LIMIT_PER_MINUTE"
+    )]
+    activitypub_inbox_rate_limit_per_minute: u32,
+
+    /// Number of activities a client can send to the ActivityPub inbox in a burst before being
+    /// rate limited
+    #[structopt(
+        long,
+        default_value = "30",
+        env = "RUSTY_PEANUTS_ACTIVITYPUB_INBOX_RATE_LIMIT_BURST"
+    )]
+    activitypub_inbox_rate_limit_burst: u32,
+
+    /// Allow fetching from and delivering to loopback and private network addresses, e.g. to
+    /// federate with a local test instance
+    #[structopt(long)]
+    activitypub_allow_private_addresses: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Render the published photos to a directory of static files instead of serving them
+    RenderStatic {
+        /// Directory to write the rendered site to
+        #[structopt(parse(from_os_str))]
+        output_dir: std::path::PathBuf,
+    },
 }
 
 pub async fn main() -> Result<()> {
@@ -84,22 +322,157 @@ pub async fn main() -> Result<()> {
         Err(_) => None,
     };
 
+    let cursor_secret = match args.cursor_secret {
+        Some(ref secret) => secret.as_bytes().to_vec(),
+        None => {
+            warn!("No cursor secret configured, pagination links won't survive restarts");
+            rand::random::<[u8; 32]>().to_vec()
+        },
+    };
+
+    let upload_storage = match (&args.media_dir, &args.s3_bucket) {
+        (Some(media_dir), _) => Some((
+            Storage::local(media_dir.clone()),
+            format!("{}/media", args.base_url),
+        )),
+        (None, Some(s3_bucket)) => Some((
+            Storage::s3(
+                args.s3_region_endpoint
+                    .as_deref()
+                    .context("Missing S3 region endpoint")?,
+                s3_bucket,
+                args.s3_access_key_id
+                    .as_deref()
+                    .context("Missing S3 access key ID")?,
+                args.s3_secret_access_key
+                    .as_deref()
+                    .context("Missing S3 secret access key")?,
+            ),
+            args.static_host.clone().context("Missing static host")?,
+        )),
+        (None, None) => None,
+    };
+    // Nothing gets uploaded or delivered while rendering a static site.
+    let webhooks = match args.command {
+        Some(_) => None,
+        None => Some(webhooks::WebhookDispatcher::start(
+            pool.clone(),
+            Duration::from_secs(args.webhook_poll_interval.max(1)),
+        )),
+    };
+    let activitypub = match (&args.activitypub_key, &args.command) {
+        (Some(key_path), None) => {
+            let pem = std::fs::read_to_string(key_path).context("Couldn't read ActivityPub key")?;
+            let key = activitypub::signatures::PrivateKey::from_pem(&pem)
+                .context("Invalid ActivityPub key")?;
+            let mut actor =
+                activitypub::LocalActor::new(&args.base_url, &args.activitypub_username, key)?;
+            if args.activitypub_allow_private_addresses {
+                actor = actor.allow_private_addresses();
+            }
+            Some(activitypub::Federation::start(
+                pool.clone(),
+                actor,
+                Duration::from_secs(args.webhook_poll_interval.max(1)),
+            ))
+        },
+        _ => None,
+    };
+    let upload_storage = upload_storage.filter(|_| args.command.is_none());
+    let uploads = upload_storage.map(|(storage, static_host)| {
+        uploads::UploadQueue::start(
+            args.upload_workers,
+            args.upload_queue_size,
+            pool.clone(),
+            storage,
+            static_host,
+            args.upload_formats.clone(),
+            webhooks.clone(),
+        )
+    });
+
     let state = State {
         args: args.clone(),
-        db: pool,
+        db: pool.clone(),
         tera: Arc::new(tera),
         cache_busting_string,
+        cursor_secret: Arc::new(cursor_secret),
+        uploads: uploads.clone(),
+        visitors: stats::DailyVisitors::new(),
+        webhooks: webhooks.clone(),
+        activitypub: activitypub.clone(),
     };
+    if let Some(Command::RenderStatic { ref output_dir }) = args.command {
+        render_static::render(&state, output_dir)
+            .await
+            .context("Failed to render static site")?;
+
+        pool.close().await;
+        telemetry::shutdown().await;
+        return Ok(());
+    }
+
     let mut app = tide::with_state(state);
 
+    let in_flight = shutdown::InFlightRequests::new();
+    app.with(in_flight.clone());
     app.with_default_tracing_middleware();
 
     web::mount(&mut app);
 
     let address: &str = args.address.as_ref();
-    app.listen((address, args.port))
+    let mut listener = app
+        .bind((address, args.port))
         .await
-        .context("Failed to start tide app")?;
+        .context("Failed to bind tide app")?;
+    for listen_info in listener.info() {
+        info!("Server listening on {}", listen_info);
+    }
+
+    futures_lite::future::or(
+        async { listener.accept().await.context("Failed to start tide app") },
+        async {
+            shutdown::wait_for_signal()
+                .await
+                .context("Failed to wait for shutdown signal")
+        },
+    )
+    .await?;
+
+    // Stop accepting new connections, but let the already spawned connection tasks finish their
+    // requests.
+    drop(listener);
+
+    // Everything using the database gets stopped before closing the pool, all within the same
+    // deadline.
+    let deadline = Instant::now() + Duration::from_secs(args.shutdown_timeout);
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+
+    info!(
+        requests.in_flight = in_flight.count(),
+        "Draining in-flight requests"
+    );
+    in_flight.drain(remaining()).await;
+
+    // Uploads can queue webhooks and activities, so they're stopped before the dispatchers.
+    if let Some(uploads) = uploads {
+        info!("Stopping upload workers");
+        uploads.shutdown(remaining()).await;
+    }
+    if let Some(webhooks) = webhooks {
+        info!("Stopping webhook dispatcher");
+        webhooks.shutdown(remaining()).await;
+    }
+    if let Some(activitypub) = activitypub {
+        info!("Stopping ActivityPub dispatcher");
+        activitypub.shutdown(remaining()).await;
+    }
+
+    info!("Closing database pool");
+    pool.close().await;
+
+    info!("Flushing telemetry");
+    telemetry::shutdown().await;
 
     Ok(())
 }
@@ -0,0 +1,32 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::photos::PhotoId;
+
+pub type CommentId = i32;
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Comment {
+    pub id: CommentId,
+    pub photo_id: PhotoId,
+    pub author: String,
+    /// Plain text, to be escaped when rendered.
+    pub body: String,
+    /// Whether the comment has been approved, pending comments aren't shown to visitors.
+    pub approved: bool,
+    /// When the comment was posted, as a W3C datetime in UTC.
+    pub created_at: String,
+}
+
+impl From<crate::db::comments::Comment> for Comment {
+    fn from(c: crate::db::comments::Comment) -> Self {
+        Comment {
+            id: c.id,
+            photo_id: c.photo_id,
+            author: c.author,
+            body: c.body,
+            approved: c.approved,
+            created_at: c.created_at,
+        }
+    }
+}
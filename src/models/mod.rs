@@ -1 +1,4 @@
+pub mod comments;
 pub mod photos;
+pub mod stats;
+pub mod webhooks;
@@ -1,34 +1,98 @@
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
-use rusty_peanuts_api_structs::Source;
+use rusty_peanuts_api_structs::{Color, PerceptualHash, Source, JPEG_MIME_TYPE};
 
 pub type PhotoId = i32;
 
-#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub struct Photo {
     pub id: PhotoId,
     pub file_stem: String,
+    /// Default title, used for languages without a title of their own.
     pub title: Option<String>,
+    /// Titles by language tag.
+    pub titles: BTreeMap<String, String>,
+    /// Caption shown with the photo, as Markdown.
+    pub caption: Option<String>,
+    /// Description of the photo for screen readers.
+    pub alt_text: Option<String>,
     pub taken_timestamp: Option<String>,
+    /// When the photo was taken as parsed from `taken_timestamp` by the database, to sort by.
+    #[serde(skip)]
+    pub taken_at: Option<OffsetDateTime>,
     pub height_offset: u8,
     pub tags: Vec<String>,
     pub sources: Vec<Source>,
     pub published: bool,
+    pub blurhash: Option<String>,
+    /// `data:` URL of a tiny version of the photo to show while it's loading.
+    pub lqip: Option<String>,
+    /// Dominant colors of the photo, most dominant first.
+    pub palette: Vec<Color>,
+    pub perceptual_hash: Option<PerceptualHash>,
+    /// Manually set position when sorting by sort key, higher sorts first.
+    pub sort_key: Option<i32>,
+}
+
+impl Photo {
+    /// Get the JPEG sources, largest first, for consumers that might not support other formats.
+    pub fn jpeg_sources(&self) -> impl DoubleEndedIterator<Item = &Source> {
+        self.sources
+            .iter()
+            .filter(|source| source.mime_type == JPEG_MIME_TYPE)
+    }
+
+    /// Get the largest JPEG source that fits within the given dimensions.
+    ///
+    /// Falls back to the smallest JPEG source if none of them fit.
+    pub fn source_fitting(
+        &self,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+    ) -> Option<&Source> {
+        self.jpeg_sources()
+            .find(|source| {
+                max_width.map_or(true, |max_width| source.width <= max_width)
+                    && max_height.map_or(true, |max_height| source.height <= max_height)
+            })
+            .or_else(|| self.jpeg_sources().last())
+    }
 }
 
 impl From<crate::db::photos::Photo> for Photo {
     fn from(mut p: crate::db::photos::Photo) -> Self {
-        p.sources.sort_by(|a, b| b.width.cmp(&a.width));
+        p.sources.sort_by(|a, b| {
+            b.width
+                .cmp(&a.width)
+                .then_with(|| a.mime_type.cmp(&b.mime_type))
+        });
 
         Photo {
             id: p.id,
             file_stem: p.file_stem,
             title: p.title,
+            titles: p.titles.0,
+            caption: p.caption,
+            alt_text: p.alt_text,
             taken_timestamp: p.taken_timestamp,
+            taken_at: p.taken_at,
             height_offset: p.height_offset as u8,
             tags: p.tags,
             sources: p.sources.to_vec(),
             published: p.published,
+            blurhash: p.blurhash,
+            lqip: p.lqip,
+            palette: p
+                .palette
+                .into_iter()
+                .map(|rgb| Color::from(rgb as u32))
+                .collect(),
+            perceptual_hash: p.perceptual_hash.map(|hash| PerceptualHash(hash as u64)),
+            sort_key: p.sort_key,
         }
     }
 }
@@ -0,0 +1,35 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::models::photos::PhotoId;
+
+/// Counts for a single day.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct DayStats {
+    /// The day in UTC, as `YYYY-MM-DD`.
+    pub day: String,
+    /// Number of distinct visitors that viewed the photo page.
+    pub views: i64,
+    /// Number of distinct visitors that favorited the photo.
+    pub favorites: i64,
+}
+
+/// Counts for a photo over a range of days.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct PhotoStats {
+    pub photo_id: PhotoId,
+    pub views: i64,
+    pub favorites: i64,
+    /// Counts per day, oldest first, leaving out days without any.
+    pub daily: Vec<DayStats>,
+}
+
+/// Counts summed over all photos with a tag over a range of days.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct TagStats {
+    pub tag: String,
+    pub views: i64,
+    pub favorites: i64,
+    /// Counts per day, oldest first, leaving out days without any.
+    pub daily: Vec<DayStats>,
+}
@@ -0,0 +1,82 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub type WebhookId = i32;
+
+/// Something that happened to a photo that webhooks can be notified about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "photo.created")]
+    PhotoCreated,
+    /// Any change to a photo's metadata, sources, height offset or sort key.
+    #[serde(rename = "photo.updated")]
+    PhotoUpdated,
+    #[serde(rename = "photo.published")]
+    PhotoPublished,
+    #[serde(rename = "photo.unpublished")]
+    PhotoUnpublished,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::PhotoCreated => "photo.created",
+            WebhookEvent::PhotoUpdated => "photo.updated",
+            WebhookEvent::PhotoPublished => "photo.published",
+            WebhookEvent::PhotoUnpublished => "photo.unpublished",
+        }
+    }
+}
+
+impl std::str::FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "photo.created" => Ok(WebhookEvent::PhotoCreated),
+            "photo.updated" => Ok(WebhookEvent::PhotoUpdated),
+            "photo.published" => Ok(WebhookEvent::PhotoPublished),
+            "photo.unpublished" => Ok(WebhookEvent::PhotoUnpublished),
+            _ => Err(format!("unknown webhook event {}", s)),
+        }
+    }
+}
+
+/// A registered webhook endpoint, without its secret.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub url: String,
+    /// Events delivered to the endpoint, every event if empty.
+    pub events: Vec<WebhookEvent>,
+    /// When the webhook was registered, as a W3C datetime in UTC.
+    pub created_at: String,
+}
+
+impl From<crate::db::webhooks::Webhook> for Webhook {
+    fn from(w: crate::db::webhooks::Webhook) -> Self {
+        Webhook {
+            id: w.id,
+            url: w.url,
+            events: w
+                .events
+                .iter()
+                .filter_map(|event| event.parse().ok())
+                .collect(),
+            created_at: w.created_at,
+        }
+    }
+}
+
+/// Request to register a webhook endpoint.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct WebhookRegistration {
+    /// HTTP or HTTPS URL to POST payloads to.
+    pub url: String,
+    /// Secret to sign payloads with, a random one is generated if unset.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Events to deliver to the endpoint, every event if empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
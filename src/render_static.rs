@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use crate::db::photos::{Page, PhotoProvider, Published};
+use crate::web::cursor::Cursor;
+use crate::web::html::is_url_safe_language;
+use crate::State;
+
+/// Origin that pages are requested from when rendering, the handlers only use the path.
+const RENDER_ORIGIN: &str = "http://localhost";
+
+/// A page to render, and the pretty URL path it's written to.
+struct StaticPage {
+    /// Path and query string to request from the handlers.
+    source: String,
+    /// Pretty URL path relative to the site root, directories end in a slash.
+    target: String,
+}
+
+/// Maps the URLs that handlers link to onto the pretty URLs of the rendered pages.
+struct LinkMap {
+    base_url: String,
+    base_path: String,
+    cursor_secret: Vec<u8>,
+    /// Pretty paths by percent-decoded path and page, `Page::Latest` for paths without cursors.
+    targets: HashMap<(String, Page), String>,
+}
+
+impl LinkMap {
+    fn new(state: &State) -> Result<Self> {
+        let base_url = state.args.base_url.trim_end_matches('/').to_string();
+        let base_path = Url::parse(&base_url)
+            .context("Failed to parse base URL")?
+            .path()
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(LinkMap {
+            base_url,
+            base_path,
+            cursor_secret: state.cursor_secret.to_vec(),
+            targets: HashMap::new(),
+        })
+    }
+
+    fn insert(&mut self, path: &str, page: Page, target: &str) {
+        let path = percent_decode_str(path).decode_utf8_lossy().to_string();
+        self.targets.insert((path, page), target.to_string());
+    }
+
+    /// Get the pretty URL for a link found on the page at `page_url`, if it points to a rendered
+    /// page.
+    fn rewrite(&self, link: &str, page_url: &Url) -> Option<String> {
+        let url = page_url.join(link).ok()?;
+        if url.origin() != page_url.origin() {
+            return None;
+        }
+
+        let path = url.path().strip_prefix(&self.base_path)?;
+        let path = percent_decode_str(path).decode_utf8_lossy().to_string();
+
+        let mut page = Page::Latest;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "cursor" => page = Cursor::decode(&value, &self.cursor_secret).ok()?.page,
+                // Other parameters, like limits or sort overrides, aren't rendered.
+                _ => return None,
+            }
+        }
+
+        let target = self.targets.get(&(path, page))?;
+        let mut rewritten = if link.contains("://") {
+            format!("{}{}", self.base_url, target)
+        } else {
+            format!("{}{}", self.base_path, target)
+        };
+        if let Some(fragment) = url.fragment() {
+            rewritten.push('#');
+            rewritten.push_str(fragment);
+        }
+
+        Some(rewritten)
+    }
+
+    /// Rewrite every link in quoted HTML attributes and sitemap `<loc>` elements.
+    fn rewrite_all(&self, body: &str, page_url: &Url) -> String {
+        const DELIMITERS: [(&str, &str); 3] = [("=\"", "\""), ("='", "'"), ("<loc>", "</loc>")];
+
+        let mut rewritten = String::with_capacity(body.len());
+        let mut rest = body;
+        loop {
+            let next = DELIMITERS
+                .iter()
+                .filter_map(|&(open, close)| Some((rest.find(open)? + open.len(), close)))
+                .min_by_key(|&(start, _)| start);
+            let (start, close) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            let (before, after) = rest.split_at(start);
+            rewritten.push_str(before);
+            let end = after.find(close).unwrap_or(after.len());
+            let (value, after) = after.split_at(end);
+            match self.rewrite(&unescape(value), page_url) {
+                Some(link) => rewritten.push_str(&link),
+                None => rewritten.push_str(value),
+            }
+            rest = after;
+        }
+        rewritten.push_str(rest);
+
+        rewritten
+    }
+}
+
+/// Undo the escaping Tera and the sitemap writer apply to links.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#x2F;", "/")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Path of the file a pretty URL path is written to.
+fn output_path(output_dir: &Path, target: &str) -> Option<PathBuf> {
+    let mut path = output_dir.to_path_buf();
+    for segment in target.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match Path::new(segment.as_ref()).components().collect::<Vec<_>>()[..] {
+            [Component::Normal(segment)] => path.push(segment),
+            _ => return None,
+        }
+    }
+    if target.ends_with('/') {
+        path.push("index.html");
+    }
+
+    Some(path)
+}
+
+/// Collect every gallery page of a gallery, following the pagination the gallery handler uses.
+async fn collect_gallery_pages(
+    state: &State,
+    conn: &mut sqlx::PgConnection,
+    links: &mut LinkMap,
+    pages: &mut Vec<StaticPage>,
+    path: &str,
+    target_prefix: &str,
+    tagged: Option<Vec<String>>,
+) -> Result<()> {
+    let sort = state.args.default_sort;
+    let limit = state.args.default_photos_per_page.into();
+
+    let mut page = Page::Latest;
+    let mut previous_target: Option<String> = None;
+    for number in 1.. {
+        let target = match number {
+            1 => format!("{}/", target_prefix),
+            _ => format!("{}/page/{}/", target_prefix, number),
+        };
+        let source = match page {
+            Page::Latest => path.to_string(),
+            page => format!(
+                "{}?cursor={}",
+                path,
+                Cursor::new(page, sort, &tagged).encode(&state.cursor_secret)
+            ),
+        };
+
+        let photos = conn
+            .get_photo_page(limit, page, sort, &tagged, Published::OnlyPublished)
+            .await?;
+        let (newer, older) = conn
+            .get_photo_pagination_ids(&photos, sort, &tagged, Published::OnlyPublished)
+            .await?;
+
+        links.insert(path, page, &target);
+        if let (Some(newer), Some(previous_target)) = (newer, &previous_target) {
+            links.insert(path, Page::After(newer), previous_target);
+        }
+        pages.push(StaticPage {
+            source,
+            target: target.clone(),
+        });
+
+        match older {
+            Some(older) => page = Page::Before(older),
+            None => {
+                links.insert(path, Page::Oldest, &target);
+                break;
+            },
+        }
+        previous_target = Some(target);
+    }
+
+    Ok(())
+}
+
+/// Render every published gallery page, tag page, photo page and sitemap into `output_dir`,
+/// using pretty URLs that work on plain static hosting.
+///
+/// Pages are rendered by the regular handlers without authentication, so only published photos
+/// end up in the output.
+#[instrument(skip_all, fields(output_dir = %output_dir.display()))]
+pub async fn render(state: &State, output_dir: &Path) -> Result<()> {
+    let mut conn = state.db.acquire().await?;
+    let mut links = LinkMap::new(state)?;
+    let mut pages = Vec::new();
+
+    collect_gallery_pages(state, &mut conn, &mut links, &mut pages, "/", "", None).await?;
+
+    for (tag, _) in conn
+        .get_photo_tags_with_counts(&None, Published::OnlyPublished)
+        .await?
+    {
+        if tag.contains('/') || tag == "." || tag == ".." {
+            warn!(tag = %tag, "Skipping tag that can't be a static directory name");
+            continue;
+        }
+
+        let path = format!("/tagged/{}", utf8_percent_encode(&tag, NON_ALPHANUMERIC));
+        collect_gallery_pages(
+            state,
+            &mut conn,
+            &mut links,
+            &mut pages,
+            &path,
+            &path,
+            Some(vec![tag]),
+        )
+        .await?;
+    }
+
+    let photo_ids = conn.get_all_photo_ids(Published::OnlyPublished).await?;
+    for photo in conn
+        .get_photos_by_ids(&photo_ids, Published::OnlyPublished)
+        .await?
+    {
+        // Static hosting can't negotiate languages, so only the language prefixes pick titles.
+        let prefixes = std::iter::once(String::new()).chain(
+            photo
+                .titles
+                .keys()
+                .filter(|language| is_url_safe_language(language))
+                .map(|language| format!("/{}", language)),
+        );
+        for prefix in prefixes {
+            for path in [
+                format!("{}/photo/{}", prefix, photo.id),
+                format!("{}/photo/{}/multi", prefix, photo.id),
+            ] {
+                let target = format!("{}/", path);
+                links.insert(&path, Page::Latest, &target);
+                pages.push(StaticPage {
+                    source: path,
+                    target,
+                });
+            }
+        }
+    }
+
+    let child_sitemaps =
+        crate::web::html::child_sitemap_paths(state, &mut conn, Published::OnlyPublished).await?;
+    for path in std::iter::once("/sitemap.xml".to_string()).chain(child_sitemaps) {
+        links.insert(&path, Page::Latest, &path);
+        pages.push(StaticPage {
+            source: path.clone(),
+            target: path,
+        });
+    }
+    drop(conn);
+
+    let mut app = tide::with_state(state.clone());
+    crate::web::mount(&mut app);
+
+    for page in &pages {
+        let path = output_path(output_dir, &page.target)
+            .with_context(|| format!("Invalid output path for {}", page.target))?;
+
+        let url = Url::parse(RENDER_ORIGIN)?.join(&page.source)?;
+        let req = tide::http::Request::new(tide::http::Method::Get, url);
+        let mut res: tide::http::Response =
+            app.respond(req).await.map_err(|err| err.into_inner())?;
+        if res.status() != tide::http::StatusCode::Ok {
+            bail!(
+                "Rendering {} failed with status {}",
+                page.source,
+                res.status()
+            );
+        }
+        let body = res.body_string().await.map_err(|err| err.into_inner())?;
+
+        let page_url = Url::parse(&format!("{}{}", links.base_url, page.source))?;
+        let body = links.rewrite_all(&body, &page_url);
+
+        if let Some(parent) = path.parent() {
+            async_std::fs::create_dir_all(parent).await?;
+        }
+        async_std::fs::write(&path, body).await?;
+        info!(page = %page.source, path = %path.display(), "Rendered page");
+    }
+
+    info!(pages = pages.len(), "Rendered static site");
+
+    Ok(())
+}
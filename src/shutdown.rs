@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::task::JoinHandle;
+use futures_lite::StreamExt;
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use tide::{Middleware, Next, Request};
+use tracing::{info, warn};
+
+/// Middleware keeping track of how many requests are currently being handled, so that shutdown
+/// can wait for them to finish.
+#[derive(Clone, Debug, Default)]
+pub struct InFlightRequests {
+    count: Arc<AtomicUsize>,
+}
+
+/// Decrements the in-flight counter when dropped, so that requests whose handlers panic or get
+/// cancelled are still accounted for.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of requests currently being handled.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Wait for all in-flight requests to finish, giving up after `deadline`.
+    ///
+    /// Returns whether all requests finished before the deadline.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        let wait = async {
+            while self.count() > 0 {
+                async_std::task::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        match async_std::future::timeout(deadline, wait).await {
+            Ok(()) => true,
+            Err(_) => {
+                warn!(
+                    requests.in_flight = self.count(),
+                    "Shutdown deadline reached with requests still in flight"
+                );
+                false
+            },
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for InFlightRequests {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let _guard = InFlightGuard(&self.count);
+
+        Ok(next.run(req).await)
+    }
+}
+
+/// Wait for the background `tasks` doing `what` to finish, giving up after `deadline`.
+///
+/// Returns whether all tasks finished before the deadline.
+pub async fn join_tasks(what: &str, tasks: Vec<JoinHandle<()>>, deadline: Duration) -> bool {
+    let wait = async {
+        for task in tasks {
+            task.await;
+        }
+    };
+
+    match async_std::future::timeout(deadline, wait).await {
+        Ok(()) => true,
+        Err(_) => {
+            warn!(
+                tasks = what,
+                "Shutdown deadline reached with tasks still running"
+            );
+            false
+        },
+    }
+}
+
+/// Wait until the process receives SIGTERM or SIGINT.
+pub async fn wait_for_signal() -> std::io::Result<()> {
+    let mut signals = Signals::new(&[SIGTERM, SIGINT])?;
+    if let Some(signal) = signals.next().await {
+        info!(signal, "Received shutdown signal");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::db::photos::PhotoId;
+use crate::db::stats::StatsEvent;
+
+/// Number of visitor fingerprints to remember per day, beyond which new visitors aren't counted
+/// so that a flood of requests can neither inflate counts nor exhaust memory.
+const MAX_FINGERPRINTS: usize = 1_000_000;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY)
+}
+
+#[derive(Debug)]
+struct Today {
+    day: u64,
+    /// Random salt for the day, never stored, so that fingerprints can't be linked to addresses
+    /// or across days.
+    salt: [u8; 32],
+    fingerprints: HashSet<[u8; 16]>,
+}
+
+impl Today {
+    fn new(day: u64) -> Self {
+        Today {
+            day,
+            salt: rand::random(),
+            fingerprints: HashSet::new(),
+        }
+    }
+}
+
+/// Deduplicates views and favorites per visitor, photo and day without storing IP addresses.
+///
+/// Visitors are only remembered as salted hashes in memory, and the salt and hashes are thrown
+/// away when the day changes.
+#[derive(Clone, Debug)]
+pub struct DailyVisitors {
+    today: Arc<Mutex<Today>>,
+}
+
+impl Default for DailyVisitors {
+    fn default() -> Self {
+        DailyVisitors {
+            today: Arc::new(Mutex::new(Today::new(current_day()))),
+        }
+    }
+}
+
+impl DailyVisitors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether this is the first time today that the client caused the event on the photo.
+    pub fn first_today(&self, client: IpAddr, photo_id: PhotoId, event: StatsEvent) -> bool {
+        let mut today = self.today.lock().expect("daily visitors lock was poisoned");
+
+        let day = current_day();
+        if today.day != day {
+            *today = Today::new(day);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(today.salt);
+        match client {
+            IpAddr::V4(address) => hasher.update(address.octets()),
+            IpAddr::V6(address) => hasher.update(address.octets()),
+        }
+        hasher.update(photo_id.to_be_bytes());
+        hasher.update([event as u8]);
+        let mut fingerprint = [0; 16];
+        fingerprint.copy_from_slice(&hasher.finalize()[..16]);
+
+        if today.fingerprints.len() >= MAX_FINGERPRINTS {
+            return false;
+        }
+        today.fingerprints.insert(fingerprint)
+    }
+}
@@ -53,6 +53,13 @@ pub(crate) fn init() -> Result<()> {
     Ok(())
 }
 
+/// Shut down the global tracer provider, flushing any spans still buffered in the batch exporter.
+pub(crate) async fn shutdown() {
+    // The batch span processor blocks on the exporter while shutting down, so keep it off of the
+    // async executor threads.
+    async_std::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
 fn env_filter_merge_from_environment(
     default_directives: &'static str,
     env_var: &'static str,
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use async_std::channel::{Receiver, Sender};
+use async_std::task::JoinHandle;
+use bytes::Bytes;
+use futures_lite::FutureExt;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use tracing::{error, info, instrument};
+
+use rusty_peanuts_media::palette::extract_palette;
+use rusty_peanuts_media::perceptual_hash::dhash;
+use rusty_peanuts_media::placeholder::Placeholders;
+use rusty_peanuts_media::storage::Storage;
+use rusty_peanuts_media::transcode::{decode_image, transcode_and_store_photo, Format};
+use rusty_peanuts_media::xmp::get_metadata;
+
+use crate::db::photos::{PhotoId, PhotoProvider, Published, SortMode};
+use crate::models::webhooks::WebhookEvent;
+use crate::webhooks::WebhookDispatcher;
+
+/// How long to remember the outcome of finished jobs for status polling.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+pub type JobId = String;
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UploadJobStatus {
+    Queued,
+    Processing,
+    Done { photo_id: PhotoId },
+    Failed { reason: String },
+}
+
+impl UploadJobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            UploadJobStatus::Done { .. } | UploadJobStatus::Failed { .. }
+        )
+    }
+}
+
+#[derive(Debug)]
+struct UploadJob {
+    id: JobId,
+    file_stem: String,
+    data: Bytes,
+}
+
+/// Everything the workers need to process uploads.
+struct Worker {
+    db: PgPool,
+    storage: Storage,
+    static_host: String,
+    formats: Vec<Format>,
+    webhooks: Option<WebhookDispatcher>,
+}
+
+/// Bounded queue of uploaded photos waiting to be transcoded by a fixed number of workers.
+#[derive(Clone, Debug)]
+pub struct UploadQueue {
+    sender: Sender<UploadJob>,
+    statuses: Arc<Mutex<HashMap<JobId, (UploadJobStatus, Instant)>>>,
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl UploadQueue {
+    /// Start `workers` background workers storing photo variants in `storage`.
+    ///
+    /// * `capacity`: How many uploads can be waiting to be processed before new ones are
+    ///   rejected.
+    /// * `static_host`: Base URL that the root of `storage` is served from.
+    /// * `formats`: Formats to transcode photos to, in addition to JPEG.
+    /// * `webhooks`: Dispatcher to wake when webhooks are queued for created photos.
+    pub fn start(
+        workers: usize,
+        capacity: usize,
+        db: PgPool,
+        storage: Storage,
+        static_host: String,
+        formats: Vec<Format>,
+        webhooks: Option<WebhookDispatcher>,
+    ) -> Self {
+        let (sender, receiver) = async_std::channel::bounded(capacity.max(1));
+        let queue = UploadQueue {
+            sender,
+            statuses: Default::default(),
+            workers: Default::default(),
+        };
+
+        let worker = Arc::new(Worker {
+            db,
+            storage,
+            static_host,
+            formats,
+            webhooks,
+        });
+        let handles = (0..workers.max(1))
+            .map(|_| async_std::task::spawn(queue.clone().run(receiver.clone(), worker.clone())))
+            .collect();
+        *queue
+            .workers
+            .lock()
+            .expect("upload worker lock was poisoned") = handles;
+
+        queue
+    }
+
+    /// Stop taking uploads and wait for the workers to process the queued ones, giving up after
+    /// `deadline`.
+    ///
+    /// Returns whether all queued uploads were processed before the deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.sender.close();
+        let workers = std::mem::take(
+            &mut *self
+                .workers
+                .lock()
+                .expect("upload worker lock was poisoned"),
+        );
+
+        crate::shutdown::join_tasks("uploads", workers, deadline).await
+    }
+
+    async fn run(self, receiver: Receiver<UploadJob>, worker: Arc<Worker>) {
+        while let Ok(job) = receiver.recv().await {
+            let id = job.id.clone();
+            let file_stem = job.file_stem.clone();
+            self.set_status(&id, UploadJobStatus::Processing);
+
+            // The encoder panics on errors, which mustn't take down the worker.
+            let status = match AssertUnwindSafe(worker.process(job)).catch_unwind().await {
+                Ok(Ok(photo_id)) => UploadJobStatus::Done { photo_id },
+                Ok(Err(err)) => {
+                    let reason = format!("{:#}", err);
+                    error!(job.id = %id, exception.message = %reason, "Upload failed");
+                    UploadJobStatus::Failed { reason }
+                },
+                Err(_) => {
+                    error!(job.id = %id, "Upload processing panicked");
+                    UploadJobStatus::Failed {
+                        reason: "processing the photo panicked".to_string(),
+                    }
+                },
+            };
+
+            // The photo exists by now if it was stored, so the file stem is no longer needed.
+            let released = match worker.db.acquire().await {
+                Ok(mut conn) => conn.release_photo_file_stem(&file_stem).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = released {
+                error!(job.id = %id, exception.message = %err, "Failed to release file stem");
+            }
+
+            self.set_status(&id, status);
+        }
+    }
+
+    fn set_status(&self, id: &str, status: UploadJobStatus) {
+        let now = Instant::now();
+        let mut statuses = self
+            .statuses
+            .lock()
+            .expect("upload status lock was poisoned");
+        statuses.retain(|_, (status, updated)| {
+            !status.is_finished() || now.duration_since(*updated) < FINISHED_JOB_RETENTION
+        });
+        statuses.insert(id.to_string(), (status, now));
+    }
+
+    /// Queue an uploaded photo for processing.
+    ///
+    /// The file stem must have been claimed with `claim_photo_file_stem`, it's released once the
+    /// upload was processed.
+    ///
+    /// Returns `None` if the queue is full or shutting down.
+    pub fn submit(&self, file_stem: String, data: Bytes) -> Option<JobId> {
+        let id = format!("{:016x}", rand::random::<u64>());
+        self.set_status(&id, UploadJobStatus::Queued);
+
+        let job = UploadJob {
+            id: id.clone(),
+            file_stem,
+            data,
+        };
+        match self.sender.try_send(job) {
+            Ok(()) => Some(id),
+            Err(_) => {
+                self.statuses
+                    .lock()
+                    .expect("upload status lock was poisoned")
+                    .remove(&id);
+                None
+            },
+        }
+    }
+
+    /// Get the status of a queued upload.
+    pub fn status(&self, id: &str) -> Option<UploadJobStatus> {
+        self.statuses
+            .lock()
+            .expect("upload status lock was poisoned")
+            .get(id)
+            .map(|(status, _)| status.clone())
+    }
+}
+
+impl Worker {
+    #[instrument(skip_all, fields(job.id = %job.id, file_stem = %job.file_stem))]
+    async fn process(&self, job: UploadJob) -> Result<PhotoId> {
+        let UploadJob {
+            file_stem, data, ..
+        } = job;
+
+        let (image, metadata, placeholders, palette, perceptual_hash) =
+            async_std::task::spawn_blocking(move || -> Result<_> {
+                let (image, format) = decode_image(std::io::Cursor::new(&data))
+                    .context("Failed to decode uploaded photo")?;
+                if format != image::ImageFormat::Tiff {
+                    bail!("Unsupported format: {:?}", format);
+                }
+
+                let metadata = get_metadata(std::io::Cursor::new(&data))
+                    .context("Failed to get XMP metadata from uploaded photo")?;
+                let placeholders = Placeholders::new(&image);
+                let palette = extract_palette(&image);
+                let perceptual_hash = dhash(&image);
+                Ok((image, metadata, placeholders, palette, perceptual_hash))
+            })
+            .await?;
+        info!("Decoded uploaded photo");
+
+        // The file stem is claimed, but a photo could have been created without uploading it,
+        // and storing the transcoded photo would overwrite that one's files.
+        let mut conn = self.db.acquire().await?;
+        if conn
+            .get_photo_by_file_stem(&file_stem, Published::All)
+            .await?
+            .is_some()
+        {
+            bail!("Photo with file stem {} already exists", file_stem);
+        }
+        drop(conn);
+
+        let sources = transcode_and_store_photo(
+            &self.storage,
+            &self.static_host,
+            &file_stem,
+            image,
+            &self.formats,
+        )
+        .await
+        .context("Failed to store transcoded photo")?;
+        info!("Stored transcoded photos");
+
+        let mut conn = self.db.acquire().await?;
+        let new_photo = crate::models::photos::Photo {
+            file_stem,
+            title: metadata.title,
+            titles: metadata.titles,
+            caption: metadata.caption,
+            alt_text: metadata.alt_text,
+            taken_timestamp: Some(metadata.create_date),
+            tags: metadata.tags,
+            sources,
+            published: false,
+            blurhash: Some(placeholders.blurhash),
+            lqip: Some(placeholders.lqip),
+            palette,
+            perceptual_hash: Some(perceptual_hash),
+            ..Default::default()
+        };
+        let photo_id = conn.insert_photo(&new_photo).await?;
+        info!(photo.id = photo_id, "Created photo from upload");
+
+        if let Some((photo, _, _)) = conn
+            .get_photo_by_id(photo_id, SortMode::Id, Published::All)
+            .await?
+        {
+            crate::webhooks::emit(
+                &mut conn,
+                self.webhooks.as_ref(),
+                WebhookEvent::PhotoCreated,
+                &photo,
+            )
+            .await;
+        }
+
+        Ok(photo_id)
+    }
+}
@@ -0,0 +1,230 @@
+use serde::Deserialize;
+use serde_json::Value;
+use tide::{Request, Response};
+use tracing::{info, instrument, warn};
+
+use crate::activitypub::{authenticate, documents, object_id, Federation, ACTIVITY_JSON};
+use crate::db::activitypub::ActivityPubProvider;
+use crate::db::photos::{Anchor, Page, PhotoId, PhotoProvider, Published, SortMode};
+use crate::web::api::rate_limit::RateLimiter;
+
+/// Number of photos per outbox page.
+const OUTBOX_PAGE_SIZE: i64 = 20;
+/// Largest activity the inbox accepts, in bytes.
+const MAX_ACTIVITY_SIZE: usize = 1024 * 1024;
+
+pub(super) fn mount(app: &mut tide::Server<crate::State>) {
+    app.at("/.well-known/webfinger").get(webfinger);
+    app.at("/activitypub/actor").get(actor);
+    app.at("/activitypub/outbox").get(outbox);
+    app.at("/activitypub/followers").get(followers);
+    // Authenticating activities can mean fetching their actors, so don't let anyone send many.
+    app.at("/activitypub/inbox")
+        .with(RateLimiter::inbox())
+        .post(inbox);
+    app.at("/activitypub/photo/:photo_id").get(photo_note);
+}
+
+/// The federation state, which is always set when these routes are mounted.
+fn federation(req: &Request<crate::State>) -> &Federation {
+    req.state()
+        .activitypub
+        .as_ref()
+        .expect("ActivityPub routes mounted without federation")
+}
+
+fn activity_response(document: &Value) -> tide::Result<Response> {
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::Body::from_json(document)?)
+        .content_type(ACTIVITY_JSON)
+        .build())
+}
+
+#[derive(Deserialize)]
+struct WebFingerQueryParams {
+    resource: String,
+}
+
+#[instrument(skip_all)]
+async fn webfinger(req: Request<crate::State>) -> tide::Result<Response> {
+    let actor = &federation(&req).actor;
+    let query: WebFingerQueryParams = req.query()?;
+
+    let resource = query.resource.as_str();
+    if !(resource.eq_ignore_ascii_case(&actor.acct())
+        || resource == actor.id()
+        || resource.trim_end_matches('/') == actor.base_url())
+    {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::Body::from_json(&documents::webfinger(actor))?)
+        .content_type("application/jrd+json")
+        .header("Access-Control-Allow-Origin", "*")
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn actor(req: Request<crate::State>) -> tide::Result<Response> {
+    let args = &req.state().args;
+    activity_response(&documents::actor(
+        &federation(&req).actor,
+        &args.site_name,
+        args.author.as_deref(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct OutboxQueryParams {
+    #[serde(default)]
+    page: bool,
+    max_id: Option<PhotoId>,
+}
+
+#[instrument(skip_all)]
+async fn outbox(req: Request<crate::State>) -> tide::Result<Response> {
+    let actor = &federation(&req).actor;
+    let mut conn = req.state().db.acquire().await?;
+    let query: OutboxQueryParams = req.query()?;
+
+    if !query.page {
+        let total = conn
+            .get_all_photo_ids(Published::OnlyPublished)
+            .await?
+            .len();
+        return activity_response(&documents::outbox(actor, total));
+    }
+
+    let (page, page_id) = match query.max_id {
+        Some(max_id) => (
+            Page::Before(Anchor::id(max_id)),
+            format!("{}?page=true&max_id={}", actor.outbox(), max_id),
+        ),
+        None => (Page::Latest, format!("{}?page=true", actor.outbox())),
+    };
+    let photos = conn
+        .get_photo_page(
+            OUTBOX_PAGE_SIZE,
+            page,
+            SortMode::Id,
+            &None,
+            Published::OnlyPublished,
+        )
+        .await?;
+
+    let next_max_id = match photos.last() {
+        Some(last) if photos.len() as i64 == OUTBOX_PAGE_SIZE => Some(last.id),
+        _ => None,
+    };
+    activity_response(&documents::outbox_page(
+        actor,
+        &page_id,
+        &photos,
+        next_max_id,
+    ))
+}
+
+#[instrument(skip_all)]
+async fn followers(req: Request<crate::State>) -> tide::Result<Response> {
+    let mut conn = req.state().db.acquire().await?;
+    let total = conn.count_activitypub_followers().await?;
+
+    activity_response(&documents::followers(&federation(&req).actor, total))
+}
+
+#[instrument(skip_all)]
+async fn photo_note(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let photo_id: i32 = req.param("photo_id")?.parse()?;
+    match conn
+        .get_photo_by_id(photo_id, state.args.default_sort, Published::OnlyPublished)
+        .await?
+    {
+        Some((photo, _, _)) => activity_response(&documents::note(&federation(&req).actor, &photo)),
+        None => Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    }
+}
+
+#[instrument(skip_all)]
+async fn inbox(mut req: Request<crate::State>) -> tide::Result<Response> {
+    if req.len().map_or(false, |len| len > MAX_ACTIVITY_SIZE) {
+        return Ok(Response::builder(tide::http::StatusCode::PayloadTooLarge).build());
+    }
+    let body = req.body_bytes().await?;
+    if body.len() > MAX_ACTIVITY_SIZE {
+        return Ok(Response::builder(tide::http::StatusCode::PayloadTooLarge).build());
+    }
+    let activity: Value = match serde_json::from_slice(&body) {
+        Ok(activity) => activity,
+        Err(_) => return Ok(Response::builder(tide::http::StatusCode::BadRequest).build()),
+    };
+
+    // Deleted accounts can't be verified as their keys are gone with them, and they're
+    // announced to every server that ever saw them, so don't bother.
+    let actor_id = activity.get("actor").and_then(object_id);
+    if activity["type"] == "Delete"
+        && actor_id.is_some()
+        && object_id(&activity["object"]) == actor_id
+    {
+        return Ok(Response::builder(tide::http::StatusCode::Accepted).build());
+    }
+
+    let federation = federation(&req);
+    let remote = match authenticate(
+        &federation.actor,
+        &federation.keys,
+        req.as_ref(),
+        &body,
+        &activity,
+    )
+    .await
+    {
+        Ok(remote) => remote,
+        Err(err) => {
+            warn!(exception.message = %err, "Rejected inbox activity");
+            return Ok(Response::builder(tide::http::StatusCode::Unauthorized).build());
+        },
+    };
+
+    let mut conn = req.state().db.acquire().await?;
+    match activity["type"].as_str() {
+        Some("Follow")
+            if object_id(&activity["object"]) == Some(federation.actor.id().as_str()) =>
+        {
+            let follow_id = match activity["id"].as_str() {
+                Some(follow_id) => follow_id,
+                None => return Ok(Response::builder(tide::http::StatusCode::BadRequest).build()),
+            };
+
+            conn.upsert_activitypub_follower(
+                &remote.id,
+                &remote.inbox,
+                remote.shared_inbox.as_deref(),
+                follow_id,
+            )
+            .await?;
+            // Follows are accepted in the follower's own inbox rather than the shared one.
+            federation
+                .send(
+                    &mut conn,
+                    &remote.inbox,
+                    &documents::accept(&federation.actor, &activity),
+                )
+                .await?;
+            info!(actor = %remote.id, "Accepted follow");
+        },
+
+        Some("Undo") if activity["object"]["type"] == "Follow" => {
+            if conn.delete_activitypub_follower(&remote.id).await? {
+                info!(actor = %remote.id, "Removed follower");
+            }
+        },
+
+        _ => {},
+    }
+
+    Ok(Response::builder(tide::http::StatusCode::Accepted).build())
+}
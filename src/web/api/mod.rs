@@ -1,3 +1,4 @@
+pub mod rate_limit;
 #[macro_use]
 pub mod utils;
 pub mod v1;
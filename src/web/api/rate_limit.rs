@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tide::{Middleware, Next, Request, Response};
+use tracing::{info, warn};
+
+/// Upper bound for how long a client can be locked out, no matter how many times it has been
+/// locked out before.
+const MAX_LOCKOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of tracked clients above which idle entries get pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Request extension that handlers use to report that a request tried to authenticate with an
+/// invalid secret key.
+#[derive(Clone, Debug, Default)]
+pub struct FailedAuthentication(Arc<AtomicBool>);
+
+impl FailedAuthentication {
+    pub fn set(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    failures: u32,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Bucket {
+            tokens: burst,
+            last_refill: now,
+            failures: 0,
+            lockouts: 0,
+            locked_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant, per_second: f64, burst: f64) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(burst);
+        self.last_refill = now;
+    }
+
+    fn is_idle(&self, now: Instant, burst: f64) -> bool {
+        self.tokens >= burst
+            && self.failures == 0
+            && self.locked_until.map_or(true, |until| until <= now)
+    }
+}
+
+/// How many requests a client can make.
+#[derive(Clone, Copy, Debug)]
+struct Limits {
+    /// Sustained number of requests per second.
+    per_second: f64,
+    /// Number of requests that can be made in a burst.
+    burst: f64,
+}
+
+impl Limits {
+    /// Limits for the API, `None` if API rate limiting is disabled.
+    fn api(args: &crate::Args) -> Option<Limits> {
+        match args.rate_limit_per_minute {
+            0 => None,
+            per_minute => Some(Limits {
+                per_second: f64::from(per_minute) / 60.0,
+                burst: f64::from(args.rate_limit_burst),
+            }),
+        }
+    }
+
+    /// Limits for the ActivityPub inbox, `None` if inbox rate limiting is disabled.
+    fn inbox(args: &crate::Args) -> Option<Limits> {
+        match args.activitypub_inbox_rate_limit_per_minute {
+            0 => None,
+            per_minute => Some(Limits {
+                per_second: f64::from(per_minute) / 60.0,
+                burst: f64::from(args.activitypub_inbox_rate_limit_burst),
+            }),
+        }
+    }
+
+    /// Limits for favoriting photos, `None` if favorite rate limiting is disabled.
+    fn favorites(args: &crate::Args) -> Option<Limits> {
+        match args.favorite_rate_limit_per_hour {
+            0 => None,
+            per_hour => Some(Limits {
+                per_second: f64::from(per_hour) / 3600.0,
+                burst: f64::from(args.favorite_rate_limit_burst),
+            }),
+        }
+    }
+
+    /// Limits for posting comments, `None` if comment rate limiting is disabled.
+    fn comments(args: &crate::Args) -> Option<Limits> {
+        match args.comment_rate_limit_per_hour {
+            0 => None,
+            per_hour => Some(Limits {
+                per_second: f64::from(per_hour) / 3600.0,
+                burst: f64::from(args.comment_rate_limit_burst),
+            }),
+        }
+    }
+}
+
+/// Token-bucket rate limiter keyed by client IP address.
+///
+/// Clients that repeatedly fail to authenticate get locked out, with the lockout duration
+/// doubling every time it happens again.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+    limits: fn(&crate::Args) -> Option<Limits>,
+}
+
+impl RateLimiter {
+    /// Rate limiter for API requests.
+    pub fn new() -> Self {
+        RateLimiter {
+            buckets: Default::default(),
+            limits: Limits::api,
+        }
+    }
+
+    /// Rate limiter for posting comments, separate from and usually stricter than the API's.
+    pub fn comments() -> Self {
+        RateLimiter {
+            buckets: Default::default(),
+            limits: Limits::comments,
+        }
+    }
+
+    /// Rate limiter for favoriting photos.
+    pub fn favorites() -> Self {
+        RateLimiter {
+            buckets: Default::default(),
+            limits: Limits::favorites,
+        }
+    }
+
+    /// Rate limiter for activities sent to the ActivityPub inbox.
+    pub fn inbox() -> Self {
+        RateLimiter {
+            buckets: Default::default(),
+            limits: Limits::inbox,
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the IP address of the client that sent a request, as seen by the trusted proxy if any.
+pub(crate) fn client_ip(req: &Request<crate::State>) -> Option<IpAddr> {
+    let address = match &req.state().args.trusted_proxy_header {
+        // The right-most entry is the one added by the trusted proxy itself, anything before it
+        // could have been supplied by the client.
+        Some(header) => req
+            .header(header.as_str())
+            .and_then(|values| values.last().as_str().rsplit(',').next())
+            .map(|value| value.trim().to_string()),
+        None => req.peer_addr().map(|addr| addr.to_string()),
+    }?;
+
+    address
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| address.parse::<IpAddr>())
+        .ok()
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    Response::builder(tide::http::StatusCode::TooManyRequests)
+        .header("Retry-After", seconds.to_string())
+        .build()
+}
+
+#[tide::utils::async_trait]
+impl Middleware<crate::State> for RateLimiter {
+    async fn handle(
+        &self,
+        mut req: Request<crate::State>,
+        next: Next<'_, crate::State>,
+    ) -> tide::Result {
+        let args = req.state().args.clone();
+        let Limits { per_second, burst } = match (self.limits)(&args) {
+            Some(limits) => limits,
+            None => return Ok(next.run(req).await),
+        };
+
+        // Without the client's address, requests are limited by the address of whoever connected,
+        // e.g. the proxy, and rejected when even that is unknown.
+        let peer_ip = || {
+            req.peer_addr()
+                .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                .map(|addr| addr.ip())
+        };
+        let ip = match client_ip(&req).or_else(peer_ip) {
+            Some(ip) => ip,
+            None => {
+                warn!("Couldn't determine client IP address, rejecting rate limited request");
+                return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                    .body(tide::convert::json!({
+                        "reason": "Couldn't determine the client address.",
+                    }))
+                    .build());
+            },
+        };
+
+        {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().expect("rate limiter lock was poisoned");
+            if buckets.len() > PRUNE_THRESHOLD {
+                buckets.retain(|_, bucket| {
+                    bucket.refill(now, per_second, burst);
+                    !bucket.is_idle(now, burst)
+                });
+            }
+
+            let bucket = buckets.entry(ip).or_insert_with(|| Bucket::new(burst, now));
+
+            if let Some(until) = bucket.locked_until {
+                if until > now {
+                    return Ok(too_many_requests(until - now));
+                }
+                bucket.locked_until = None;
+            }
+
+            bucket.refill(now, per_second, burst);
+            if bucket.tokens < 1.0 {
+                let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / per_second);
+                return Ok(too_many_requests(retry_after));
+            }
+            bucket.tokens -= 1.0;
+        }
+
+        let failed_authentication = FailedAuthentication::default();
+        req.set_ext(failed_authentication.clone());
+
+        let res = next.run(req).await;
+
+        if failed_authentication.is_set() || res.status() == tide::http::StatusCode::Forbidden {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().expect("rate limiter lock was poisoned");
+            let bucket = buckets.entry(ip).or_insert_with(|| Bucket::new(burst, now));
+
+            bucket.failures += 1;
+            if bucket.failures >= args.rate_limit_max_failures {
+                let lockout = Duration::from_secs(args.rate_limit_lockout_seconds)
+                    .checked_mul(1 << bucket.lockouts.min(16))
+                    .unwrap_or(MAX_LOCKOUT)
+                    .min(MAX_LOCKOUT);
+                info!(
+                    client.ip = %ip,
+                    lockout.seconds = lockout.as_secs(),
+                    "Locking out client after repeated authentication failures"
+                );
+
+                bucket.failures = 0;
+                bucket.lockouts += 1;
+                bucket.locked_until = Some(now + lockout);
+            }
+        }
+
+        Ok(res)
+    }
+}
@@ -2,6 +2,7 @@ use sqlx::PgConnection;
 use tide::Request;
 
 use crate::db::secret_keys::SecretKeyProvider;
+use crate::web::api::rate_limit::FailedAuthentication;
 
 pub async fn validate_secret_key(
     req: &Request<crate::State>,
@@ -18,6 +19,10 @@ pub async fn validate_secret_key(
         return Ok(Some(true));
     }
 
+    if let Some(failed_authentication) = req.ext::<FailedAuthentication>() {
+        failed_authentication.set();
+    }
+
     Ok(Some(false))
 }
 
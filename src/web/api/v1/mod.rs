@@ -1,25 +1,355 @@
-use tide::{Request, Response};
+use std::convert::Infallible;
+
+use async_std::io::ReadExt;
+use serde::Deserialize;
+use sqlx::PgConnection;
+use tide::http::Method;
+use tide::{Endpoint, Request, Response};
 use tracing::{info, instrument};
 
-use crate::db::photos::{PhotoProvider, Published};
+use crate::db::comments::{CommentProvider, CommentStatus};
+use crate::db::photos::{Page, PhotoProvider, Published, SortMode};
+use crate::db::stats::StatsProvider;
+use crate::db::webhooks::WebhookProvider;
+use crate::models::photos::Photo;
+use crate::models::webhooks::{WebhookEvent, WebhookRegistration};
+use crate::web::api::rate_limit::RateLimiter;
 use crate::web::api::utils::validate_secret_key;
-use rusty_peanuts_api_structs::PhotoPayload;
+use crate::web::cursor::Cursor;
+use rusty_peanuts_api_structs::{PerceptualHash, PhotoPayload};
+use rusty_peanuts_media::storage::is_safe_name;
+
+pub mod openapi;
+
+/// Something API routes can be registered on.
+///
+/// Lets the route table be used both for mounting the API and for describing it.
+trait RouteTable {
+    fn add<E: Endpoint<crate::State>>(&mut self, method: Method, path: &'static str, endpoint: E);
+}
+
+impl RouteTable for tide::Route<'_, crate::State> {
+    fn add<E: Endpoint<crate::State>>(&mut self, method: Method, path: &'static str, endpoint: E) {
+        self.at(path).method(method, endpoint);
+    }
+}
+
+struct RouteRecorder(Vec<(Method, &'static str)>);
+
+impl RouteTable for RouteRecorder {
+    fn add<E: Endpoint<crate::State>>(&mut self, method: Method, path: &'static str, _: E) {
+        self.0.push((method, path));
+    }
+}
 
 pub(super) fn mount(mut route: tide::Route<crate::State>) {
-    route.at("/photos").post(create_photo);
+    route.with(RateLimiter::new());
+
+    register_routes(&mut route);
+}
+
+fn register_routes(routes: &mut impl RouteTable) {
+    routes.add(Method::Get, "/openapi.json", get_openapi_spec);
+
+    routes.add(Method::Get, "/photos", list_photos);
+    routes.add(Method::Post, "/photos", create_photo);
+    routes.add(Method::Get, "/photos/random", get_random_photos);
+    routes.add(Method::Get, "/photos/similar", get_similar_photos);
+    routes.add(Method::Post, "/photos/upload", upload_photo);
+    routes.add(Method::Get, "/photos/upload/:job_id", get_upload_status);
+
+    routes.add(Method::Get, "/photo/by-id/:photo_id", get_photo);
+    routes.add(
+        Method::Post,
+        "/photo/by-id/:photo_id/published",
+        update_photo_published,
+    );
+    routes.add(
+        Method::Post,
+        "/photo/by-id/:photo_id/height-offset",
+        update_photo_height_offset,
+    );
+    routes.add(
+        Method::Post,
+        "/photo/by-id/:photo_id/sort-key",
+        update_photo_sort_key,
+    );
+
+    routes.add(
+        Method::Get,
+        "/photo/by-filestem/:file_stem",
+        get_photo_by_file_stem,
+    );
+    routes.add(Method::Post, "/photo/by-filestem/:file_stem", update_photo);
+
+    routes.add(Method::Get, "/comments", list_comments);
+    routes.add(
+        Method::Post,
+        "/comment/:comment_id/approved",
+        update_comment_approved,
+    );
+    routes.add(Method::Delete, "/comment/:comment_id", delete_comment);
+
+    routes.add(Method::Get, "/stats/photos", get_photo_stats);
+    routes.add(Method::Get, "/stats/tags", get_tag_stats);
+
+    routes.add(Method::Get, "/webhooks", list_webhooks);
+    routes.add(Method::Post, "/webhooks", create_webhook);
+    routes.add(Method::Delete, "/webhook/:webhook_id", delete_webhook);
+}
+
+/// All routes served by API v1, relative to `/api/v1`.
+pub fn routes() -> Vec<(Method, &'static str)> {
+    let mut recorder = RouteRecorder(Vec::new());
+    register_routes(&mut recorder);
+    recorder.0
+}
+
+/// Queue webhook deliveries about a photo that was just changed, returning the changed photo.
+async fn emit_photo_event(
+    req: &Request<crate::State>,
+    conn: &mut PgConnection,
+    photo_id: i32,
+    event: WebhookEvent,
+) -> Result<Option<Photo>, sqlx::Error> {
+    let state = req.state();
+    let photo = conn
+        .get_photo_by_id(photo_id, state.args.default_sort, Published::All)
+        .await?
+        .map(|(photo, _, _)| photo);
+    if let Some(ref photo) = photo {
+        crate::webhooks::emit(conn, state.webhooks.as_ref(), event, photo).await;
+    }
+
+    Ok(photo)
+}
+
+#[instrument(skip_all)]
+async fn get_openapi_spec(req: Request<crate::State>) -> tide::Result<Response> {
+    let spec = openapi::spec(&req.state().args.base_url);
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::Body::from_json(&spec)?)
+        .build())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ListPhotosQueryParams {
+    /// Comma-separated list of tags that all returned photos must have.
+    tagged: Option<String>,
+    /// Whether to only return published photos. Returning unpublished photos requires a valid
+    /// secret key.
+    published: Option<bool>,
+    limit: Option<u8>,
+    cursor: Option<String>,
+    /// Overrides the site's default sort mode.
+    sort: Option<SortMode>,
+}
+
+#[instrument(skip_all)]
+async fn list_photos(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    let query: ListPhotosQueryParams = req.query()?;
+
+    let published = match query.published {
+        Some(true) => Published::OnlyPublished,
+        Some(false) => {
+            require_valid_secret_key!(req, conn);
+            Published::All
+        },
+        None => match validate_secret_key(&req, &mut conn).await? {
+            None => Published::OnlyPublished,
+            Some(false) => Published::OnlyPublished,
+            Some(true) => Published::All,
+        },
+    };
+
+    let tagged = query.tagged.as_ref().map(|tags| {
+        tags.split(',')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| tag.to_string())
+            .collect::<Vec<_>>()
+    });
+
+    let limit = query
+        .limit
+        .unwrap_or(state.args.default_photos_per_page)
+        .min(state.args.max_photos_per_page);
+
+    let sort = query.sort.unwrap_or(state.args.default_sort);
+
+    let page = match query.cursor {
+        Some(ref token) => match Cursor::decode(token, &state.cursor_secret) {
+            Ok(cursor) if cursor.matches(sort, &tagged) => cursor.page,
+            _ => {
+                return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                    .body(tide::convert::json!({
+                        "reason": "Invalid cursor.",
+                    }))
+                    .build());
+            },
+        },
+        None => Page::Latest,
+    };
+
+    let photos = conn
+        .get_photo_page(limit.into(), page, sort, &tagged, published)
+        .await?;
+
+    let (newer, older) = conn
+        .get_photo_pagination_ids(&photos, sort, &tagged, published)
+        .await?;
+
+    let cursor = |page| Cursor::new(page, sort, &tagged).encode(&state.cursor_secret);
+    let prev = newer.map(|newer| cursor(Page::After(newer)));
+    let next = older.map(|older| cursor(Page::Before(older)));
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "photos": photos,
+            "next": next,
+            "prev": prev,
+        }))
+        .build())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RandomPhotosQueryParams {
+    /// Comma-separated list of tags that all returned photos must have.
+    tagged: Option<String>,
+    count: Option<u8>,
+}
+
+#[instrument(skip_all)]
+async fn get_random_photos(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    let published = match validate_secret_key(&req, &mut conn).await? {
+        None => Published::OnlyPublished,
+        Some(false) => Published::OnlyPublished,
+        Some(true) => Published::All,
+    };
+
+    let query: RandomPhotosQueryParams = req.query()?;
+    let tagged = query.tagged.as_ref().map(|tags| {
+        tags.split(',')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| tag.to_string())
+            .collect::<Vec<_>>()
+    });
+    let count = query.count.unwrap_or(1).min(state.args.max_photos_per_page);
+
+    let photo_ids = conn
+        .get_random_photo_ids(count.into(), &tagged, published)
+        .await?;
+    let photos = conn.get_photos_by_ids(&photo_ids, published).await?;
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .header("Cache-Control", "no-store")
+        .body(tide::convert::json!({
+            "photos": photos,
+        }))
+        .build())
+}
+
+/// Maximum number of bits perceptual hashes can differ by for photos to be similar by default.
+const DEFAULT_SIMILARITY_DISTANCE: u32 = 10;
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct SimilarPhotosQueryParams {
+    /// Photo to find photos similar to.
+    photo_id: Option<i32>,
+    /// Perceptual hash to find photos similar to, for photos that haven't been uploaded yet.
+    perceptual_hash: Option<PerceptualHash>,
+    max_distance: Option<u32>,
+}
+
+#[instrument(skip_all)]
+async fn get_similar_photos(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
 
-    route.at("/photo/by-id/:photo_id").get(get_photo);
-    route
-        .at("/photo/by-id/:photo_id/published")
-        .post(update_photo_published);
-    route
-        .at("/photo/by-id/:photo_id/height-offset")
-        .post(update_photo_height_offset);
+    let published = match validate_secret_key(&req, &mut conn).await? {
+        None => Published::OnlyPublished,
+        Some(false) => Published::OnlyPublished,
+        Some(true) => Published::All,
+    };
 
-    route
-        .at("/photo/by-filestem/:file_stem")
-        .get(get_photo_by_file_stem)
-        .post(update_photo);
+    let query: SimilarPhotosQueryParams = req.query()?;
+    let (perceptual_hash, exclude) = match (query.photo_id, query.perceptual_hash) {
+        (Some(photo_id), None) => match conn
+            .get_photo_by_id(photo_id, state.args.default_sort, published)
+            .await?
+        {
+            Some((photo, _, _)) => match photo.perceptual_hash {
+                Some(perceptual_hash) => (perceptual_hash, Some(photo.id)),
+                None => {
+                    return Ok(
+                        Response::builder(tide::http::StatusCode::UnprocessableEntity)
+                            .body(tide::convert::json!({
+                                "reason": "Photo has no perceptual hash.",
+                            }))
+                            .build(),
+                    );
+                },
+            },
+            None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+        },
+        (None, Some(perceptual_hash)) => (perceptual_hash, None),
+        _ => {
+            return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                .body(tide::convert::json!({
+                    "reason": "Exactly one of photo_id and perceptual_hash is required.",
+                }))
+                .build());
+        },
+    };
+    let max_distance = query
+        .max_distance
+        .unwrap_or(DEFAULT_SIMILARITY_DISTANCE)
+        .min(64);
+
+    let similar = conn
+        .get_similar_photo_ids(perceptual_hash, max_distance, exclude, published)
+        .await?;
+    let photo_ids: Vec<_> = similar.iter().map(|&(photo_id, _)| photo_id).collect();
+    let photos = conn.get_photos_by_ids(&photo_ids, published).await?;
+
+    let similar: Vec<_> = similar
+        .into_iter()
+        .filter_map(|(photo_id, distance)| {
+            let photo = photos.iter().find(|photo| photo.id == photo_id)?;
+            Some(tide::convert::json!({
+                "distance": distance,
+                "photo": photo,
+            }))
+        })
+        .collect();
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "perceptual_hash": perceptual_hash,
+            "similar": similar,
+        }))
+        .build())
 }
 
 #[instrument(skip_all)]
@@ -38,7 +368,10 @@ async fn get_photo(req: Request<crate::State>) -> tide::Result<Response> {
     };
 
     let photo_id: i32 = req.param("photo_id")?.parse()?;
-    let res = match conn.get_photo_by_id(photo_id, published).await? {
+    let res = match conn
+        .get_photo_by_id(photo_id, state.args.default_sort, published)
+        .await?
+    {
         Some((photo, _, _)) => Response::builder(tide::http::StatusCode::Ok)
             .body(tide::Body::from_json(&photo)?)
             .build(),
@@ -72,13 +405,28 @@ async fn create_photo(mut req: Request<crate::State>) -> tide::Result<Response>
     let new_photo = crate::models::photos::Photo {
         file_stem: payload.file_stem.clone(),
         title: payload.title,
+        titles: payload.titles.unwrap_or_default(),
+        caption: payload.caption,
+        alt_text: payload.alt_text,
         taken_timestamp: payload.taken_timestamp,
         tags: payload.tags,
         sources,
         published: false,
+        blurhash: payload.blurhash,
+        lqip: payload.lqip,
+        palette: payload.palette.unwrap_or_default(),
+        perceptual_hash: payload.perceptual_hash,
         ..Default::default()
     };
 
+    if conn.is_photo_file_stem_claimed(&payload.file_stem).await? {
+        return Ok(Response::builder(tide::http::StatusCode::Conflict)
+            .body(tide::convert::json!({
+                "reason": format!("Photo with file stem {} is being uploaded.", &payload.file_stem),
+            }))
+            .build());
+    }
+
     let old_photo = conn
         .get_photo_by_file_stem(&payload.file_stem, Published::All)
         .await?;
@@ -91,10 +439,8 @@ async fn create_photo(mut req: Request<crate::State>) -> tide::Result<Response>
             .build()),
         None => {
             let id = conn.insert_photo(&new_photo).await?;
-            let created_photo = conn
-                .get_photo_by_id(id, Published::All)
-                .await?
-                .map(|(photo, _, _)| photo);
+            let created_photo =
+                emit_photo_event(&req, &mut conn, id, WebhookEvent::PhotoCreated).await?;
 
             Ok(Response::builder(tide::http::StatusCode::Created)
                 .body(tide::convert::json!({
@@ -156,10 +502,13 @@ async fn update_photo(mut req: Request<crate::State>) -> tide::Result<Response>
     };
 
     let changed = conn.update_photo(&old_photo, &payload).await?;
-    let updated_photo = conn
-        .get_photo_by_id(old_photo.id, Published::All)
-        .await?
-        .map(|(photo, _, _)| photo);
+    let updated_photo = if changed {
+        emit_photo_event(&req, &mut conn, old_photo.id, WebhookEvent::PhotoUpdated).await?
+    } else {
+        conn.get_photo_by_id(old_photo.id, req.state().args.default_sort, Published::All)
+            .await?
+            .map(|(photo, _, _)| photo)
+    };
 
     Ok(Response::builder(tide::http::StatusCode::Ok)
         .body(tide::convert::json!({
@@ -184,12 +533,27 @@ async fn update_photo_published(mut req: Request<crate::State>) -> tide::Result<
     let published: bool = req.body_json().await?;
 
     let photo_id: i32 = req.param("photo_id")?.parse()?;
-    let photo = match conn.get_photo_by_id(photo_id, Published::All).await? {
+    let photo = match conn
+        .get_photo_by_id(photo_id, req.state().args.default_sort, Published::All)
+        .await?
+    {
         Some((photo, _, _)) => photo,
         None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
     };
 
     conn.set_photo_published_state(photo.id, published).await?;
+    if photo.published != published {
+        let event = match published {
+            true => WebhookEvent::PhotoPublished,
+            false => WebhookEvent::PhotoUnpublished,
+        };
+        let photo = emit_photo_event(&req, &mut conn, photo.id, event).await?;
+
+        if let (true, Some(federation), Some(photo)) = (published, &req.state().activitypub, photo)
+        {
+            federation.publish(&mut conn, &photo).await;
+        }
+    }
 
     Ok(Response::builder(tide::http::StatusCode::Ok)
         .body(tide::convert::json!({
@@ -212,13 +576,456 @@ async fn update_photo_height_offset(mut req: Request<crate::State>) -> tide::Res
     let height_offset: u8 = req.body_json().await?;
 
     let photo_id: i32 = req.param("photo_id")?.parse()?;
-    let photo = match conn.get_photo_by_id(photo_id, Published::All).await? {
+    let photo = match conn
+        .get_photo_by_id(photo_id, req.state().args.default_sort, Published::All)
+        .await?
+    {
         Some((photo, _, _)) => photo,
         None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
     };
 
     conn.set_photo_height_offset(photo.id, height_offset)
         .await?;
+    if photo.height_offset != height_offset {
+        emit_photo_event(&req, &mut conn, photo.id, WebhookEvent::PhotoUpdated).await?;
+    }
+
+    Ok(Response::builder(tide::http::StatusCode::NoContent).build())
+}
+
+#[instrument(skip_all)]
+async fn upload_photo(mut req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state().clone();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let uploads = match state.uploads {
+        Some(ref uploads) => uploads,
+        None => {
+            return Ok(Response::builder(tide::http::StatusCode::NotImplemented)
+                .body(tide::convert::json!({
+                    "reason": "Uploads are not configured on this server.",
+                }))
+                .build());
+        },
+    };
+
+    let max_upload_size = state.args.max_upload_size;
+    let too_large = || {
+        Response::builder(tide::http::StatusCode::PayloadTooLarge)
+            .body(tide::convert::json!({
+                "reason": format!("Uploads can be at most {} bytes.", max_upload_size),
+            }))
+            .build()
+    };
+    if req.len().map_or(false, |len| len > max_upload_size) {
+        return Ok(too_large());
+    }
+
+    let boundary = match req
+        .content_type()
+        .and_then(|mime| multer::parse_boundary(mime.to_string()).ok())
+    {
+        Some(boundary) => boundary,
+        None => {
+            return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                .body(tide::convert::json!({
+                    "reason": "Expected a multipart/form-data body.",
+                }))
+                .build());
+        },
+    };
+
+    // The body length isn't always known up front, so never read more than the limit.
+    let mut data = Vec::new();
+    req.take_body()
+        .into_reader()
+        .take(max_upload_size as u64 + 1)
+        .read_to_end(&mut data)
+        .await?;
+    if data.len() > max_upload_size {
+        return Ok(too_large());
+    }
+
+    let mut multipart = multer::Multipart::new(
+        futures_lite::stream::once(Ok::<_, Infallible>(bytes::Bytes::from(data))),
+        boundary,
+    );
+    let mut upload = None;
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let file_stem = field
+            .file_name()
+            .map(|file_name| match file_name.rsplit_once('.') {
+                Some((file_stem, _)) => file_stem.to_string(),
+                None => file_name.to_string(),
+            })
+            .filter(|file_stem| !file_stem.is_empty());
+        upload = Some((file_stem, field.bytes().await?));
+        break;
+    }
+
+    let (file_stem, data) = match upload {
+        Some((Some(file_stem), data)) => (file_stem, data),
+        _ => {
+            return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                .body(tide::convert::json!({
+                    "reason": "Expected a \"file\" field with a file name.",
+                }))
+                .build());
+        },
+    };
+    if !is_safe_name(&file_stem) {
+        return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+            .body(tide::convert::json!({
+                "reason": "Invalid file name, only ASCII letters, digits, \".\", \"_\" and \"-\" \
+                           are allowed, not starting with \".\".",
+            }))
+            .build());
+    }
+
+    if let Some(photo) = conn
+        .get_photo_by_file_stem(&file_stem, Published::All)
+        .await?
+    {
+        return Ok(Response::builder(tide::http::StatusCode::Conflict)
+            .body(tide::convert::json!({
+                "reason": format!("Photo with file stem {} already exists.", &file_stem),
+                "existing": photo,
+            }))
+            .build());
+    }
+    // Reserve the file stem until the upload is processed, so that nothing else gets stored
+    // under it in the meantime.
+    if !conn.claim_photo_file_stem(&file_stem).await? {
+        return Ok(Response::builder(tide::http::StatusCode::Conflict)
+            .body(tide::convert::json!({
+                "reason": format!("Photo with file stem {} is already being uploaded.", &file_stem),
+            }))
+            .build());
+    }
+
+    let job_id = match uploads.submit(file_stem.clone(), data) {
+        Some(job_id) => job_id,
+        None => {
+            conn.release_photo_file_stem(&file_stem).await?;
+            return Ok(
+                Response::builder(tide::http::StatusCode::ServiceUnavailable)
+                    .header("Retry-After", "60")
+                    .body(tide::convert::json!({
+                        "reason": "Too many uploads are waiting to be processed.",
+                    }))
+                    .build(),
+            );
+        },
+    };
+    info!(job.id = %job_id, "Queued uploaded photo");
+
+    let status_url = format!("{}/api/v1/photos/upload/{}", state.args.base_url, job_id);
+    Ok(Response::builder(tide::http::StatusCode::Accepted)
+        .header("Location", &status_url)
+        .body(tide::convert::json!({
+            "job_id": job_id,
+            "status_url": status_url,
+        }))
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn get_upload_status(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let job_id = req.param("job_id")?;
+    let status = state
+        .uploads
+        .as_ref()
+        .and_then(|uploads| uploads.status(job_id));
+    let res = match status {
+        Some(status) => Response::builder(tide::http::StatusCode::Ok)
+            .body(tide::Body::from_json(&status)?)
+            .build(),
+        None => Response::builder(tide::http::StatusCode::NotFound).build(),
+    };
+
+    Ok(res)
+}
+
+#[instrument(skip_all)]
+async fn update_photo_sort_key(mut req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let sort_key: Option<i32> = req.body_json().await?;
+
+    let photo_id: i32 = req.param("photo_id")?.parse()?;
+    let photo = match conn
+        .get_photo_by_id(photo_id, req.state().args.default_sort, Published::All)
+        .await?
+    {
+        Some((photo, _, _)) => photo,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    conn.set_photo_sort_key(photo.id, sort_key).await?;
+    if photo.sort_key != sort_key {
+        emit_photo_event(&req, &mut conn, photo.id, WebhookEvent::PhotoUpdated).await?;
+    }
+
+    Ok(Response::builder(tide::http::StatusCode::NoContent).build())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ListCommentsQueryParams {
+    /// Which comments to list, defaults to the ones waiting for approval.
+    status: Option<CommentStatus>,
+    /// Only list comments on this photo.
+    photo_id: Option<i32>,
+}
+
+#[instrument(skip_all)]
+async fn list_comments(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let query: ListCommentsQueryParams = req.query()?;
+    let comments = conn
+        .get_comments(
+            query.photo_id,
+            query.status.unwrap_or(CommentStatus::Pending),
+        )
+        .await?;
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "comments": comments,
+        }))
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn update_comment_approved(mut req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let approved: bool = req.body_json().await?;
+
+    let comment_id: i32 = req.param("comment_id")?.parse()?;
+    if !conn.set_comment_approved(comment_id, approved).await? {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+    info!(
+        comment.id = comment_id,
+        approved, "Updated comment approval"
+    );
+
+    Ok(Response::builder(tide::http::StatusCode::NoContent).build())
+}
+
+#[instrument(skip_all)]
+async fn delete_comment(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let comment_id: i32 = req.param("comment_id")?.parse()?;
+    if !conn.delete_comment(comment_id).await? {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+    info!(comment.id = comment_id, "Deleted comment");
+
+    Ok(Response::builder(tide::http::StatusCode::NoContent).build())
+}
+
+/// Number of days of counts returned by the stats endpoints by default.
+const DEFAULT_STATS_DAYS: u16 = 30;
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PhotoStatsQueryParams {
+    /// Number of days to get counts for, including today.
+    days: Option<u16>,
+    /// Only get the counts of this photo.
+    photo_id: Option<i32>,
+}
+
+#[instrument(skip_all)]
+async fn get_photo_stats(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let query: PhotoStatsQueryParams = req.query()?;
+    let days = query.days.unwrap_or(DEFAULT_STATS_DAYS).max(1);
+    let photos = conn.get_photo_stats(days.into(), query.photo_id).await?;
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "days": days,
+            "photos": photos,
+        }))
+        .build())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TagStatsQueryParams {
+    /// Number of days to get counts for, including today.
+    days: Option<u16>,
+    /// Only get the counts of this tag.
+    tag: Option<String>,
+}
+
+#[instrument(skip_all)]
+async fn get_tag_stats(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let query: TagStatsQueryParams = req.query()?;
+    let days = query.days.unwrap_or(DEFAULT_STATS_DAYS).max(1);
+    let tags = conn
+        .get_tag_stats(days.into(), query.tag.as_deref())
+        .await?;
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "days": days,
+            "tags": tags,
+        }))
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn list_webhooks(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let webhooks = conn.get_webhooks().await?;
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::convert::json!({
+            "webhooks": webhooks,
+        }))
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn create_webhook(mut req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let registration: WebhookRegistration = req.body_json().await?;
+
+    match url::Url::parse(&registration.url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {},
+        _ => {
+            return Ok(Response::builder(tide::http::StatusCode::BadRequest)
+                .body(tide::convert::json!({
+                    "reason": "The URL must be an absolute HTTP or HTTPS URL.",
+                }))
+                .build());
+        },
+    }
+
+    let secret = match registration.secret {
+        Some(secret) if !secret.is_empty() => secret,
+        _ => rand::random::<[u8; 32]>()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    };
+
+    let id = conn
+        .insert_webhook(&registration.url, &secret, &registration.events)
+        .await?;
+    info!(webhook.id = id, webhook.url = %registration.url, "Registered webhook");
+
+    // The secret is only ever returned here.
+    Ok(Response::builder(tide::http::StatusCode::Created)
+        .body(tide::convert::json!({
+            "id": id,
+            "secret": secret,
+        }))
+        .build())
+}
+
+#[instrument(skip_all)]
+async fn delete_webhook(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state
+        .db
+        .acquire()
+        .await
+        .expect("couldn't get DB connection");
+
+    require_valid_secret_key!(req, conn);
+
+    let webhook_id: i32 = req.param("webhook_id")?.parse()?;
+    if !conn.delete_webhook(webhook_id).await? {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+    info!(webhook.id = webhook_id, "Deleted webhook");
 
     Ok(Response::builder(tide::http::StatusCode::NoContent).build())
 }
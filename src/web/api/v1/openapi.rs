@@ -0,0 +1,645 @@
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+use tide::http::Method;
+
+use crate::models::comments::Comment;
+use crate::models::photos::Photo;
+use crate::models::stats::{PhotoStats, TagStats};
+use crate::models::webhooks::{Webhook, WebhookRegistration};
+use crate::uploads::UploadJobStatus;
+use rusty_peanuts_api_structs::PhotoPayload;
+
+/// Turn a tide route path like `/photo/by-id/:photo_id` into an OpenAPI path template like
+/// `/photo/by-id/{photo_id}`.
+pub fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn path_parameters(path: &str) -> Vec<Value> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| {
+            let schema = match name {
+                "photo_id" | "comment_id" | "webhook_id" => {
+                    json!({ "type": "integer", "format": "int32" })
+                },
+                _ => json!({ "type": "string" }),
+            };
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": schema,
+            },
+        },
+    })
+}
+
+fn json_request_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": {
+            "application/json": {
+                "schema": schema,
+            },
+        },
+    })
+}
+
+/// Requests can optionally be authenticated to also see unpublished photos.
+fn optional_auth() -> Value {
+    json!([{}, { "bearerAuth": [] }])
+}
+
+/// Requests must be authenticated.
+fn required_auth() -> Value {
+    json!([{ "bearerAuth": [] }])
+}
+
+fn stats_days_parameter() -> Value {
+    json!({
+        "name": "days",
+        "in": "query",
+        "description": "Number of days to get counts for, including today. Defaults to 30.",
+        "schema": { "type": "integer", "minimum": 1, "maximum": 65535 },
+    })
+}
+
+/// Describe a single route in the route table.
+///
+/// Returns `None` for routes that haven't been described yet.
+fn operation(method: Method, path: &str) -> Option<Value> {
+    let operation = match (method, path) {
+        (Method::Get, "/openapi.json") => json!({
+            "summary": "Get this OpenAPI description",
+            "responses": {
+                "200": json_response("The OpenAPI description", json!({ "type": "object" })),
+            },
+        }),
+
+        (Method::Get, "/photos") => json!({
+            "summary": "List a page of photos, newest first",
+            "security": optional_auth(),
+            "parameters": [
+                {
+                    "name": "tagged",
+                    "in": "query",
+                    "description": "Comma-separated list of tags that all photos must have.",
+                    "schema": { "type": "string" },
+                },
+                {
+                    "name": "published",
+                    "in": "query",
+                    "description": "Whether to only list published photos. Listing unpublished \
+                                    photos requires authentication.",
+                    "schema": { "type": "boolean" },
+                },
+                {
+                    "name": "limit",
+                    "in": "query",
+                    "schema": { "type": "integer", "minimum": 0, "maximum": 255 },
+                },
+                {
+                    "name": "cursor",
+                    "in": "query",
+                    "description": "Opaque cursor from the `next` or `prev` field of a previous \
+                                    response.",
+                    "schema": { "type": "string" },
+                },
+                {
+                    "name": "sort",
+                    "in": "query",
+                    "description": "Order to list photos in. Defaults to the site's default sort \
+                                    mode. Cursors are only valid for the sort mode they were \
+                                    created with.",
+                    "schema": {
+                        "type": "string",
+                        "enum": ["id", "taken_timestamp", "sort_key"],
+                    },
+                },
+            ],
+            "responses": {
+                "200": json_response("A page of photos", json!({
+                    "type": "object",
+                    "required": ["photos", "next", "prev"],
+                    "properties": {
+                        "photos": { "type": "array", "items": schema_ref("Photo") },
+                        "next": { "type": "string", "nullable": true },
+                        "prev": { "type": "string", "nullable": true },
+                    },
+                })),
+                "400": { "description": "Invalid cursor" },
+                "401": { "description": "Unpublished photos requested without a secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Post, "/photos") => json!({
+            "summary": "Create a new photo",
+            "security": required_auth(),
+            "requestBody": json_request_body(schema_ref("PhotoPayload")),
+            "responses": {
+                "201": json_response("The photo was created", json!({
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer", "format": "int32" },
+                        "created": schema_ref("Photo"),
+                    },
+                })),
+                "400": { "description": "The payload didn't contain any sources" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "409": json_response("A photo with the same file stem already exists or is \
+                                      being uploaded", json!({
+                    "type": "object",
+                    "properties": {
+                        "reason": { "type": "string" },
+                        "existing": schema_ref("Photo"),
+                    },
+                })),
+            },
+        }),
+
+        (Method::Get, "/photos/random") => json!({
+            "summary": "Get random photos",
+            "security": optional_auth(),
+            "parameters": [
+                {
+                    "name": "tagged",
+                    "in": "query",
+                    "description": "Comma-separated list of tags that all photos must have.",
+                    "schema": { "type": "string" },
+                },
+                {
+                    "name": "count",
+                    "in": "query",
+                    "description": "Maximum number of photos to get, defaults to 1.",
+                    "schema": { "type": "integer", "minimum": 0, "maximum": 255 },
+                },
+            ],
+            "responses": {
+                "200": json_response("Distinct random photos", json!({
+                    "type": "object",
+                    "required": ["photos"],
+                    "properties": {
+                        "photos": { "type": "array", "items": schema_ref("Photo") },
+                    },
+                })),
+            },
+        }),
+
+        (Method::Get, "/photos/similar") => json!({
+            "summary": "Find photos that look similar to a photo, most similar first",
+            "security": optional_auth(),
+            "parameters": [
+                {
+                    "name": "photo_id",
+                    "in": "query",
+                    "description": "Photo to find similar photos to, excluding itself.",
+                    "schema": { "type": "integer", "format": "int32" },
+                },
+                {
+                    "name": "perceptual_hash",
+                    "in": "query",
+                    "description": "Perceptual hash to find similar photos to, instead of a \
+                                    photo ID.",
+                    "schema": schema_ref("PerceptualHash"),
+                },
+                {
+                    "name": "max_distance",
+                    "in": "query",
+                    "description": "Maximum number of bits the perceptual hashes can differ \
+                                    by, defaults to 10.",
+                    "schema": { "type": "integer", "minimum": 0, "maximum": 64 },
+                },
+            ],
+            "responses": {
+                "200": json_response("Similar photos", json!({
+                    "type": "object",
+                    "required": ["perceptual_hash", "similar"],
+                    "properties": {
+                        "perceptual_hash": schema_ref("PerceptualHash"),
+                        "similar": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["distance", "photo"],
+                                "properties": {
+                                    "distance": { "type": "integer", "minimum": 0 },
+                                    "photo": schema_ref("Photo"),
+                                },
+                            },
+                        },
+                    },
+                })),
+                "400": { "description": "Neither or both of photo_id and perceptual_hash given" },
+                "404": { "description": "No such photo" },
+                "422": { "description": "The photo has no perceptual hash" },
+            },
+        }),
+
+        (Method::Post, "/photos/upload") => json!({
+            "summary": "Upload a photo to be transcoded and created in the background",
+            "security": required_auth(),
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "multipart/form-data": {
+                        "schema": {
+                            "type": "object",
+                            "required": ["file"],
+                            "properties": {
+                                "file": {
+                                    "type": "string",
+                                    "format": "binary",
+                                    "description": "TIFF file with XMP metadata, its file name \
+                                                    without extension becomes the file stem. \
+                                                    File names may only contain ASCII letters, \
+                                                    digits, `.`, `_` and `-`, and mustn't start \
+                                                    with `.`.",
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "202": json_response("The photo was queued for processing", json!({
+                    "type": "object",
+                    "properties": {
+                        "job_id": { "type": "string" },
+                        "status_url": { "type": "string", "format": "uri" },
+                    },
+                })),
+                "400": { "description": "Missing or invalid file" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "409": json_response("A photo with this file stem already exists or is being \
+                                      uploaded", json!({
+                    "type": "object",
+                    "properties": {
+                        "reason": { "type": "string" },
+                        "existing": schema_ref("Photo"),
+                    },
+                })),
+                "413": { "description": "The file is too large" },
+                "501": { "description": "Uploads are not configured on this server" },
+                "503": { "description": "Too many uploads are waiting to be processed" },
+            },
+        }),
+
+        (Method::Get, "/photos/upload/:job_id") => json!({
+            "summary": "Get the status of an uploaded photo",
+            "security": required_auth(),
+            "responses": {
+                "200": json_response("The upload's status", schema_ref("UploadJobStatus")),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such upload, or it finished too long ago" },
+            },
+        }),
+
+        (Method::Get, "/photo/by-id/:photo_id") => json!({
+            "summary": "Get a photo by ID",
+            "security": optional_auth(),
+            "responses": {
+                "200": json_response("The photo", schema_ref("Photo")),
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Post, "/photo/by-id/:photo_id/published") => json!({
+            "summary": "Set whether a photo is published",
+            "description": "Publishing a photo posts it to the gallery's fediverse followers if \
+                            ActivityPub is enabled.",
+            "security": required_auth(),
+            "requestBody": json_request_body(json!({ "type": "boolean" })),
+            "responses": {
+                "200": json_response("The published state was updated", json!({
+                    "type": "object",
+                    "properties": {
+                        "published": { "type": "boolean" },
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Post, "/photo/by-id/:photo_id/height-offset") => json!({
+            "summary": "Set the percentage offset used when cropping a photo vertically",
+            "security": required_auth(),
+            "requestBody": json_request_body(json!({
+                "type": "integer",
+                "minimum": 0,
+                "maximum": 100,
+            })),
+            "responses": {
+                "204": { "description": "The height offset was updated" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Post, "/photo/by-id/:photo_id/sort-key") => json!({
+            "summary": "Set or clear the manual sort key of a photo",
+            "security": required_auth(),
+            "requestBody": json_request_body(json!({
+                "type": "integer",
+                "format": "int32",
+                "nullable": true,
+            })),
+            "responses": {
+                "204": { "description": "The sort key was updated" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Get, "/photo/by-filestem/:file_stem") => json!({
+            "summary": "Get a photo by file stem",
+            "security": optional_auth(),
+            "responses": {
+                "200": json_response("The photo", schema_ref("Photo")),
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Post, "/photo/by-filestem/:file_stem") => json!({
+            "summary": "Update a photo by file stem",
+            "security": required_auth(),
+            "requestBody": json_request_body(schema_ref("PhotoPayload")),
+            "responses": {
+                "200": json_response("The photo was updated", json!({
+                    "type": "object",
+                    "properties": {
+                        "changed": { "type": "boolean" },
+                        "previous": schema_ref("Photo"),
+                        "current": schema_ref("Photo"),
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such photo" },
+            },
+        }),
+
+        (Method::Get, "/comments") => json!({
+            "summary": "List comments by moderation status, oldest first",
+            "security": required_auth(),
+            "parameters": [
+                {
+                    "name": "status",
+                    "in": "query",
+                    "description": "Which comments to list, defaults to pending ones.",
+                    "schema": { "type": "string", "enum": ["pending", "approved"] },
+                },
+                {
+                    "name": "photo_id",
+                    "in": "query",
+                    "description": "Only list comments on this photo.",
+                    "schema": { "type": "integer", "format": "int32" },
+                },
+            ],
+            "responses": {
+                "200": json_response("The comments", json!({
+                    "type": "object",
+                    "required": ["comments"],
+                    "properties": {
+                        "comments": { "type": "array", "items": schema_ref("Comment") },
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Post, "/comment/:comment_id/approved") => json!({
+            "summary": "Approve a comment so that it's shown, or move it back to pending",
+            "security": required_auth(),
+            "requestBody": json_request_body(json!({ "type": "boolean" })),
+            "responses": {
+                "204": { "description": "The comment's approval was updated" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such comment" },
+            },
+        }),
+
+        (Method::Delete, "/comment/:comment_id") => json!({
+            "summary": "Delete a comment, e.g. to reject it",
+            "security": required_auth(),
+            "responses": {
+                "204": { "description": "The comment was deleted" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such comment" },
+            },
+        }),
+
+        (Method::Get, "/stats/photos") => json!({
+            "summary": "Get daily view and favorite counts per photo, most viewed first",
+            "security": required_auth(),
+            "parameters": [
+                stats_days_parameter(),
+                {
+                    "name": "photo_id",
+                    "in": "query",
+                    "description": "Only get the counts of this photo.",
+                    "schema": { "type": "integer", "format": "int32" },
+                },
+            ],
+            "responses": {
+                "200": json_response("The counts", json!({
+                    "type": "object",
+                    "required": ["days", "photos"],
+                    "properties": {
+                        "days": { "type": "integer", "minimum": 1 },
+                        "photos": { "type": "array", "items": schema_ref("PhotoStats") },
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Get, "/stats/tags") => json!({
+            "summary": "Get daily view and favorite counts summed per tag, most viewed first",
+            "security": required_auth(),
+            "parameters": [
+                stats_days_parameter(),
+                {
+                    "name": "tag",
+                    "in": "query",
+                    "description": "Only get the counts of this tag.",
+                    "schema": { "type": "string" },
+                },
+            ],
+            "responses": {
+                "200": json_response("The counts", json!({
+                    "type": "object",
+                    "required": ["days", "tags"],
+                    "properties": {
+                        "days": { "type": "integer", "minimum": 1 },
+                        "tags": { "type": "array", "items": schema_ref("TagStats") },
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Get, "/webhooks") => json!({
+            "summary": "List registered webhook endpoints",
+            "security": required_auth(),
+            "responses": {
+                "200": json_response("The webhooks", json!({
+                    "type": "object",
+                    "required": ["webhooks"],
+                    "properties": {
+                        "webhooks": { "type": "array", "items": schema_ref("Webhook") },
+                    },
+                })),
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Post, "/webhooks") => json!({
+            "summary": "Register a webhook endpoint",
+            "description": "Events are POSTed as JSON objects with `event` and `photo` fields. \
+                            The `X-Rusty-Peanuts-Signature` header contains `sha256=` followed \
+                            by the hex-encoded HMAC-SHA256 of the body using the webhook's \
+                            secret. Failed deliveries are retried with exponential backoff, \
+                            with the same `X-Rusty-Peanuts-Delivery` header.",
+            "security": required_auth(),
+            "requestBody": json_request_body(schema_ref("WebhookRegistration")),
+            "responses": {
+                "201": json_response("The webhook was registered", json!({
+                    "type": "object",
+                    "required": ["id", "secret"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int32" },
+                        "secret": {
+                            "type": "string",
+                            "description": "Secret that payloads are signed with, only ever \
+                                            returned here.",
+                        },
+                    },
+                })),
+                "400": { "description": "The URL isn't an absolute HTTP or HTTPS URL" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+            },
+        }),
+
+        (Method::Delete, "/webhook/:webhook_id") => json!({
+            "summary": "Delete a webhook endpoint along with its queued deliveries",
+            "security": required_auth(),
+            "responses": {
+                "204": { "description": "The webhook was deleted" },
+                "401": { "description": "Missing secret key" },
+                "403": { "description": "Invalid secret key" },
+                "404": { "description": "No such webhook" },
+            },
+        }),
+
+        _ => return None,
+    };
+
+    Some(operation)
+}
+
+/// Generate the OpenAPI 3 description of API v1 from its route table and payload types.
+///
+/// Panics if a route in the route table hasn't been described.
+pub fn spec(base_url: &str) -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    generator.subschema_for::<Comment>();
+    generator.subschema_for::<Photo>();
+    generator.subschema_for::<PhotoPayload>();
+    generator.subschema_for::<PhotoStats>();
+    generator.subschema_for::<TagStats>();
+    generator.subschema_for::<Webhook>();
+    generator.subschema_for::<WebhookRegistration>();
+    generator.subschema_for::<UploadJobStatus>();
+    let schemas: Map<String, Value> = generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| {
+            let schema = serde_json::to_value(schema).expect("could not serialize JSON schema");
+            (name, schema)
+        })
+        .collect();
+
+    let mut paths = Map::new();
+    for (method, path) in super::routes() {
+        let mut operation = operation(method, path)
+            .unwrap_or_else(|| panic!("API route {} {} is not described", method, path));
+
+        let parameters = path_parameters(path);
+        if !parameters.is_empty() {
+            let existing = operation
+                .as_object_mut()
+                .expect("operation must be a JSON object")
+                .entry("parameters")
+                .or_insert_with(|| json!([]))
+                .as_array_mut()
+                .expect("operation parameters must be a JSON array");
+            existing.splice(0..0, parameters);
+        }
+
+        operation["responses"]["429"] = json!({
+            "description": "Rate limited, retry after the number of seconds in `Retry-After`",
+        });
+
+        let path_item = paths.entry(openapi_path(path)).or_insert_with(|| json!({}));
+        path_item[method.to_string().to_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "rusty-peanuts",
+            "version": "1",
+        },
+        "servers": [
+            { "url": format!("{}/api/v1", base_url) },
+        ],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "A secret key from the `secret_keys` table.",
+                },
+            },
+        },
+    })
+}
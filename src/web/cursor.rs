@@ -0,0 +1,107 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::db::photos::{Anchor, Page, SortMode};
+
+#[derive(Error, Debug)]
+pub enum CursorError {
+    #[error("malformed cursor")]
+    Malformed,
+    #[error("invalid cursor signature")]
+    InvalidSignature,
+}
+
+/// Position in a paginated list of photos.
+///
+/// Cursors are handed out to clients as encrypted opaque tokens, so that the encoding can change
+/// without clients depending on it, so that clients can't craft their own, and so that they don't
+/// reveal the IDs and sort values of photos that clients might not get to see otherwise.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Cursor {
+    #[serde(rename = "s")]
+    pub sort: SortMode,
+    #[serde(rename = "p")]
+    pub page: Page,
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    pub tagged: Option<Vec<String>>,
+}
+
+impl Cursor {
+    pub fn new(page: Page, sort: SortMode, tagged: &Option<Vec<String>>) -> Self {
+        Cursor {
+            sort,
+            page,
+            tagged: tagged.clone(),
+        }
+    }
+
+    /// Encode the cursor into an encrypted and authenticated opaque token.
+    ///
+    /// Tokens are the random nonce followed by the encrypted cursor, so the same cursor encodes
+    /// differently every time.
+    pub fn encode(&self, secret: &[u8]) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("could not generate cursor nonce");
+
+        let mut payload = serde_json::to_vec(self).expect("could not serialize cursor");
+        key(secret)
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut payload,
+            )
+            .expect("could not encrypt cursor");
+
+        let mut token = nonce.to_vec();
+        token.extend(payload);
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decrypt and verify a token created by [`Cursor::encode`].
+    pub fn decode(token: &str, secret: &[u8]) -> Result<Self, CursorError> {
+        let token = base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| CursorError::Malformed)?;
+        if token.len() < NONCE_LEN {
+            return Err(CursorError::Malformed);
+        }
+
+        let (nonce, payload) = token.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| CursorError::Malformed)?;
+        let mut payload = payload.to_vec();
+        let json = key(secret)
+            .open_in_place(nonce, Aad::empty(), &mut payload)
+            .map_err(|_| CursorError::InvalidSignature)?;
+
+        serde_json::from_slice(json).map_err(|_| CursorError::Malformed)
+    }
+
+    /// Get the page that a pre-cursor `offset` query parameter pointed to.
+    ///
+    /// Non-negative offsets meant photos before that ID, while negative ones encoded photos after
+    /// the ID `-offset - 1`.
+    pub fn legacy_offset_page(offset: i32) -> Page {
+        if offset >= 0 {
+            Page::Before(Anchor::id(offset))
+        } else {
+            Page::After(Anchor::id((-(offset as i64) - 1) as i32))
+        }
+    }
+
+    /// Whether the cursor was created for a listing sorted and filtered like this one.
+    pub fn matches(&self, sort: SortMode, tagged: &Option<Vec<String>>) -> bool {
+        self.sort == sort && &self.tagged == tagged
+    }
+}
+
+/// Key for encrypting cursors, derived from a secret of any size.
+fn key(secret: &[u8]) -> LessSafeKey {
+    let key = Sha256::digest(secret);
+    LessSafeKey::new(
+        UnboundKey::new(&CHACHA20_POLY1305, &key).expect("ChaCha20-Poly1305 keys are 32 bytes"),
+    )
+}
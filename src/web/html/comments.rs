@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use tide::{Request, Response};
+use tracing::{info, instrument};
+
+use crate::db::comments::CommentProvider;
+use crate::db::photos::PhotoProvider;
+use crate::web::html::allowed_publish_status;
+
+/// Longest author name, in characters.
+const MAX_AUTHOR_LENGTH: usize = 100;
+/// Longest comment, in characters.
+const MAX_BODY_LENGTH: usize = 5000;
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CommentForm {
+    author: String,
+    body: String,
+    /// Honeypot field that's hidden from people, anything that fills it in is a bot.
+    website: String,
+}
+
+/// What happened to a posted comment, shown on the photo page after redirecting back to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum CommentNotice {
+    /// The comment was stored and is waiting to be approved.
+    Pending,
+    /// The comment was missing its author or body, or either was too long.
+    Invalid,
+}
+
+impl CommentNotice {
+    fn as_str(self) -> &'static str {
+        match self {
+            CommentNotice::Pending => "pending",
+            CommentNotice::Invalid => "invalid",
+        }
+    }
+}
+
+/// Send the commenter back to the photo's comments, with a notice about what happened.
+fn redirect_to_photo(state: &crate::State, photo_id: i32, notice: CommentNotice) -> Response {
+    let location = format!(
+        "{}/photo/{}?comment={}#comments",
+        state.args.base_url,
+        photo_id,
+        notice.as_str()
+    );
+    tide::Redirect::see_other(location).into()
+}
+
+/// Store a comment posted through the form on a photo page, pending approval.
+#[instrument(skip_all)]
+pub(super) async fn post_comment(mut req: Request<crate::State>) -> tide::Result<Response> {
+    let form: CommentForm = req.body_form().await?;
+    let photo_id = req.param("photo_id")?.parse::<i32>()?;
+
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    if conn
+        .get_photo_by_id(photo_id, state.args.default_sort, published)
+        .await?
+        .is_none()
+    {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+
+    // Bots get told the same as people, so that they don't learn to avoid the honeypot.
+    if !form.website.is_empty() {
+        info!(
+            photo.id = photo_id,
+            "Dropping comment that filled in the honeypot"
+        );
+        return Ok(redirect_to_photo(state, photo_id, CommentNotice::Pending));
+    }
+
+    let author = form.author.trim();
+    let body = form.body.trim();
+    if author.is_empty()
+        || body.is_empty()
+        || author.chars().count() > MAX_AUTHOR_LENGTH
+        || body.chars().count() > MAX_BODY_LENGTH
+    {
+        return Ok(redirect_to_photo(state, photo_id, CommentNotice::Invalid));
+    }
+
+    let comment_id = conn.insert_comment(photo_id, author, body).await?;
+    info!(
+        photo.id = photo_id,
+        comment.id = comment_id,
+        "Stored comment pending approval"
+    );
+
+    Ok(redirect_to_photo(state, photo_id, CommentNotice::Pending))
+}
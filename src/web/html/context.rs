@@ -0,0 +1,74 @@
+use serde::Serialize;
+
+use crate::models::photos::Photo;
+use rusty_peanuts_api_structs::{Color, Source, JPEG_MIME_TYPE};
+
+/// Formats in the order browsers should consider them in a `<picture>` element, before falling
+/// back to JPEG.
+const FORMAT_PREFERENCE: [&str; 2] = ["image/avif", "image/webp"];
+
+/// Sources of a single format, largest first.
+#[derive(Debug, Serialize)]
+pub(super) struct SourceGroup<'a> {
+    mime_type: &'a str,
+    sources: Vec<&'a Source>,
+}
+
+/// A photo along with what templates need to render it that isn't part of the API.
+#[derive(Debug, Serialize)]
+pub(super) struct PhotoContext<'a> {
+    #[serde(flatten)]
+    photo: &'a Photo,
+    /// Sources grouped by format, with preferred formats first and JPEG last so that it can be
+    /// used as the `<img>` fallback.
+    sources_by_format: Vec<SourceGroup<'a>>,
+    /// The photo's most dominant color, to use as its background while it's loading.
+    placeholder_color: Option<&'a Color>,
+    /// The caption rendered from Markdown to sanitized HTML, safe to include unescaped.
+    caption_html: Option<String>,
+    /// Text for the `alt` attribute, falling back to the title when there's no alt text.
+    alt: Option<&'a str>,
+}
+
+/// Render Markdown to HTML, removing anything that could run scripts or break the page.
+pub(crate) fn render_markdown(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new(markdown);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    ammonia::clean(&html)
+}
+
+impl<'a> From<&'a Photo> for PhotoContext<'a> {
+    fn from(photo: &'a Photo) -> Self {
+        let rank = |mime_type: &str| {
+            FORMAT_PREFERENCE
+                .iter()
+                .position(|preferred| *preferred == mime_type)
+                .unwrap_or(FORMAT_PREFERENCE.len())
+        };
+
+        let mut sources_by_format: Vec<SourceGroup> = Vec::new();
+        for source in &photo.sources {
+            match sources_by_format
+                .iter_mut()
+                .find(|group| group.mime_type == source.mime_type)
+            {
+                Some(group) => group.sources.push(source),
+                None => sources_by_format.push(SourceGroup {
+                    mime_type: &source.mime_type,
+                    sources: vec![source],
+                }),
+            }
+        }
+        sources_by_format
+            .sort_by_key(|group| (group.mime_type == JPEG_MIME_TYPE, rank(group.mime_type)));
+
+        PhotoContext {
+            photo,
+            sources_by_format,
+            placeholder_color: photo.palette.first(),
+            caption_html: photo.caption.as_deref().map(render_markdown),
+            alt: photo.alt_text.as_deref().or(photo.title.as_deref()),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// A link to the same page in another language, for `<link rel="alternate" hreflang="...">`.
+#[derive(Debug, Serialize)]
+pub(super) struct Alternate {
+    pub hreflang: String,
+    pub href: String,
+}
+
+/// Parse an `Accept-Language` header into language ranges, most preferred first.
+///
+/// Ranges with a quality of zero and the `*` wildcard are left out, as they never pick a specific
+/// title.
+fn accepted_languages(header: &str) -> Vec<&str> {
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let language = parts.next().filter(|language| !language.is_empty())?;
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse().ok())?;
+            Some((language, quality))
+        })
+        .filter(|&(language, quality)| language != "*" && quality > 0.0)
+        .collect();
+
+    // Stable, so that ranges with equal quality keep their order.
+    ranges.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    ranges.into_iter().map(|(language, _)| language).collect()
+}
+
+fn primary_subtag(language: &str) -> &str {
+    language.split('-').next().unwrap_or(language)
+}
+
+/// Find the language of the title that best matches a language range.
+///
+/// Prefers exact matches, then titles in a more specific variant of the range, e.g. `en-GB` for
+/// `en`, and finally titles in the range's primary language, e.g. `en` for `en-US`.
+pub(crate) fn match_language<'a>(
+    titles: &'a BTreeMap<String, String>,
+    range: &str,
+) -> Option<&'a str> {
+    let languages = || titles.keys().map(String::as_str);
+
+    languages()
+        .find(|language| language.eq_ignore_ascii_case(range))
+        .or_else(|| {
+            languages().find(|language| primary_subtag(language).eq_ignore_ascii_case(range))
+        })
+        .or_else(|| {
+            languages().find(|language| language.eq_ignore_ascii_case(primary_subtag(range)))
+        })
+}
+
+/// Pick the language of the title to show for an `Accept-Language` header.
+pub(super) fn negotiate<'a>(
+    titles: &'a BTreeMap<String, String>,
+    accept_language: &str,
+) -> Option<&'a str> {
+    accepted_languages(accept_language)
+        .into_iter()
+        .find_map(|range| match_language(titles, range))
+}
+
+/// Whether a title's language can be used in URLs as-is, which valid language tags always can.
+pub(crate) fn is_url_safe_language(language: &str) -> bool {
+    !language.is_empty()
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
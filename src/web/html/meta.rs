@@ -0,0 +1,137 @@
+use serde::Serialize;
+use serde_json::json;
+
+use crate::models::photos::Photo;
+use crate::Args;
+
+/// Image to show in link previews.
+#[derive(Debug, Serialize)]
+pub(super) struct MetaImage {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub alt: Option<String>,
+}
+
+/// Social and structured metadata for a page, inserted as `meta` into every HTML context.
+///
+/// The Open Graph and Twitter card fields map directly onto `<meta>` tags, while `json_ld` is a
+/// serialized schema.org document that is safe to embed in a `<script>` element as-is.
+#[derive(Debug, Serialize)]
+pub(super) struct PageMeta {
+    pub title: String,
+    pub url: String,
+    pub site_name: String,
+    pub author: Option<String>,
+    pub og_type: &'static str,
+    pub image: Option<MetaImage>,
+    pub twitter_card: &'static str,
+    pub twitter_site: Option<String>,
+    pub json_ld: String,
+}
+
+/// Serialize a JSON-LD document so that it can't break out of the `<script>` element it's
+/// embedded in.
+fn serialize_json_ld(value: &serde_json::Value) -> String {
+    serde_json::to_string(value)
+        .expect("could not serialize JSON-LD")
+        .replace("</", "<\\/")
+}
+
+fn meta_image(photo: &Photo) -> Option<MetaImage> {
+    photo.jpeg_sources().next().map(|source| MetaImage {
+        url: source.url.clone(),
+        width: source.width,
+        height: source.height,
+        alt: photo.alt_text.clone().or_else(|| photo.title.clone()),
+    })
+}
+
+fn image_object(args: &Args, photo: &Photo) -> serde_json::Value {
+    let mut image_object = json!({
+        "@type": "ImageObject",
+        "url": format!("{}/photo/{}", args.base_url, photo.id),
+        "name": photo.title,
+        "keywords": photo.tags.join(", "),
+    });
+
+    if let Some(source) = photo.jpeg_sources().next() {
+        image_object["contentUrl"] = json!(source.url);
+        image_object["width"] = json!(source.width);
+        image_object["height"] = json!(source.height);
+    }
+    if let Some(thumbnail) = photo.jpeg_sources().last() {
+        image_object["thumbnailUrl"] = json!(thumbnail.url);
+    }
+    if let Some(ref caption) = photo.caption {
+        image_object["caption"] = json!(caption);
+    }
+    if let Some(ref taken_timestamp) = photo.taken_timestamp {
+        image_object["dateCreated"] = json!(taken_timestamp);
+    }
+    if let Some(ref author) = args.author {
+        image_object["author"] = json!({ "@type": "Person", "name": author });
+        image_object["creator"] = json!({ "@type": "Person", "name": author });
+    }
+
+    image_object
+}
+
+impl PageMeta {
+    /// Metadata for a page showing a single photo.
+    pub(super) fn for_photo(args: &Args, title: &str, url: &str, photo: &Photo) -> Self {
+        let mut json_ld = image_object(args, photo);
+        json_ld["@context"] = json!("https://schema.org");
+        json_ld["mainEntityOfPage"] = json!(url);
+
+        PageMeta {
+            title: title.to_string(),
+            url: url.to_string(),
+            site_name: args.site_name.clone(),
+            author: args.author.clone(),
+            og_type: "article",
+            image: meta_image(photo),
+            twitter_card: "summary_large_image",
+            twitter_site: args.twitter_site.clone(),
+            json_ld: serialize_json_ld(&json_ld),
+        }
+    }
+
+    /// Metadata for a gallery page, using the first photo on the page as preview image.
+    pub(super) fn for_gallery(args: &Args, title: &str, url: &str, photos: &[Photo]) -> Self {
+        let json_ld = json!({
+            "@context": "https://schema.org",
+            "@type": "CollectionPage",
+            "name": title,
+            "url": url,
+            "isPartOf": {
+                "@type": "WebSite",
+                "name": args.site_name,
+                "url": format!("{}/", args.base_url),
+            },
+            "hasPart": photos
+                .iter()
+                .map(|photo| image_object(args, photo))
+                .collect::<Vec<_>>(),
+        });
+
+        let image = photos.first().and_then(meta_image);
+        let twitter_card = if image.is_some() {
+            "summary_large_image"
+        } else {
+            "summary"
+        };
+
+        PageMeta {
+            title: title.to_string(),
+            url: url.to_string(),
+            site_name: args.site_name.clone(),
+            author: args.author.clone(),
+            og_type: "website",
+            image,
+            twitter_card,
+            twitter_site: args.twitter_site.clone(),
+            json_ld: serialize_json_ld(&json_ld),
+        }
+    }
+}
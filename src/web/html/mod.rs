@@ -1,27 +1,72 @@
+use std::net::IpAddr;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use tide::{Request, Response};
-use tracing::instrument;
+use tracing::{error, instrument};
 
-use crate::db::photos::{PhotoProvider, Published};
+use crate::db::comments::{CommentProvider, CommentStatus};
+use crate::db::photos::{Page, PhotoProvider, Published, SortMode};
 use crate::db::secret_keys::SecretKeyProvider;
-
+use crate::db::stats::{StatsEvent, StatsProvider};
+use crate::web::api::rate_limit::{client_ip, RateLimiter};
+use crate::web::cursor::Cursor;
+use comments::CommentNotice;
+use context::PhotoContext;
+use meta::PageMeta;
+use rusty_peanuts_api_structs::Color;
+
+mod comments;
+mod context;
+mod lang;
+mod meta;
+mod sitemap;
 mod utils;
 
+pub(crate) use context::render_markdown;
+pub(crate) use lang::{is_url_safe_language, match_language};
+pub(crate) use sitemap::child_sitemap_paths;
+
+/// How far in RGB space a photo's palette color can be from the color being browsed by.
+const NEAR_COLOR_DISTANCE: u32 = 64;
+
 pub(in super::super) fn mount(route: &mut tide::Server<crate::State>) {
     route.at("/").get(gallery);
-    route.at("/sitemap.xml").get(sitemap);
+    route.at("/sitemap.xml").get(sitemap::sitemap);
+    route.at("/sitemap/:name").get(sitemap::child_sitemap);
+
+    route.at("/random").get(random_photo);
 
     route.at("/tagged/:tagged").get(gallery);
+    route.at("/tagged/:tagged/random").get(random_photo);
+
+    route.at("/color/:hex").get(color_gallery);
+
+    route.at("/popular").get(popular_gallery);
 
     route.at("/photo/:photo_id").get(single_photo);
+    route
+        .at("/photo/:photo_id")
+        .with(RateLimiter::comments())
+        .post(comments::post_comment);
+    route
+        .at("/photo/:photo_id/favorite")
+        .with(RateLimiter::favorites())
+        .post(favorite_photo);
     route
         .at("/photo/:photo_id/multi")
         .get(single_photo_multiple_times);
+
+    // The same photo pages with the title in a specific language.
+    route.at("/:lang/photo/:photo_id").get(single_photo);
+    route
+        .at("/:lang/photo/:photo_id/multi")
+        .get(single_photo_multiple_times);
 }
 
 #[instrument(skip_all)]
-async fn allowed_publish_status(
+pub(in crate::web) async fn allowed_publish_status(
     req: &Request<crate::State>,
     conn: &mut PgConnection,
 ) -> Result<Published, sqlx::Error> {
@@ -46,6 +91,12 @@ async fn allowed_publish_status(
 #[serde(default)]
 struct GalleryQueryParams {
     limit: Option<u8>,
+    cursor: Option<String>,
+    /// Overrides the site's default sort mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<SortMode>,
+    /// Pagination parameter used before cursors, only kept around to redirect old links.
+    #[serde(skip_serializing)]
     offset: Option<i32>,
 }
 
@@ -66,6 +117,54 @@ async fn gallery(req: Request<crate::State>) -> tide::Result<Response> {
         .map(|tag| vec![tag])
         .ok();
     let query: GalleryQueryParams = req.query()?;
+    let sort = query.sort.unwrap_or(state.args.default_sort);
+
+    // Only mention the sort mode in links when it's not the default.
+    let sort_override = |sort: SortMode| Some(sort).filter(|&sort| sort != state.args.default_sort);
+    let pagination_qs = |page: Page, sort: SortMode| {
+        serde_qs::to_string(&GalleryQueryParams {
+            limit: query.limit,
+            cursor: Some(Cursor::new(page, sort, &tagged).encode(&state.cursor_secret)),
+            sort: sort_override(sort),
+            offset: None,
+        })
+        .expect("could not encode pagination query string")
+    };
+
+    // Offsets were IDs, so they always point into the gallery sorted by ID.
+    if let Some(offset) = query.offset {
+        let location = format!(
+            "{}?{}",
+            req.url().path(),
+            pagination_qs(Cursor::legacy_offset_page(offset), SortMode::Id)
+        );
+        // Cursors encrypted with a random secret stop working on restart, so don't let browsers
+        // and crawlers remember where the offset led.
+        return Ok(match state.args.cursor_secret {
+            Some(_) => tide::Redirect::permanent(location).into(),
+            None => tide::Redirect::new(location).into(),
+        });
+    }
+
+    let newest_qs = serde_qs::to_string(&GalleryQueryParams {
+        limit: query.limit,
+        cursor: None,
+        sort: sort_override(sort),
+        offset: None,
+    })
+    .expect("could not encode newest pagination query string");
+
+    // Send cursors that don't work (anymore) to the newest photos, keeping the sort mode.
+    let page = match query.cursor {
+        Some(ref token) => match Cursor::decode(token, &state.cursor_secret) {
+            Ok(cursor) if cursor.matches(sort, &tagged) => cursor.page,
+            _ => {
+                let location = format!("{}?{}", req.url().path(), newest_qs);
+                return Ok(tide::Redirect::new(location).into());
+            },
+        },
+        None => Page::Latest,
+    };
 
     let limit = match query.limit {
         Some(n) if n < state.args.max_photos_per_page => n,
@@ -74,69 +173,38 @@ async fn gallery(req: Request<crate::State>) -> tide::Result<Response> {
     };
 
     let photos = conn
-        .get_photo_page(limit.into(), query.offset.into(), &tagged, published)
+        .get_photo_page(limit.into(), page, sort, &tagged, published)
         .await?;
 
     let (newer, older) = conn
-        .get_photo_pagination_ids(&photos, &tagged, published)
+        .get_photo_pagination_ids(&photos, sort, &tagged, published)
         .await?;
 
     let tags = conn.get_photo_tags_with_counts(&tagged, published).await?;
 
-    let newest_qs = serde_qs::to_string(&GalleryQueryParams {
-        limit: query.limit,
-        offset: None,
-    })
-    .expect("could not encode newest pagination query string");
-
-    let newer_qs = newer.map(|newer_id| {
-        serde_qs::to_string(&GalleryQueryParams {
-            limit: query.limit,
-            offset: Some(-newer_id - 1),
-        })
-        .expect("could not encode newer pagination query string")
-    });
-
-    let older_qs = older.map(|older_id| {
-        serde_qs::to_string(&GalleryQueryParams {
-            limit: query.limit,
-            offset: Some(older_id),
-        })
-        .expect("could not encode older pagination query string")
-    });
+    let newer_qs = newer.map(|newer| pagination_qs(Page::After(newer), sort));
+    let older_qs = older.map(|older| pagination_qs(Page::Before(older), sort));
+    let oldest_qs = pagination_qs(Page::Oldest, sort);
 
-    let oldest_qs = serde_qs::to_string(&GalleryQueryParams {
-        limit: query.limit,
-        offset: Some(-1),
-    })
-    .expect("could not encode newest pagination query string");
+    let (title, canonical_href) = match tagged {
+        Some(tag) => {
+            let title = format!("tagged {}", tag[0]);
+            let canonical_href = format!("{}/tagged/{}", state.args.base_url, tag[0]);
+            (title, canonical_href)
+        },
+        None => ("gallery".to_string(), format!("{}/", state.args.base_url)),
+    };
+    let meta = PageMeta::for_gallery(&state.args, &title, &canonical_href, &photos);
 
     let mut context = tera::Context::new();
     context.insert("cache_buster", &state.cache_busting_string);
-    match tagged {
-        Some(tag) => {
-            context.insert("title", &format!("tagged {}", tag[0]));
-            let canonical_href = if let Some(offset) = query.offset {
-                format!(
-                    "{}/tagged/{}?offset={}",
-                    state.args.base_url, tag[0], offset
-                )
-            } else {
-                format!("{}/tagged/{}", state.args.base_url, tag[0])
-            };
-            context.insert("canonical_href", &canonical_href);
-        },
-        None => {
-            context.insert("title", "gallery");
-            let canonical_href = if let Some(offset) = query.offset {
-                format!("{}/?offset={}", state.args.base_url, offset)
-            } else {
-                format!("{}/", state.args.base_url)
-            };
-            context.insert("canonical_href", &canonical_href);
-        },
-    }
-    context.insert("photos", &photos);
+    context.insert("title", &title);
+    context.insert("canonical_href", &canonical_href);
+    context.insert("meta", &meta);
+    context.insert(
+        "photos",
+        &photos.iter().map(PhotoContext::from).collect::<Vec<_>>(),
+    );
     context.insert("newest_qs", &newest_qs);
     context.insert("newer_qs", &newer_qs);
     context.insert("older_qs", &older_qs);
@@ -152,39 +220,210 @@ async fn gallery(req: Request<crate::State>) -> tide::Result<Response> {
 }
 
 #[instrument(skip_all)]
-async fn sitemap(req: Request<crate::State>) -> tide::Result<Response> {
+async fn color_gallery(req: Request<crate::State>) -> tide::Result<Response> {
     let state = req.state();
+
+    let color: Color = match req.param("hex")?.parse() {
+        Ok(color) => color,
+        Err(_) => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
     let mut conn = state.db.acquire().await?;
+    let published = allowed_publish_status(&req, &mut conn).await?;
+
+    let photo_ids = conn
+        .get_photo_ids_near_color(
+            color,
+            NEAR_COLOR_DISTANCE,
+            state.args.max_photos_per_page.into(),
+            published,
+        )
+        .await?;
+    let photos = conn.get_photos_by_ids(&photo_ids, published).await?;
+    let tags = conn.get_photo_tags_with_counts(&None, published).await?;
+
+    let title = format!("photos in {}", color);
+    let canonical_href = format!(
+        "{}/color/{}",
+        state.args.base_url,
+        color.to_string().trim_start_matches('#')
+    );
+    let meta = PageMeta::for_gallery(&state.args, &title, &canonical_href, &photos);
+
+    // Photos are sorted by how close they are to the color rather than by ID, so there's only
+    // ever a single page.
+    let mut context = tera::Context::new();
+    context.insert("cache_buster", &state.cache_busting_string);
+    context.insert("title", &title);
+    context.insert("canonical_href", &canonical_href);
+    context.insert("meta", &meta);
+    context.insert("color", &color);
+    context.insert(
+        "photos",
+        &photos.iter().map(PhotoContext::from).collect::<Vec<_>>(),
+    );
+    context.insert("newest_qs", "");
+    context.insert("newer_qs", &None::<String>);
+    context.insert("older_qs", &None::<String>);
+    context.insert("oldest_qs", &None::<String>);
+    context.insert("tags", &tags);
+
+    let rendered = utils::render(state, "gallery.html", &context)?;
+    let res = Response::builder(tide::http::StatusCode::Ok)
+        .content_type("text/html")
+        .body(rendered)
+        .build();
+    Ok(res)
+}
 
+#[instrument(skip_all)]
+async fn popular_gallery(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
     let published = allowed_publish_status(&req, &mut conn).await?;
 
-    let mut buf = Vec::new();
-    let sitemap_writer = sitemap::writer::SiteMapWriter::new(&mut buf);
-    let mut urlwriter = sitemap_writer.start_urlset()?;
+    let photo_ids = conn
+        .get_popular_photo_ids(
+            state.args.popular_days.into(),
+            state.args.max_photos_per_page.into(),
+            published,
+        )
+        .await?;
+    let photos = conn.get_photos_by_ids(&photo_ids, published).await?;
+    let tags = conn.get_photo_tags_with_counts(&None, published).await?;
+
+    let title = "popular photos";
+    let canonical_href = format!("{}/popular", state.args.base_url);
+    let meta = PageMeta::for_gallery(&state.args, title, &canonical_href, &photos);
 
-    urlwriter.url(format!("{}/", state.args.base_url))?;
+    // Photos are sorted by their counts rather than by ID, so there's only ever a single page.
+    let mut context = tera::Context::new();
+    context.insert("cache_buster", &state.cache_busting_string);
+    context.insert("title", title);
+    context.insert("canonical_href", &canonical_href);
+    context.insert("meta", &meta);
+    context.insert(
+        "photos",
+        &photos.iter().map(PhotoContext::from).collect::<Vec<_>>(),
+    );
+    context.insert("newest_qs", "");
+    context.insert("newer_qs", &None::<String>);
+    context.insert("older_qs", &None::<String>);
+    context.insert("oldest_qs", &None::<String>);
+    context.insert("tags", &tags);
 
-    for (tag, _) in conn.get_photo_tags_with_counts(&None, published).await? {
-        urlwriter.url(format!("{}/tagged/{}", state.args.base_url, tag))?;
+    let rendered = utils::render(state, "gallery.html", &context)?;
+    let res = Response::builder(tide::http::StatusCode::Ok)
+        .content_type("text/html")
+        .body(rendered)
+        .build();
+    Ok(res)
+}
+
+/// Count a view or favorite of a photo, unless the client already did the same today.
+///
+/// Clients without a known IP address, like the static renderer, aren't counted. Failing to
+/// count is logged rather than failing the request.
+async fn record_photo_event(
+    state: &crate::State,
+    client: Option<IpAddr>,
+    photo_id: i32,
+    event: StatsEvent,
+) {
+    let client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    if !state.visitors.first_today(client, photo_id, event) {
+        return;
     }
 
-    for id in conn.get_all_photo_ids(published).await? {
-        urlwriter.url(format!("{}/photo/{}", state.args.base_url, id))?;
+    let res = async {
+        let mut conn = state.db.acquire().await?;
+        conn.record_photo_event(photo_id, event).await
     }
+    .await;
+    if let Err(err) = res {
+        error!(
+            exception.message = %err,
+            photo.id = photo_id,
+            event = ?event,
+            "Failed to count photo event"
+        );
+    }
+}
 
-    urlwriter.end()?;
+#[instrument(skip_all)]
+async fn favorite_photo(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let photo_id = req.param("photo_id")?.parse::<i32>()?;
 
-    let res = Response::builder(tide::http::StatusCode::Ok)
-        .body(buf)
-        .content_type(tide::http::mime::XML)
-        .build();
+    let mut conn = state.db.acquire().await?;
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    if conn
+        .get_photo_by_id(photo_id, state.args.default_sort, published)
+        .await?
+        .is_none()
+    {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+
+    drop(conn);
+
+    record_photo_event(state, client_ip(&req), photo_id, StatsEvent::Favorite).await;
+
+    let location = format!("{}/photo/{}", state.args.base_url, photo_id);
+    Ok(tide::Redirect::see_other(location).into())
+}
+
+#[instrument(skip_all)]
+async fn random_photo(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let published = allowed_publish_status(&req, &mut conn).await?;
+
+    let tagged = req
+        .param("tagged")
+        .map(|tag| {
+            percent_encoding::percent_decode_str(tag)
+                .decode_utf8_lossy()
+                .to_string()
+        })
+        .map(|tag| vec![tag])
+        .ok();
+
+    let photo_id = match conn
+        .get_random_photo_ids(1, &tagged, published)
+        .await?
+        .first()
+    {
+        Some(&photo_id) => photo_id,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let location = format!("{}/photo/{}", state.args.base_url, photo_id);
+    let mut res: Response = tide::Redirect::new(location).into();
+    res.insert_header("Cache-Control", "no-store");
     Ok(res)
 }
 
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct PhotoQueryParams {
+    /// Overrides the site's default sort mode for finding the newer and older photos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<SortMode>,
+    /// What happened to a comment that was just posted.
+    #[serde(skip_serializing)]
+    comment: Option<CommentNotice>,
+}
+
 #[instrument(skip_all)]
 async fn photo_internal(
     req: Request<crate::State>,
     mut context: tera::Context,
+    page_path: String,
     template: &'static str,
 ) -> tide::Result<Response> {
     let state = req.state();
@@ -192,10 +431,21 @@ async fn photo_internal(
 
     let photo_id = req.param("photo_id")?.parse::<i32>()?;
 
+    let query: PhotoQueryParams = req.query()?;
+    let sort = query.sort.unwrap_or(state.args.default_sort);
+
     let published = allowed_publish_status(&req, &mut conn).await?;
-    let res = conn.get_photo_by_id(photo_id, published).await?;
+    let res = conn.get_photo_by_id(photo_id, sort, published).await?;
 
-    let photo = match res {
+    // Lets templates keep the sort mode when linking to the newer and older photos.
+    let sort_qs = serde_qs::to_string(&PhotoQueryParams {
+        sort: Some(sort).filter(|&sort| sort != state.args.default_sort),
+        comment: None,
+    })
+    .expect("could not encode sort query string");
+    context.insert("sort_qs", &sort_qs);
+
+    let mut photo = match res {
         Some((photo, newer, older)) => {
             if let Some(newer_id) = newer {
                 context.insert("newer_id", &newer_id);
@@ -208,18 +458,63 @@ async fn photo_internal(
         None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
     };
 
-    context.insert("cache_buster", &state.cache_busting_string);
-    match photo.title {
-        Some(ref title) => context.insert("title", &title),
-        None => context.insert("title", "Untitled"),
+    // The language in the URL picks the title, otherwise the browser's preferences do.
+    let prefixed = req.param("lang").is_ok();
+    let language = match req.param("lang") {
+        Ok(lang) => match lang::match_language(&photo.titles, lang) {
+            Some(language) if lang::is_url_safe_language(language) => Some(language.to_string()),
+            _ => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+        },
+        Err(_) => req.header("Accept-Language").and_then(|values| {
+            lang::negotiate(&photo.titles, values.last().as_str()).map(str::to_string)
+        }),
+    };
+    if let Some(ref language) = language {
+        photo.title = photo.titles.get(language).cloned();
+    }
+
+    let canonical_href = match language {
+        Some(ref language) if prefixed => {
+            format!("{}/{}{}", state.args.base_url, language, page_path)
+        },
+        _ => format!("{}{}", state.args.base_url, page_path),
+    };
+
+    let mut alternates: Vec<lang::Alternate> = photo
+        .titles
+        .keys()
+        .filter(|language| lang::is_url_safe_language(language))
+        .map(|language| lang::Alternate {
+            hreflang: language.clone(),
+            href: format!("{}/{}{}", state.args.base_url, language, page_path),
+        })
+        .collect();
+    if !alternates.is_empty() {
+        alternates.push(lang::Alternate {
+            hreflang: "x-default".to_string(),
+            href: format!("{}{}", state.args.base_url, page_path),
+        });
     }
-    context.insert("photo", &photo);
+    context.insert("lang", &language);
+    context.insert("alternates", &alternates);
+
+    let title = photo.title.as_deref().unwrap_or("Untitled");
+    let meta = PageMeta::for_photo(&state.args, title, &canonical_href, &photo);
+
+    context.insert("cache_buster", &state.cache_busting_string);
+    context.insert("title", title);
+    context.insert("canonical_href", &canonical_href);
+    context.insert("meta", &meta);
+    context.insert("photo", &PhotoContext::from(&photo));
 
     let rendered = utils::render(state, template, &context)?;
-    let res = Response::builder(tide::http::StatusCode::Ok)
+    let mut res = Response::builder(tide::http::StatusCode::Ok)
         .content_type("text/html")
         .body(rendered)
         .build();
+    if !prefixed && !photo.titles.is_empty() {
+        res.insert_header("Vary", "Accept-Language");
+    }
     Ok(res)
 }
 
@@ -229,24 +524,48 @@ async fn single_photo(req: Request<crate::State>) -> tide::Result<Response> {
     let mut context = tera::Context::new();
 
     let photo_id = req.param("photo_id")?;
-    context.insert(
-        "canonical_href",
-        &format!("{}/photo/{}", state.args.base_url, photo_id),
+    let page_path = format!("/photo/{}", photo_id);
+    let oembed_href = format!(
+        "{}/oembed?url={}&format=json",
+        state.args.base_url,
+        utf8_percent_encode(
+            &format!("{}{}", state.args.base_url, page_path),
+            NON_ALPHANUMERIC
+        )
     );
+    context.insert("oembed_href", &oembed_href);
+
+    // Only approved comments are ever shown, pending ones wait for moderation.
+    let photo_id: i32 = photo_id.parse()?;
+    let mut conn = state.db.acquire().await?;
+    let comments = conn
+        .get_comments(Some(photo_id), CommentStatus::Approved)
+        .await?;
+    drop(conn);
+    context.insert("comments", &comments);
+
+    let query: PhotoQueryParams = req.query()?;
+    context.insert("comment_notice", &query.comment);
+
+    let state = state.clone();
+    let client = client_ip(&req);
+    let res = photo_internal(req, context, page_path, "photo.html").await?;
+
+    // Only count views of photos that exist and the client is allowed to see.
+    if res.status() == tide::http::StatusCode::Ok {
+        record_photo_event(&state, client, photo_id, StatsEvent::View).await;
+    }
 
-    photo_internal(req, context, "photo.html").await
+    Ok(res)
 }
 
 #[instrument(skip_all)]
 async fn single_photo_multiple_times(req: Request<crate::State>) -> tide::Result<Response> {
     let state = req.state();
-    let mut context = tera::Context::new();
+    let context = tera::Context::new();
 
     let photo_id = req.param("photo_id")?;
-    context.insert(
-        "canonical_href",
-        &format!("{}/photo/{}/multi", state.args.base_url, photo_id),
-    );
+    let page_path = format!("/photo/{}/multi", photo_id);
 
-    photo_internal(req, context, "single-photo-multiple-times.html").await
+    photo_internal(req, context, page_path, "single-photo-multiple-times.html").await
 }
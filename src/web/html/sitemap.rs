@@ -0,0 +1,321 @@
+use sqlx::PgConnection;
+use tide::{Request, Response};
+use tracing::instrument;
+
+use crate::db::photos::{PhotoProvider, Published};
+use crate::web::html::allowed_publish_status;
+
+/// Most URLs a single sitemap may contain according to the sitemap protocol.
+const MAX_URLS: usize = 50_000;
+/// Largest size in bytes of an uncompressed sitemap according to the sitemap protocol.
+const MAX_SIZE: usize = 50 * 1024 * 1024;
+
+const URLSET_START: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" "#,
+    r#"xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">"#,
+);
+const URLSET_END: &str = "</urlset>";
+
+const SITEMAPINDEX_START: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+);
+const SITEMAPINDEX_END: &str = "</sitemapindex>";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A single `<url>` element of a sitemap.
+struct Entry {
+    loc: String,
+    lastmod: Option<String>,
+    image_url: Option<String>,
+    image_title: Option<String>,
+}
+
+impl Entry {
+    fn to_xml(&self) -> String {
+        let mut xml = format!("<url><loc>{}</loc>", escape(&self.loc));
+        if let Some(ref lastmod) = self.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", escape(lastmod)));
+        }
+        if let Some(ref image_url) = self.image_url {
+            xml.push_str(&format!(
+                "<image:image><image:loc>{}</image:loc>",
+                escape(image_url)
+            ));
+            if let Some(ref image_title) = self.image_title {
+                xml.push_str(&format!(
+                    "<image:title>{}</image:title>",
+                    escape(image_title)
+                ));
+            }
+            xml.push_str("</image:image>");
+        }
+        xml.push_str("</url>");
+        xml
+    }
+}
+
+/// Sections of the site that get their own child sitemaps when split into a sitemap index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Section {
+    /// The gallery and tag pages.
+    Pages,
+    Photos,
+}
+
+impl Section {
+    const ALL: [Section; 2] = [Section::Pages, Section::Photos];
+
+    fn name(self) -> &'static str {
+        match self {
+            Section::Pages => "pages",
+            Section::Photos => "photos",
+        }
+    }
+}
+
+/// A chunk of entries small enough for a single sitemap.
+struct Chunk {
+    entries: Vec<String>,
+    /// Latest `lastmod` of the entries.
+    lastmod: Option<String>,
+}
+
+impl Chunk {
+    fn to_xml(&self) -> String {
+        let mut xml = URLSET_START.to_string();
+        for entry in &self.entries {
+            xml.push_str(entry);
+        }
+        xml.push_str(URLSET_END);
+        xml
+    }
+}
+
+/// Split entries into chunks that each stay within the protocol limits.
+fn chunk_entries(entries: Vec<Entry>) -> Vec<Chunk> {
+    let max_size = MAX_SIZE - URLSET_START.len() - URLSET_END.len();
+
+    let mut chunks = Vec::new();
+    let mut chunk = Chunk {
+        entries: Vec::new(),
+        lastmod: None,
+    };
+    let mut size = 0;
+    for entry in entries {
+        let xml = entry.to_xml();
+        if !chunk.entries.is_empty()
+            && (chunk.entries.len() >= MAX_URLS || size + xml.len() > max_size)
+        {
+            chunks.push(std::mem::replace(
+                &mut chunk,
+                Chunk {
+                    entries: Vec::new(),
+                    lastmod: None,
+                },
+            ));
+            size = 0;
+        }
+
+        size += xml.len();
+        chunk.entries.push(xml);
+        // W3C datetimes in UTC sort chronologically as strings.
+        chunk.lastmod = std::cmp::max(chunk.lastmod, entry.lastmod);
+    }
+    if !chunk.entries.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+async fn section_entries(
+    state: &crate::State,
+    conn: &mut PgConnection,
+    section: Section,
+    published: Published,
+) -> Result<Vec<Entry>, sqlx::Error> {
+    let entries = match section {
+        Section::Pages => {
+            let tags = conn.get_tags_last_updated(published).await?;
+            let gallery_lastmod = tags.iter().map(|(_, lastmod)| lastmod).max().cloned();
+
+            let mut entries = vec![Entry {
+                loc: format!("{}/", state.args.base_url),
+                lastmod: gallery_lastmod,
+                image_url: None,
+                image_title: None,
+            }];
+            entries.extend(tags.into_iter().map(|(tag, lastmod)| Entry {
+                loc: format!("{}/tagged/{}", state.args.base_url, tag),
+                lastmod: Some(lastmod),
+                image_url: None,
+                image_title: None,
+            }));
+            entries
+        },
+        Section::Photos => conn
+            .get_sitemap_photos(published)
+            .await?
+            .into_iter()
+            .map(|photo| Entry {
+                loc: format!("{}/photo/{}", state.args.base_url, photo.id),
+                lastmod: Some(photo.updated_at),
+                image_url: photo.image_url,
+                image_title: photo.title,
+            })
+            .collect(),
+    };
+
+    Ok(entries)
+}
+
+/// Everything in the sitemap, split into chunks per section.
+struct Sitemap {
+    sections: Vec<(Section, Vec<Chunk>)>,
+}
+
+impl Sitemap {
+    async fn build(
+        state: &crate::State,
+        conn: &mut PgConnection,
+        published: Published,
+    ) -> Result<Self, sqlx::Error> {
+        let mut sections = Vec::new();
+        for section in Section::ALL {
+            let entries = section_entries(state, conn, section, published).await?;
+            sections.push((section, chunk_entries(entries)));
+        }
+
+        Ok(Sitemap { sections })
+    }
+
+    /// Whether everything fits into a single sitemap, without needing a sitemap index.
+    fn fits_single_sitemap(&self) -> bool {
+        let chunks = || self.sections.iter().flat_map(|(_, chunks)| chunks);
+        let urls: usize = chunks().map(|chunk| chunk.entries.len()).sum();
+        let size: usize = chunks()
+            .flat_map(|chunk| &chunk.entries)
+            .map(|entry| entry.len())
+            .sum();
+
+        urls <= MAX_URLS && size + URLSET_START.len() + URLSET_END.len() <= MAX_SIZE
+    }
+
+    /// Paths of the child sitemaps, empty if everything fits into a single sitemap.
+    fn child_paths(&self) -> Vec<(String, Option<&str>)> {
+        if self.fits_single_sitemap() {
+            return Vec::new();
+        }
+
+        self.sections
+            .iter()
+            .flat_map(|(section, chunks)| {
+                chunks.iter().enumerate().map(move |(i, chunk)| {
+                    (
+                        format!("/sitemap/{}-{}.xml", section.name(), i + 1),
+                        chunk.lastmod.as_deref(),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn to_xml(&self, base_url: &str) -> String {
+        let children = self.child_paths();
+        if children.is_empty() {
+            let mut xml = URLSET_START.to_string();
+            for entry in self
+                .sections
+                .iter()
+                .flat_map(|(_, chunks)| chunks)
+                .flat_map(|chunk| &chunk.entries)
+            {
+                xml.push_str(entry);
+            }
+            xml.push_str(URLSET_END);
+            return xml;
+        }
+
+        let mut xml = SITEMAPINDEX_START.to_string();
+        for (path, lastmod) in children {
+            xml.push_str(&format!(
+                "<sitemap><loc>{}</loc>",
+                escape(&format!("{}{}", base_url, path))
+            ));
+            if let Some(lastmod) = lastmod {
+                xml.push_str(&format!("<lastmod>{}</lastmod>", escape(lastmod)));
+            }
+            xml.push_str("</sitemap>");
+        }
+        xml.push_str(SITEMAPINDEX_END);
+        xml
+    }
+
+    /// Get a child sitemap by its file name, e.g. `photos-2.xml`.
+    fn child(&self, name: &str) -> Option<&Chunk> {
+        let (section, number) = name.strip_suffix(".xml")?.rsplit_once('-')?;
+        let number: usize = number.parse().ok()?;
+
+        let (_, chunks) = self
+            .sections
+            .iter()
+            .find(|(candidate, _)| candidate.name() == section)?;
+        chunks.get(number.checked_sub(1)?)
+    }
+}
+
+/// Paths of the child sitemaps of the sitemap index, empty if there's only a single sitemap.
+pub(crate) async fn child_sitemap_paths(
+    state: &crate::State,
+    conn: &mut PgConnection,
+    published: Published,
+) -> Result<Vec<String>, sqlx::Error> {
+    let sitemap = Sitemap::build(state, conn, published).await?;
+    Ok(sitemap
+        .child_paths()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect())
+}
+
+fn xml_response(xml: String) -> Response {
+    Response::builder(tide::http::StatusCode::Ok)
+        .body(xml)
+        .content_type(tide::http::mime::XML)
+        .build()
+}
+
+/// The sitemap, or a sitemap index when the site has too many URLs for a single sitemap.
+#[instrument(skip_all)]
+pub(super) async fn sitemap(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    let sitemap = Sitemap::build(state, &mut conn, published).await?;
+
+    Ok(xml_response(sitemap.to_xml(&state.args.base_url)))
+}
+
+#[instrument(skip_all)]
+pub(super) async fn child_sitemap(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    let sitemap = Sitemap::build(state, &mut conn, published).await?;
+
+    match sitemap.child(req.param("name")?) {
+        Some(chunk) => Ok(xml_response(chunk.to_xml())),
+        None => Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    }
+}
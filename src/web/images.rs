@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use sha2::{Digest, Sha256};
+use tide::{Request, Response};
+use tracing::{info, instrument, warn};
+
+use crate::db::photos::PhotoProvider;
+use crate::web::html::allowed_publish_status;
+
+const CACHE_CONTROL: &str = "public, max-age=86400";
+/// Unpublished photos are only visible with a secret key, so mustn't end up in shared caches.
+const UNPUBLISHED_CACHE_CONTROL: &str = "private, no-store";
+
+pub(super) fn mount(app: &mut tide::Server<crate::State>) {
+    app.at("/img/:photo_id/:size").get(resized_photo);
+}
+
+/// Target size of a resized photo, either a width or an exact width and height to crop to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TargetSize {
+    width: u32,
+    height: Option<u32>,
+}
+
+impl std::str::FromStr for TargetSize {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('x') {
+            Some((width, height)) => Ok(TargetSize {
+                width: width.parse()?,
+                height: Some(height.parse()?),
+            }),
+            None => Ok(TargetSize {
+                width: s.parse()?,
+                height: None,
+            }),
+        }
+    }
+}
+
+/// Resize an image to the target size.
+///
+/// Images are never scaled up. When cropping to an exact size that's larger than the image, the
+/// target size is scaled down to fit the image, keeping its aspect ratio. The image is then
+/// scaled to cover the target size, and cropped horizontally around the center and vertically at
+/// `height_offset` percent.
+fn resize(image: &DynamicImage, size: TargetSize, height_offset: u8) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+
+    match size.height {
+        None => {
+            if size.width >= width {
+                image.clone()
+            } else {
+                image.resize(size.width, u32::MAX, FilterType::Lanczos3)
+            }
+        },
+        Some(target_height) => {
+            let fit = f64::min(
+                1.0,
+                f64::min(
+                    f64::from(width) / f64::from(size.width.max(1)),
+                    f64::from(height) / f64::from(target_height.max(1)),
+                ),
+            );
+            let target_width = ((f64::from(size.width) * fit).round() as u32).clamp(1, width);
+            let target_height = ((f64::from(target_height) * fit).round() as u32).clamp(1, height);
+
+            let scale = f64::max(
+                f64::from(target_width) / f64::from(width),
+                f64::from(target_height) / f64::from(height),
+            );
+            let scaled_width = ((f64::from(width) * scale).round() as u32).max(target_width);
+            let scaled_height = ((f64::from(height) * scale).round() as u32).max(target_height);
+            let scaled = image.resize_exact(scaled_width, scaled_height, FilterType::Lanczos3);
+
+            let x = (scaled_width - target_width) / 2;
+            let y = (u64::from(scaled_height - target_height) * u64::from(height_offset.min(100))
+                / 100) as u32;
+            scaled.crop_imm(x, y, target_width, target_height)
+        },
+    }
+}
+
+fn encode_jpeg(image: &DynamicImage) -> image::ImageResult<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, 80);
+    encoder.encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))?;
+    Ok(data)
+}
+
+/// Path in the cache directory for a resized photo.
+///
+/// Includes everything the result depends on, so that changing the height offset or replacing
+/// the sources doesn't serve stale images.
+fn cache_path(
+    cache_dir: &Path,
+    photo_id: i32,
+    size: &str,
+    height_offset: u8,
+    url: &str,
+) -> PathBuf {
+    let source_hash = Sha256::digest(url.as_bytes());
+    let source_hash: String = source_hash[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    cache_dir
+        .join(photo_id.to_string())
+        .join(format!("{}.{}.{}.jpeg", size, height_offset, source_hash))
+}
+
+fn jpeg_response(data: Vec<u8>, published: bool) -> Response {
+    let cache_control = if published {
+        CACHE_CONTROL
+    } else {
+        UNPUBLISHED_CACHE_CONTROL
+    };
+
+    Response::builder(tide::http::StatusCode::Ok)
+        .content_type("image/jpeg")
+        .header("Cache-Control", cache_control)
+        .body(data)
+        .build()
+}
+
+#[instrument(skip_all)]
+async fn resized_photo(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let cache_dir = match state.args.resize_cache_dir {
+        Some(ref cache_dir) => cache_dir,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let size_param = req.param("size")?;
+    if !state
+        .args
+        .resize_sizes
+        .iter()
+        .any(|size| size == size_param)
+    {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+    let size: TargetSize = size_param.parse()?;
+
+    let photo_id = req.param("photo_id")?.parse::<i32>()?;
+
+    let mut conn = state.db.acquire().await?;
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    let photo = match conn
+        .get_photo_by_id(photo_id, state.args.default_sort, published)
+        .await?
+    {
+        Some((photo, _, _)) => photo,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+    drop(conn);
+
+    let source = match photo.jpeg_sources().next() {
+        Some(source) => source,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let path = cache_path(
+        cache_dir,
+        photo.id,
+        size_param,
+        photo.height_offset,
+        &source.url,
+    );
+    if let Ok(data) = async_std::fs::read(&path).await {
+        return Ok(jpeg_response(data, photo.published));
+    }
+
+    info!(photo.id = photo.id, size = size_param, source.url = %source.url, "Resizing photo");
+    let mut source_res = match surf::get(&source.url).await {
+        Ok(res) => res,
+        Err(err) => {
+            warn!(
+                photo.id = photo.id,
+                source.url = %source.url,
+                exception.message = %err,
+                "Failed to fetch photo source"
+            );
+            return Ok(Response::builder(tide::http::StatusCode::BadGateway).build());
+        },
+    };
+    if !source_res.status().is_success() {
+        warn!(
+            photo.id = photo.id,
+            source.url = %source.url,
+            status = %source_res.status(),
+            "Failed to fetch photo source"
+        );
+        let status = match source_res.status() {
+            tide::http::StatusCode::NotFound | tide::http::StatusCode::Gone => {
+                tide::http::StatusCode::NotFound
+            },
+            _ => tide::http::StatusCode::BadGateway,
+        };
+        return Ok(Response::builder(status).build());
+    }
+    let original = source_res.body_bytes().await?;
+
+    let height_offset = photo.height_offset;
+    let data = async_std::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&original)?;
+        encode_jpeg(&resize(&image, size, height_offset))
+    })
+    .await?;
+
+    // Write to a temporary file first so that concurrent requests never see partial files.
+    let parent = path.parent().expect("cache path always has a parent");
+    async_std::fs::create_dir_all(parent).await?;
+    let temporary_path = path.with_extension(format!("{:08x}.tmp", rand::random::<u32>()));
+    async_std::fs::write(&temporary_path, &data).await?;
+    async_std::fs::rename(&temporary_path, &path).await?;
+
+    Ok(jpeg_response(data, photo.published))
+}
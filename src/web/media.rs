@@ -0,0 +1,130 @@
+use std::path::{Component, Path, PathBuf};
+
+use async_std::io::{BufReader, ReadExt, SeekExt, SeekFrom};
+use tide::http::Mime;
+use tide::{Body, Request, Response};
+use tracing::instrument;
+
+/// Media files are named after their contents' dimensions and never change once written, so
+/// they can be cached for as long as browsers allow.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+pub(super) fn mount(app: &mut tide::Server<crate::State>) {
+    app.at("/media/*path").get(media_file);
+}
+
+/// Resolve a request path inside of the media directory, refusing anything that could escape it.
+fn resolve(media_dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+
+    let mut resolved = media_dir.to_path_buf();
+    for component in Path::new(path.as_ref()).components() {
+        match component {
+            Component::Normal(segment) => resolved.push(segment),
+            _ => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+fn mime_from_extension(extension: &str) -> Option<Mime> {
+    match extension {
+        // Newer image formats that http-types doesn't know about.
+        "avif" => "image/avif".parse().ok(),
+        "webp" => "image/webp".parse().ok(),
+        _ => Mime::from_extension(extension),
+    }
+}
+
+/// Parse a single `Range: bytes=...` header value into an inclusive byte range.
+///
+/// Returns `None` for range sets with multiple ranges, which are answered with the whole file,
+/// and `Some(Err(()))` for ranges that can't be satisfied.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(suffix) if suffix > 0 && len > 0 => Ok((len.saturating_sub(suffix), len - 1)),
+            _ => Err(()),
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) if start < len => Ok((start, len - 1)),
+            _ => Err(()),
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end && start < len => Ok((start, end.min(len - 1))),
+            _ => Err(()),
+        },
+    };
+
+    Some(range)
+}
+
+#[instrument(skip_all)]
+async fn media_file(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let media_dir = match state.args.media_dir {
+        Some(ref media_dir) => media_dir,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let path = match resolve(media_dir, req.param("path")?) {
+        Some(path) => path,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let mut file = match async_std::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Ok(Response::builder(tide::http::StatusCode::NotFound).build());
+    }
+    let len = metadata.len();
+
+    let mime = path
+        .extension()
+        .and_then(|extension| mime_from_extension(&extension.to_string_lossy()))
+        .unwrap_or(tide::http::mime::BYTE_STREAM);
+
+    let range = req
+        .header("Range")
+        .and_then(|values| parse_range(values.last().as_str(), len));
+
+    let mut res = match range {
+        None => Response::builder(tide::http::StatusCode::Ok)
+            .body(Body::from_reader(BufReader::new(file), Some(len as usize)))
+            .build(),
+        Some(Ok((start, end))) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            let range_len = end - start + 1;
+
+            Response::builder(tide::http::StatusCode::PartialContent)
+                .header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+                .body(Body::from_reader(
+                    BufReader::new(file.take(range_len)),
+                    Some(range_len as usize),
+                ))
+                .build()
+        },
+        Some(Err(())) => Response::builder(tide::http::StatusCode::RequestedRangeNotSatisfiable)
+            .header("Content-Range", format!("bytes */{}", len))
+            .build(),
+    };
+
+    if res.status().is_success() {
+        res.set_content_type(mime);
+    }
+    res.insert_header("Accept-Ranges", "bytes");
+    res.insert_header("Cache-Control", CACHE_CONTROL);
+    Ok(res)
+}
@@ -1,7 +1,22 @@
+pub mod activitypub;
 pub mod api;
+pub mod cursor;
 pub mod html;
+pub mod images;
+pub mod media;
+pub mod oembed;
 
 pub(super) fn mount(app: &mut tide::Server<crate::State>) {
     html::mount(app);
+    if app.state().args.resize_cache_dir.is_some() {
+        images::mount(app);
+    }
+    if app.state().args.media_dir.is_some() {
+        media::mount(app);
+    }
+    oembed::mount(app);
+    if app.state().activitypub.is_some() {
+        activitypub::mount(app);
+    }
     api::mount(app.at("/api"));
 }
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use tide::{Request, Response};
+use tracing::instrument;
+
+use crate::db::photos::PhotoProvider;
+use crate::web::html::{allowed_publish_status, is_url_safe_language, match_language};
+
+pub(super) fn mount(app: &mut tide::Server<crate::State>) {
+    app.at("/oembed").get(oembed);
+}
+
+#[derive(Deserialize)]
+struct OEmbedQueryParams {
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    format: Option<String>,
+}
+
+/// Response for an oEmbed `photo` type, see <https://oembed.com/#section2.3>.
+#[derive(Serialize)]
+struct OEmbedPhoto<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_name: Option<&'a str>,
+    provider_name: &'a str,
+    provider_url: &'a str,
+    url: &'a str,
+    width: u32,
+    height: u32,
+}
+
+/// Get the photo ID, and the language if the URL has one, from a URL to a photo page on this
+/// gallery.
+fn photo_id_from_url<'a>(base_url: &str, url: &'a str) -> Option<(Option<&'a str>, i32)> {
+    // Require a slash after the base URL, so that e.g. `https://example.com.evil` isn't local.
+    let path = url.strip_prefix(&format!("{}/", base_url.trim_end_matches('/')))?;
+    let path = path.split(&['?', '#'][..]).next()?;
+
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments[..] {
+        ["photo", photo_id] | ["photo", photo_id, "multi"] => Some((None, photo_id.parse().ok()?)),
+        [language, "photo", photo_id] | [language, "photo", photo_id, "multi"]
+            if is_url_safe_language(language) =>
+        {
+            Some((Some(language), photo_id.parse().ok()?))
+        },
+        _ => None,
+    }
+}
+
+#[instrument(skip_all)]
+async fn oembed(req: Request<crate::State>) -> tide::Result<Response> {
+    let state = req.state();
+    let mut conn = state.db.acquire().await?;
+
+    let query: OEmbedQueryParams = req.query()?;
+    if !matches!(query.format.as_deref(), None | Some("json")) {
+        return Ok(Response::builder(tide::http::StatusCode::NotImplemented).build());
+    }
+
+    let (language, photo_id) = match photo_id_from_url(&state.args.base_url, &query.url) {
+        Some(page) => page,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let published = allowed_publish_status(&req, &mut conn).await?;
+    let photo = match conn
+        .get_photo_by_id(photo_id, state.args.default_sort, published)
+        .await?
+    {
+        Some((photo, _, _)) => photo,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    let source = match photo.source_fitting(query.maxwidth, query.maxheight) {
+        Some(source) => source,
+        None => return Ok(Response::builder(tide::http::StatusCode::NotFound).build()),
+    };
+
+    // Like on the photo page, the language in the URL picks the title.
+    let title = language
+        .and_then(|language| match_language(&photo.titles, language))
+        .and_then(|language| photo.titles.get(language))
+        .or(photo.title.as_ref());
+
+    let body = OEmbedPhoto {
+        kind: "photo",
+        version: "1.0",
+        title: title.map(String::as_str),
+        author_name: state.args.author.as_deref(),
+        provider_name: &state.args.site_name,
+        provider_url: &state.args.base_url,
+        url: &source.url,
+        width: source.width,
+        height: source.height,
+    };
+
+    Ok(Response::builder(tide::http::StatusCode::Ok)
+        .body(tide::Body::from_json(&body)?)
+        .build())
+}
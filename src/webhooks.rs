@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_std::channel::{Receiver, Sender};
+use async_std::task::JoinHandle;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::postgres::PgPool;
+use sqlx::PgConnection;
+use tracing::{error, info, instrument, warn};
+
+use crate::db::webhooks::{DeliveryId, PendingDelivery, WebhookProvider};
+use crate::models::photos::Photo;
+use crate::models::webhooks::WebhookEvent;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header containing the HMAC-SHA256 of the request body, as `sha256=` followed by hex digits.
+pub const SIGNATURE_HEADER: &str = "X-Rusty-Peanuts-Signature";
+/// Header containing the event name, e.g. `photo.published`.
+pub const EVENT_HEADER: &str = "X-Rusty-Peanuts-Event";
+/// Header containing the delivery ID, which stays the same across retries.
+pub const DELIVERY_HEADER: &str = "X-Rusty-Peanuts-Delivery";
+
+/// Number of attempts after which a delivery is given up on.
+const MAX_ATTEMPTS: i32 = 10;
+/// Delay before the first retry, doubled for every following one.
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Upper bound for the delay between retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long an endpoint gets to respond.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a claimed delivery is kept from being claimed again, must exceed the timeout.
+const CLAIM_LEASE: Duration = Duration::from_secs(5 * 60);
+/// Number of deliveries to claim at a time.
+const CLAIM_BATCH_SIZE: i64 = 16;
+
+/// Sign a payload for the signature header.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take keys of any size");
+    mac.update(body);
+
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", signature)
+}
+
+/// How long to wait before retrying a delivery that failed its `attempts`th attempt.
+pub fn retry_delay(attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    BASE_RETRY_DELAY
+        .checked_mul(1 << doublings)
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// POST a signed payload to a webhook endpoint, failing unless it responds with a 2xx status.
+pub async fn deliver(
+    url: &str,
+    secret: &str,
+    delivery_id: DeliveryId,
+    event: &str,
+    body: &[u8],
+) -> Result<()> {
+    let req = surf::post(url)
+        .header(SIGNATURE_HEADER, sign(secret, body))
+        .header(EVENT_HEADER, event)
+        .header(DELIVERY_HEADER, delivery_id.to_string())
+        .content_type(surf::http::mime::JSON)
+        .body(body.to_vec());
+
+    let res = async_std::future::timeout(DELIVERY_TIMEOUT, req)
+        .await
+        .context("Endpoint didn't respond in time")?
+        .map_err(|err| err.into_inner())
+        .context("Failed to send request")?;
+
+    if !res.status().is_success() {
+        bail!("Endpoint responded with status {}", res.status());
+    }
+
+    Ok(())
+}
+
+/// Queue an event about a photo for delivery to every webhook registered for it.
+///
+/// Failing to queue is logged rather than failing the request that caused the event, as the
+/// change it's about has already been made.
+pub async fn emit(
+    conn: &mut PgConnection,
+    dispatcher: Option<&WebhookDispatcher>,
+    event: WebhookEvent,
+    photo: &Photo,
+) {
+    let payload = serde_json::json!({
+        "event": event,
+        "photo": photo,
+    });
+
+    match conn.enqueue_webhook_deliveries(event, &payload).await {
+        Ok(0) => {},
+        Ok(queued) => {
+            info!(
+                event = event.as_str(),
+                photo.id = photo.id,
+                deliveries = queued,
+                "Queued webhook deliveries"
+            );
+            if let Some(dispatcher) = dispatcher {
+                dispatcher.wake();
+            }
+        },
+        Err(err) => error!(
+            exception.message = %err,
+            event = event.as_str(),
+            photo.id = photo.id,
+            "Failed to queue webhook deliveries"
+        ),
+    }
+}
+
+/// Background task delivering queued webhook payloads, retrying failed deliveries with
+/// exponential backoff.
+///
+/// The queue lives in the database, so deliveries survive restarts.
+#[derive(Clone, Debug)]
+pub struct WebhookDispatcher {
+    wake: Sender<()>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl WebhookDispatcher {
+    /// Start delivering queued payloads, checking for due deliveries every `poll_interval` and
+    /// whenever new ones are queued.
+    pub fn start(db: PgPool, poll_interval: Duration) -> Self {
+        let (wake, woken) = async_std::channel::bounded(1);
+        let task = async_std::task::spawn(run(db, woken, poll_interval));
+
+        WebhookDispatcher {
+            wake,
+            task: Arc::new(Mutex::new(Some(task))),
+        }
+    }
+
+    /// Check for due deliveries right away.
+    pub fn wake(&self) {
+        // A full channel means a check is already pending.
+        let _ = self.wake.try_send(());
+    }
+
+    /// Stop checking for deliveries and wait for the current batch to be attempted, giving up
+    /// after `deadline`.
+    ///
+    /// Returns whether the batch was finished before the deadline.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.wake.close();
+        let task = self
+            .task
+            .lock()
+            .expect("dispatcher task lock was poisoned")
+            .take();
+
+        crate::shutdown::join_tasks("webhooks", task.into_iter().collect(), deadline).await
+    }
+}
+
+async fn run(db: PgPool, woken: Receiver<()>, poll_interval: Duration) {
+    // The wake channel is only closed when shutting down.
+    while !woken.is_closed() {
+        match deliver_due(&db).await {
+            // A full batch means there might be more due deliveries waiting.
+            Ok(claimed) if claimed >= CLAIM_BATCH_SIZE as usize => continue,
+            Ok(_) => {},
+            Err(err) => error!(
+                exception.message = %format!("{:#}", err),
+                "Failed to process webhook deliveries"
+            ),
+        }
+
+        let _ = async_std::future::timeout(poll_interval, woken.recv()).await;
+    }
+}
+
+/// Claim and attempt a batch of due deliveries, returning how many were claimed.
+async fn deliver_due(db: &PgPool) -> Result<usize> {
+    let mut conn = db.acquire().await?;
+    let deliveries = conn
+        .claim_webhook_deliveries(CLAIM_BATCH_SIZE, CLAIM_LEASE.as_secs_f64())
+        .await?;
+    drop(conn);
+
+    let claimed = deliveries.len();
+    for delivery in deliveries {
+        attempt(db, delivery).await?;
+    }
+
+    Ok(claimed)
+}
+
+#[instrument(skip_all, fields(delivery.id = delivery.id, event = %delivery.event))]
+async fn attempt(db: &PgPool, delivery: PendingDelivery) -> Result<()> {
+    let body = serde_json::to_vec(&delivery.payload.0)?;
+    let res = deliver(
+        &delivery.url,
+        &delivery.secret,
+        delivery.id,
+        &delivery.event,
+        &body,
+    )
+    .await;
+
+    let mut conn = db.acquire().await?;
+    match res {
+        Ok(()) => {
+            info!(attempts = delivery.attempts, "Delivered webhook");
+            conn.mark_webhook_delivered(delivery.id).await?;
+        },
+        Err(err) if delivery.attempts >= MAX_ATTEMPTS => {
+            let reason = format!("{:#}", err);
+            error!(
+                attempts = delivery.attempts,
+                exception.message = %reason,
+                "Giving up on webhook delivery"
+            );
+            conn.fail_webhook_delivery(delivery.id, &reason).await?;
+        },
+        Err(err) => {
+            let reason = format!("{:#}", err);
+            let delay = retry_delay(delivery.attempts);
+            warn!(
+                attempts = delivery.attempts,
+                retry.seconds = delay.as_secs(),
+                exception.message = %reason,
+                "Webhook delivery failed, retrying later"
+            );
+            conn.retry_webhook_delivery(delivery.id, delay.as_secs_f64(), &reason)
+                .await?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,387 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_std::channel::Sender;
+use serde_json::{json, Value};
+use tide::listener::Listener;
+use tide::{Request, Response, StatusCode};
+
+use rusty_peanuts::activitypub::addresses::is_public;
+use rusty_peanuts::activitypub::signatures::{
+    sign_request, verify_request, PrivateKey, PublicKey, SignatureError, SignatureParams,
+};
+use rusty_peanuts::activitypub::{
+    authenticate, documents, fetch_actor, post_signed, KeyCache, LocalActor,
+};
+use rusty_peanuts::models::photos::Photo;
+use rusty_peanuts_api_structs::Source;
+
+const LOCAL_KEY: &str = include_str!("fixtures/activitypub-local.pem");
+const REMOTE_KEY: &str = include_str!("fixtures/activitypub-remote.pem");
+
+/// Where the mock instance's inbox reports whether requests were signed with the local key.
+type Received = Sender<Result<Value, SignatureError>>;
+
+#[derive(Clone)]
+struct MockInstance {
+    received: Received,
+    /// Number of times Alice's actor document was fetched.
+    actor_fetches: Arc<AtomicUsize>,
+}
+
+/// The gallery's actor, allowed to talk to the mock instances on the loopback interface.
+fn local_actor() -> LocalActor {
+    public_only_actor().allow_private_addresses()
+}
+
+fn public_only_actor() -> LocalActor {
+    let key = PrivateKey::from_pem(LOCAL_KEY).unwrap();
+    LocalActor::new("https://photos.example.com", "gallery", key).unwrap()
+}
+
+/// Serve an app on a random local port, returning its base URL.
+async fn serve<State: Clone + Send + Sync + 'static>(app: tide::Server<State>) -> String {
+    let mut listener = app
+        .bind("127.0.0.1:0")
+        .await
+        .expect("couldn't bind mock instance");
+    let url = listener.info()[0].connection().to_string();
+    async_std::task::spawn(async move { listener.accept().await });
+    url
+}
+
+/// Start a mock instance with an actor at `/users/alice` using the remote key, and an inbox at
+/// `/inbox` that verifies requests signed with the local key.
+async fn mock_instance(received: Received) -> String {
+    mock_instance_counting_fetches(received, Default::default()).await
+}
+
+async fn mock_instance_counting_fetches(
+    received: Received,
+    actor_fetches: Arc<AtomicUsize>,
+) -> String {
+    let mut app = tide::with_state(MockInstance {
+        received,
+        actor_fetches,
+    });
+
+    app.at("/users/alice")
+        .get(|req: Request<MockInstance>| async move {
+            req.state().actor_fetches.fetch_add(1, Ordering::SeqCst);
+            let id = req.url().to_string();
+            let key = PrivateKey::from_pem(REMOTE_KEY)?;
+            Ok(Response::builder(StatusCode::Ok)
+                .body(tide::Body::from_json(&json!({
+                    "id": id,
+                    "type": "Person",
+                    "inbox": format!("{}/inbox", id),
+                    "endpoints": { "sharedInbox": id.replace("/users/alice", "/inbox") },
+                    "publicKey": {
+                        "id": format!("{}#main-key", id),
+                        "owner": id,
+                        "publicKeyPem": key.public_key_pem(),
+                    },
+                }))?)
+                .build())
+        });
+
+    app.at("/inbox")
+        .post(|mut req: Request<MockInstance>| async move {
+            let body = req.body_bytes().await?;
+            let key = PublicKey::from_pem(local_actor().key().public_key_pem())?;
+            let res = SignatureParams::from_request(req.as_ref())
+                .and_then(|params| verify_request(req.as_ref(), &body, &params, &key))
+                .map(|()| serde_json::from_slice(&body).unwrap());
+
+            let status = match res {
+                Ok(_) => StatusCode::Accepted,
+                Err(_) => StatusCode::Unauthorized,
+            };
+            let _ = req.state().received.send(res).await;
+            Ok(Response::new(status))
+        });
+
+    serve(app).await
+}
+
+#[derive(Clone)]
+struct GalleryInbox {
+    actor: Arc<LocalActor>,
+    keys: KeyCache,
+    authenticated: Sender<Result<String, String>>,
+}
+
+/// Start an inbox authenticating activities the way the gallery's does, returning its URL.
+async fn gallery_inbox(authenticated: Sender<Result<String, String>>) -> String {
+    let mut app = tide::with_state(GalleryInbox {
+        actor: Arc::new(local_actor()),
+        keys: KeyCache::new(),
+        authenticated,
+    });
+
+    app.at("/inbox")
+        .post(|mut req: Request<GalleryInbox>| async move {
+            let body = req.body_bytes().await?;
+            let activity: Value = serde_json::from_slice(&body)?;
+            let state = req.state();
+            let res = authenticate(&state.actor, &state.keys, req.as_ref(), &body, &activity)
+                .await
+                .map(|remote| remote.id)
+                .map_err(|err| err.to_string());
+
+            let status = match res {
+                Ok(_) => StatusCode::Accepted,
+                Err(_) => StatusCode::Unauthorized,
+            };
+            let _ = req.state().authenticated.send(res).await;
+            Ok(Response::new(status))
+        });
+
+    format!("{}/inbox", serve(app).await)
+}
+
+fn follow(actor: &str) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "id": format!("{}#follows/1", actor),
+        "type": "Follow",
+        "actor": actor,
+        "object": local_actor().id(),
+    }))
+    .unwrap()
+}
+
+#[test]
+fn webfinger_points_to_actor_with_usable_key() {
+    let actor = local_actor();
+
+    let webfinger = documents::webfinger(&actor);
+    assert_eq!(webfinger["subject"], "acct:gallery@photos.example.com");
+    assert_eq!(webfinger["links"][0]["href"], actor.id());
+
+    let document = documents::actor(&actor, "Photos", Some("By <someone>"));
+    assert_eq!(document["inbox"], actor.inbox());
+    assert_eq!(document["summary"], "<p>By &lt;someone&gt;</p>");
+    assert_eq!(document["publicKey"]["id"], actor.key_id());
+
+    let public_key =
+        PublicKey::from_pem(document["publicKey"]["publicKeyPem"].as_str().unwrap()).unwrap();
+    let signature = actor.key().sign(b"message");
+    assert!(public_key.verify(b"message", &signature));
+    assert!(!public_key.verify(b"other message", &signature));
+}
+
+#[test]
+fn create_attaches_largest_jpeg() {
+    let actor = local_actor();
+    let source = |width, mime_type: &str| Source {
+        width,
+        height: width / 2,
+        url: format!("https://static.example.com/{}/{}", mime_type, width),
+        mime_type: mime_type.to_string(),
+    };
+    let photo = Photo {
+        id: 7,
+        title: Some("Sunset & sea".to_string()),
+        alt_text: Some("The sun setting over the sea".to_string()),
+        tags: vec!["sunset".to_string(), "two words".to_string()],
+        sources: vec![
+            source(2000, "image/avif"),
+            source(2000, "image/jpeg"),
+            source(1000, "image/jpeg"),
+        ],
+        published: true,
+        ..Photo::default()
+    };
+
+    let create = documents::create(&actor, &photo, Some("2026-10-18T12:00:00Z"));
+    assert_eq!(create["type"], "Create");
+    assert_eq!(create["actor"], actor.id());
+    assert_eq!(create["published"], "2026-10-18T12:00:00Z");
+
+    let note = &create["object"];
+    assert_eq!(note["id"], actor.photo_note(7));
+    assert_eq!(note["url"], "https://photos.example.com/photo/7");
+    assert!(note["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Sunset &amp; sea</p>"));
+    assert_eq!(
+        note["attachment"],
+        json!([{
+            "type": "Document",
+            "mediaType": "image/jpeg",
+            "url": "https://static.example.com/image/jpeg/2000",
+            "name": "The sun setting over the sea",
+            "width": 2000,
+            "height": 1000,
+            "blurhash": null,
+        }])
+    );
+    assert_eq!(note["tag"].as_array().unwrap().len(), 1);
+    assert_eq!(note["tag"][0]["name"], "#sunset");
+}
+
+#[async_std::test]
+async fn delivers_signed_activity_to_mock_instance() {
+    let (received, deliveries) = async_std::channel::unbounded();
+    let instance = mock_instance(received).await;
+    let actor = local_actor();
+
+    let activity = json!({ "type": "Accept", "actor": actor.id() });
+    post_signed(
+        actor.key(),
+        &actor.key_id(),
+        &format!("{}/inbox", instance),
+        &serde_json::to_vec(&activity).unwrap(),
+    )
+    .await
+    .expect("delivery failed");
+
+    assert_eq!(deliveries.recv().await.unwrap(), Ok(activity));
+}
+
+#[async_std::test]
+async fn mock_instance_rejects_tampered_body() {
+    let (received, deliveries) = async_std::channel::unbounded();
+    let instance = mock_instance(received).await;
+    let actor = local_actor();
+
+    let url = url::Url::parse(&format!("{}/inbox", instance)).unwrap();
+    let mut req = surf::post(url.as_str()).body(r#"{"type":"Tampered"}"#);
+    for (name, value) in sign_request(
+        actor.key(),
+        &actor.key_id(),
+        "POST",
+        &url,
+        Some(br#"{"type":"Original"}"#),
+    ) {
+        req = req.header(name, value);
+    }
+    let res = req.await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    assert_eq!(
+        deliveries.recv().await.unwrap(),
+        Err(SignatureError::Digest)
+    );
+}
+
+#[async_std::test]
+async fn authenticates_follow_from_mock_instance() {
+    let (received, _) = async_std::channel::unbounded();
+    let instance = mock_instance(received).await;
+    let (authenticated, results) = async_std::channel::unbounded();
+    let inbox = gallery_inbox(authenticated).await;
+
+    let remote_key = PrivateKey::from_pem(REMOTE_KEY).unwrap();
+    let alice = format!("{}/users/alice", instance);
+    post_signed(
+        &remote_key,
+        &format!("{}#main-key", alice),
+        &inbox,
+        &follow(&alice),
+    )
+    .await
+    .expect("follow was rejected");
+
+    assert_eq!(results.recv().await.unwrap(), Ok(alice));
+}
+
+#[async_std::test]
+async fn rejects_activity_for_another_actor() {
+    let (received, _) = async_std::channel::unbounded();
+    let instance = mock_instance(received).await;
+    let (authenticated, results) = async_std::channel::unbounded();
+    let inbox = gallery_inbox(authenticated).await;
+
+    // Signed with Alice's key, but claiming to be from Mallory.
+    let remote_key = PrivateKey::from_pem(REMOTE_KEY).unwrap();
+    let alice = format!("{}/users/alice", instance);
+    let mallory = format!("{}/users/mallory", instance);
+    assert!(post_signed(
+        &remote_key,
+        &format!("{}#main-key", alice),
+        &inbox,
+        &follow(&mallory),
+    )
+    .await
+    .is_err());
+
+    assert!(results.recv().await.unwrap().is_err());
+}
+
+#[async_std::test]
+async fn caches_keys_of_authenticated_actors() {
+    let (received, _) = async_std::channel::unbounded();
+    let actor_fetches = Arc::new(AtomicUsize::new(0));
+    let instance = mock_instance_counting_fetches(received, actor_fetches.clone()).await;
+    let (authenticated, results) = async_std::channel::unbounded();
+    let inbox = gallery_inbox(authenticated).await;
+
+    let remote_key = PrivateKey::from_pem(REMOTE_KEY).unwrap();
+    let alice = format!("{}/users/alice", instance);
+    for _ in 0..3 {
+        post_signed(
+            &remote_key,
+            &format!("{}#main-key", alice),
+            &inbox,
+            &follow(&alice),
+        )
+        .await
+        .expect("follow was rejected");
+        assert_eq!(results.recv().await.unwrap(), Ok(alice.clone()));
+    }
+
+    assert_eq!(actor_fetches.load(Ordering::SeqCst), 1);
+}
+
+#[async_std::test]
+async fn refuses_to_contact_private_addresses() {
+    let (received, deliveries) = async_std::channel::unbounded();
+    let instance = mock_instance(received).await;
+    let actor = public_only_actor();
+
+    let alice = format!("{}/users/alice", instance);
+    let err = fetch_actor(&actor, &format!("{}#main-key", alice))
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("non-public address 127.0.0.1"));
+
+    assert!(actor
+        .deliver(&format!("{}/inbox", instance), b"{}")
+        .await
+        .is_err());
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[test]
+fn only_considers_internet_addresses_public() {
+    for address in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+        assert!(is_public(address.parse::<IpAddr>().unwrap()), "{}", address);
+    }
+
+    for address in [
+        "0.0.0.0",
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "255.255.255.255",
+        "::",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+        "::ffff:169.254.169.254",
+        "64:ff9b::a00:1",
+    ] {
+        assert!(
+            !is_public(address.parse::<IpAddr>().unwrap()),
+            "{}",
+            address
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use rusty_peanuts::db::photos::{Anchor, Page, SortMode};
+use rusty_peanuts::web::cursor::{Cursor, CursorError};
+
+const SECRET: &[u8] = b"cursor secret";
+/// Length of the nonce at the start of tokens.
+const NONCE_LEN: usize = 12;
+
+fn tagged(tags: &[&str]) -> Option<Vec<String>> {
+    Some(tags.iter().map(|tag| tag.to_string()).collect())
+}
+
+fn decode_token(token: &str) -> Vec<u8> {
+    base64::decode_config(token, base64::URL_SAFE_NO_PAD).unwrap()
+}
+
+fn encode_token(token: &[u8]) -> String {
+    base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+}
+
+#[test]
+fn decodes_encoded_cursor() {
+    let anchor = Anchor {
+        id: 42,
+        key: Some(1_600_000_000_000_000),
+    };
+    let cursor = Cursor::new(
+        Page::Before(anchor),
+        SortMode::TakenTimestamp,
+        &tagged(&["sea"]),
+    );
+
+    let token = cursor.encode(SECRET);
+    assert_eq!(Cursor::decode(&token, SECRET).unwrap(), cursor);
+}
+
+#[test]
+fn rejects_cursor_signed_with_another_secret() {
+    let token = Cursor::new(Page::After(Anchor::id(7)), SortMode::Id, &None).encode(SECRET);
+
+    assert!(matches!(
+        Cursor::decode(&token, b"another secret"),
+        Err(CursorError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn rejects_tampered_cursor() {
+    let token = Cursor::new(Page::Before(Anchor::id(42)), SortMode::Id, &None).encode(SECRET);
+
+    // The encrypted payload of a cursor for another page, with the original nonce.
+    let forged = Cursor::new(Page::Before(Anchor::id(1000)), SortMode::Id, &None).encode(SECRET);
+    let spliced = [
+        &decode_token(&token)[..NONCE_LEN],
+        &decode_token(&forged)[NONCE_LEN..],
+    ]
+    .concat();
+    assert!(matches!(
+        Cursor::decode(&encode_token(&spliced), SECRET),
+        Err(CursorError::InvalidSignature)
+    ));
+
+    let mut flipped = decode_token(&token);
+    *flipped.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        Cursor::decode(&encode_token(&flipped), SECRET),
+        Err(CursorError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn hides_cursor_contents() {
+    let cursor = Cursor::new(
+        Page::Before(Anchor::id(424242)),
+        SortMode::Id,
+        &tagged(&["sea"]),
+    );
+
+    let first = cursor.encode(SECRET);
+    let second = cursor.encode(SECRET);
+    assert_ne!(first, second);
+    for token in [first, second] {
+        let token = String::from_utf8_lossy(&decode_token(&token)).into_owned();
+        assert!(!token.contains("424242"));
+        assert!(!token.contains("sea"));
+    }
+}
+
+#[test]
+fn rejects_malformed_cursor() {
+    for token in ["", "short", "not base64!", "e30.AAAA"] {
+        assert!(Cursor::decode(token, SECRET).is_err(), "{:?}", token);
+    }
+}
+
+#[test]
+fn only_matches_listing_with_same_sort_and_filter() {
+    let cursor = Cursor::new(
+        Page::Before(Anchor::id(42)),
+        SortMode::Id,
+        &tagged(&["sea"]),
+    );
+
+    assert!(cursor.matches(SortMode::Id, &tagged(&["sea"])));
+    assert!(!cursor.matches(SortMode::SortKey, &tagged(&["sea"])));
+    assert!(!cursor.matches(SortMode::Id, &tagged(&["sky"])));
+    assert!(!cursor.matches(SortMode::Id, &None));
+}
+
+#[test]
+fn maps_legacy_offsets_to_pages() {
+    assert_eq!(Cursor::legacy_offset_page(42), Page::Before(Anchor::id(42)));
+    assert_eq!(Cursor::legacy_offset_page(-43), Page::After(Anchor::id(42)));
+}
@@ -0,0 +1,102 @@
+use serde_json::Value;
+
+use rusty_peanuts::web::api::v1::{openapi, routes};
+
+fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => refs.push(reference),
+                    _ => collect_refs(value, refs),
+                }
+            }
+        },
+        Value::Array(values) => {
+            for value in values {
+                collect_refs(value, refs);
+            }
+        },
+        _ => {},
+    }
+}
+
+#[test]
+fn spec_describes_every_route() {
+    let spec = openapi::spec("https://example.com");
+
+    for (method, path) in routes() {
+        let method_name = method.to_string().to_lowercase();
+        let operation = &spec["paths"][openapi::openapi_path(path)][method_name];
+        assert!(
+            operation.is_object(),
+            "route {} {} missing from OpenAPI description",
+            method,
+            path
+        );
+    }
+}
+
+#[test]
+fn spec_has_no_stale_routes() {
+    let spec = openapi::spec("https://example.com");
+    let routes: Vec<_> = routes()
+        .into_iter()
+        .map(|(method, path)| {
+            (
+                method.to_string().to_lowercase(),
+                openapi::openapi_path(path),
+            )
+        })
+        .collect();
+
+    let paths = spec["paths"].as_object().expect("paths must be an object");
+    for (path, item) in paths {
+        for method in item
+            .as_object()
+            .expect("path item must be an object")
+            .keys()
+        {
+            assert!(
+                routes.contains(&(method.clone(), path.clone())),
+                "OpenAPI description contains {} {} which isn't routed",
+                method,
+                path
+            );
+        }
+    }
+}
+
+#[test]
+fn spec_references_resolve() {
+    let spec = openapi::spec("https://example.com");
+
+    let mut refs = Vec::new();
+    collect_refs(&spec, &mut refs);
+    assert!(!refs.is_empty());
+
+    for reference in refs {
+        let pointer = reference
+            .strip_prefix('#')
+            .unwrap_or_else(|| panic!("reference {} is not local", reference));
+        assert!(
+            spec.pointer(pointer).is_some(),
+            "reference {} doesn't resolve",
+            reference
+        );
+    }
+}
+
+#[test]
+fn photo_schema_matches_serialized_photo() {
+    let spec = openapi::spec("https://example.com");
+    let schema = &spec["components"]["schemas"]["Photo"]["properties"];
+    let photo = serde_json::to_value(rusty_peanuts::models::photos::Photo::default()).unwrap();
+
+    let mut schema_fields: Vec<_> = schema.as_object().unwrap().keys().collect();
+    let mut photo_fields: Vec<_> = photo.as_object().unwrap().keys().collect();
+    schema_fields.sort();
+    photo_fields.sort();
+
+    assert_eq!(schema_fields, photo_fields);
+}
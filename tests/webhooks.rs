@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use async_std::channel::{Receiver, Sender};
+use tide::listener::Listener;
+use tide::{Request, StatusCode};
+
+use rusty_peanuts::webhooks::{
+    deliver, retry_delay, sign, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER,
+};
+
+/// A request received by the stand-in endpoint.
+#[derive(Debug)]
+struct Received {
+    signature: Option<String>,
+    event: Option<String>,
+    delivery: Option<String>,
+    body: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct StandIn {
+    received: Sender<Received>,
+    status: StatusCode,
+}
+
+/// Start a local HTTP endpoint responding with `status`, returning its URL and the requests
+/// it receives.
+async fn stand_in(status: StatusCode) -> (String, Receiver<Received>) {
+    let (received, requests) = async_std::channel::unbounded();
+
+    let mut app = tide::with_state(StandIn { received, status });
+    app.at("/hook")
+        .post(|mut req: Request<StandIn>| async move {
+            let header = |name: &str| req.header(name).map(|values| values.last().to_string());
+            let signature = header(SIGNATURE_HEADER);
+            let event = header(EVENT_HEADER);
+            let delivery = header(DELIVERY_HEADER);
+            let body = req.body_bytes().await?;
+
+            let state = req.state();
+            let _ = state
+                .received
+                .send(Received {
+                    signature,
+                    event,
+                    delivery,
+                    body,
+                })
+                .await;
+            Ok(tide::Response::new(state.status))
+        });
+
+    let mut listener = app
+        .bind("127.0.0.1:0")
+        .await
+        .expect("couldn't bind stand-in");
+    let url = format!("{}/hook", listener.info()[0].connection());
+    async_std::task::spawn(async move { listener.accept().await });
+
+    (url, requests)
+}
+
+/// HMAC-SHA256 test case 2 from RFC 4231, as a signature header.
+const RFC_4231_KEY: &str = "Jefe";
+const RFC_4231_DATA: &[u8] = b"what do ya want for nothing?";
+const RFC_4231_SIGNATURE: &str =
+    "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+#[async_std::test]
+async fn delivers_signed_payload() {
+    let (url, requests) = stand_in(StatusCode::NoContent).await;
+
+    deliver(&url, RFC_4231_KEY, 42, "photo.published", RFC_4231_DATA)
+        .await
+        .expect("delivery failed");
+
+    let received = requests.recv().await.unwrap();
+    assert_eq!(received.body, RFC_4231_DATA);
+    assert_eq!(received.signature.as_deref(), Some(RFC_4231_SIGNATURE));
+    assert_eq!(received.event.as_deref(), Some("photo.published"));
+    assert_eq!(received.delivery.as_deref(), Some("42"));
+}
+
+#[async_std::test]
+async fn fails_on_error_status() {
+    let (url, requests) = stand_in(StatusCode::InternalServerError).await;
+
+    assert!(deliver(&url, "secret", 1, "photo.created", b"{}")
+        .await
+        .is_err());
+    assert!(requests.recv().await.is_ok());
+}
+
+#[test]
+fn signature_is_hmac_sha256_of_body() {
+    assert_eq!(sign(RFC_4231_KEY, RFC_4231_DATA), RFC_4231_SIGNATURE);
+    assert_ne!(sign("other secret", RFC_4231_DATA), RFC_4231_SIGNATURE);
+    assert_ne!(sign(RFC_4231_KEY, b"other body"), RFC_4231_SIGNATURE);
+}
+
+#[test]
+fn retry_delay_backs_off_up_to_a_limit() {
+    assert_eq!(retry_delay(1), Duration::from_secs(30));
+    assert_eq!(retry_delay(2), Duration::from_secs(60));
+    assert_eq!(retry_delay(3), Duration::from_secs(120));
+    assert_eq!(retry_delay(100), Duration::from_secs(6 * 60 * 60));
+}